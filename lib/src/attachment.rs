@@ -0,0 +1,294 @@
+//! Encrypted file attachments.
+//!
+//! A file is encrypted with a fresh random key and split into chunks that are
+//! each sealed on their own, bound to the attachment id, their position and
+//! whether they are the last one, so chunks can't be swapped, reordered or
+//! dropped. The chunks are uploaded to a [`BlobStore`] as opaque blobs, and the
+//! [`Attachment`] holding the key, digest, size and MIME type is sent to the
+//! recipient inside an encrypted message.
+
+use std::{error::Error, fmt};
+
+use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm, Nonce};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::{blob::{BlobError, BlobStore}, content::MessageId};
+
+/// Plaintext bytes in each chunk, small enough that a sealed chunk fits in a
+/// single durable object storage value once hex encoded.
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+/// The most chunks an attachment can have, capping attachments at 16MiB.
+pub const MAX_CHUNKS: u32 = 512;
+
+#[derive(Debug)]
+pub enum AttachmentError {
+    /// The file is bigger than `CHUNK_SIZE * MAX_CHUNKS`.
+    TooLarge,
+    /// A chunk isn't in the blob store.
+    MissingChunk(u32),
+    /// A chunk failed authentication, or is in the wrong place.
+    InvalidChunk(u32),
+    /// The decrypted file doesn't match the size or digest it was sent with.
+    Digest,
+    Store(BlobError),
+}
+
+impl fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge => f.write_str("attachment is too large"),
+            Self::MissingChunk(i) => write!(f, "attachment chunk {} is missing", i),
+            Self::InvalidChunk(i) => write!(f, "attachment chunk {} is invalid", i),
+            Self::Digest => f.write_str("attachment does not match its digest"),
+            Self::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for AttachmentError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Store(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<BlobError> for AttachmentError {
+    fn from(e: BlobError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// Everything needed to fetch and decrypt an attachment.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: MessageId,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    /// SHA-256 of the plaintext.
+    #[serde(with = "crate::wire::bytes")]
+    pub digest: [u8; 32],
+    pub chunks: u32,
+    #[serde(with = "crate::wire::bytes")]
+    key: [u8; 32],
+}
+
+impl Attachment {
+    /// Encrypts a file, returning the attachment to send and the sealed chunks to upload.
+    pub fn encrypt(name: &str, mime_type: &str, data: &[u8]) -> Result<(Self, Vec<Vec<u8>>), AttachmentError> {
+        let mut plaintexts: Vec<&[u8]> = data.chunks(CHUNK_SIZE).collect();
+        if plaintexts.is_empty() {
+            // an empty file is still one chunk, so there is something to authenticate
+            plaintexts.push(&[]);
+        }
+        if plaintexts.len() > MAX_CHUNKS as usize {
+            return Err(AttachmentError::TooLarge);
+        }
+
+        let mut key = [0; 32];
+        rand::thread_rng().fill(&mut key[..]);
+
+        let attachment = Self {
+            id: MessageId::generate(),
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+            digest: Sha256::digest(data).into(),
+            chunks: plaintexts.len() as u32,
+            key,
+        };
+
+        let sealed = plaintexts
+            .into_iter()
+            .enumerate()
+            .map(|(i, plaintext)| attachment.seal(i as u32, plaintext))
+            .collect();
+
+        Ok((attachment, sealed))
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.mime_type.starts_with("image/")
+    }
+
+    /// Where a chunk is kept in a blob store.
+    pub fn chunk_key(&self, index: u32) -> String {
+        format!("{}/{}", self.id, index)
+    }
+
+    pub fn decrypt_chunk(&self, index: u32, chunk: &[u8]) -> Result<Vec<u8>, AttachmentError> {
+        if index >= self.chunks {
+            return Err(AttachmentError::InvalidChunk(index));
+        }
+
+        Aes256Gcm::new(&self.key.into())
+            .decrypt(&self.nonce(index), Payload { msg: chunk, aad: &self.id.bytes() })
+            .map_err(|_| AttachmentError::InvalidChunk(index))
+    }
+
+    /// Decrypts every chunk in order and checks the result against the digest.
+    pub fn decrypt(&self, chunks: &[Vec<u8>]) -> Result<Vec<u8>, AttachmentError> {
+        if self.chunks > MAX_CHUNKS || self.size > (CHUNK_SIZE as u64) * u64::from(self.chunks) {
+            return Err(AttachmentError::TooLarge);
+        }
+        if chunks.len() > self.chunks as usize {
+            return Err(AttachmentError::InvalidChunk(self.chunks));
+        }
+
+        let mut data = Vec::with_capacity(self.size as usize);
+        for index in 0..self.chunks {
+            let chunk = chunks.get(index as usize).ok_or(AttachmentError::MissingChunk(index))?;
+            data.extend(self.decrypt_chunk(index, chunk)?);
+        }
+
+        if data.len() as u64 != self.size || Sha256::digest(&data).as_slice() != self.digest {
+            return Err(AttachmentError::Digest);
+        }
+
+        Ok(data)
+    }
+
+    /// Uploads the sealed chunks, calling `progress` with the number uploaded so far.
+    pub async fn upload(
+        &self,
+        store: &mut impl BlobStore,
+        chunks: Vec<Vec<u8>>,
+        mut progress: impl FnMut(u32),
+    ) -> Result<(), AttachmentError> {
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            store.put(&self.chunk_key(index as u32), chunk).await?;
+            progress(index as u32 + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Downloads and decrypts the file, calling `progress` with the number of
+    /// chunks downloaded so far.
+    pub async fn download(
+        &self,
+        store: &impl BlobStore,
+        mut progress: impl FnMut(u32),
+    ) -> Result<Vec<u8>, AttachmentError> {
+        let mut chunks = Vec::new();
+        for index in 0..self.chunks.min(MAX_CHUNKS) {
+            let chunk = store
+                .get(&self.chunk_key(index))
+                .await?
+                .ok_or(AttachmentError::MissingChunk(index))?;
+            chunks.push(chunk);
+            progress(index + 1);
+        }
+
+        self.decrypt(&chunks)
+    }
+
+    fn seal(&self, index: u32, plaintext: &[u8]) -> Vec<u8> {
+        Aes256Gcm::new(&self.key.into())
+            .encrypt(&self.nonce(index), Payload { msg: plaintext, aad: &self.id.bytes() })
+            .expect("aes-gcm encryption does not fail")
+    }
+
+    // every chunk has its own nonce under the attachment's key, and the last
+    // chunk is marked so the file can't be truncated at a chunk boundary.
+    fn nonce(&self, index: u32) -> Nonce<aes_gcm::aead::consts::U12> {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&index.to_be_bytes());
+        nonce[4] = u8::from(index + 1 == self.chunks);
+
+        nonce.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::blob::MemoryBlobStore;
+    use super::*;
+
+    fn file(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_attachment_round_trip() {
+        let mut store = MemoryBlobStore::new();
+
+        for len in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE + 17] {
+            let data = file(len);
+            let (attachment, chunks) = Attachment::encrypt("file.bin", "application/octet-stream", &data).unwrap();
+            assert_eq!(attachment.chunks as usize, chunks.len());
+
+            let mut uploaded = 0;
+            attachment.upload(&mut store, chunks, |n| uploaded = n).await.unwrap();
+            assert_eq!(uploaded, attachment.chunks);
+
+            let mut downloaded = 0;
+            let decrypted = attachment.download(&store, |n| downloaded = n).await.unwrap();
+            assert_eq!(downloaded, attachment.chunks);
+            assert_eq!(decrypted, data);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_attachment_is_encrypted() {
+        let data = file(CHUNK_SIZE);
+        let (_, chunks) = Attachment::encrypt("file.bin", "application/octet-stream", &data).unwrap();
+
+        assert!(!chunks[0].windows(64).any(|w| w == &data[..64]));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_attachment_serialize() {
+        let data = file(10);
+        let (attachment, chunks) = Attachment::encrypt("cat.png", "image/png", &data).unwrap();
+
+        let attachment: Attachment = serde_json::from_str(&serde_json::to_string(&attachment).unwrap()).unwrap();
+
+        assert!(attachment.is_image());
+        assert_eq!(attachment.decrypt(&chunks).unwrap(), data);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_attachment_tampered_chunks() {
+        let (attachment, chunks) = Attachment::encrypt("file.bin", "application/octet-stream", &file(3 * CHUNK_SIZE)).unwrap();
+
+        let mut tampered = chunks.clone();
+        tampered[1][0] ^= 1;
+        assert!(matches!(attachment.decrypt(&tampered), Err(AttachmentError::InvalidChunk(1))));
+
+        let mut reordered = chunks.clone();
+        reordered.swap(0, 1);
+        assert!(matches!(attachment.decrypt(&reordered), Err(AttachmentError::InvalidChunk(0))));
+
+        let truncated = &chunks[..2];
+        assert!(matches!(attachment.decrypt(truncated), Err(AttachmentError::MissingChunk(2))));
+
+        // chunks from another attachment don't decrypt even at the same position
+        let (_, other) = Attachment::encrypt("file.bin", "application/octet-stream", &file(3 * CHUNK_SIZE)).unwrap();
+        assert!(matches!(attachment.decrypt(&other), Err(AttachmentError::InvalidChunk(0))));
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_attachment_missing_from_store() {
+        let store = MemoryBlobStore::new();
+        let (attachment, _) = Attachment::encrypt("file.bin", "application/octet-stream", &file(10)).unwrap();
+
+        assert!(matches!(attachment.download(&store, |_| {}).await, Err(AttachmentError::MissingChunk(0))));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_attachment_too_large() {
+        let data = vec![0; CHUNK_SIZE * MAX_CHUNKS as usize + 1];
+
+        assert!(matches!(Attachment::encrypt("big.bin", "application/octet-stream", &data), Err(AttachmentError::TooLarge)));
+    }
+}
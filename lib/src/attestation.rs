@@ -0,0 +1,173 @@
+//! Contacts vouching for each other's keys.
+//!
+//! Comparing safety numbers only tells two people about each other. After
+//! verifying a contact, a user can also sign an [`Attestation`] that the
+//! contact's key is really theirs and publish it. Someone about to add that
+//! contact then sees which of the people they verified themselves vouch for
+//! the key with [`vouchers`]. Attestations from anyone else count for nothing,
+//! so keys made up to vouch for each other don't help.
+
+use std::collections::HashSet;
+
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    pki::{PublicKey, SecretKey, Signature},
+    signing::{Encoder, Signable},
+    wire::{self, WireError},
+};
+
+/// One key vouching for another after verifying it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attestation {
+    pub attester: PublicKey,
+    pub subject: PublicKey,
+    /// Milliseconds since the unix epoch.
+    pub created_at: u64,
+    signature: Signature,
+}
+
+impl Attestation {
+    pub fn new(attester: &SecretKey, subject: &PublicKey, created_at: u64) -> Self {
+        let attester_key = attester.public_key();
+        let signature = attester.sign(&SignedFields { attester: &attester_key, subject, created_at });
+
+        Self {
+            attester: attester_key,
+            subject: subject.clone(),
+            created_at,
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        let fields = SignedFields { attester: &self.attester, subject: &self.subject, created_at: self.created_at };
+
+        self.attester.verify(&fields, &self.signature)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        wire::decode(bytes)
+    }
+}
+
+/// The keys in `trusted` with a valid attestation for `subject`, each once and
+/// in the order first found. A key vouching for itself doesn't count.
+pub fn vouchers(subject: &PublicKey, attestations: &[Attestation], trusted: &HashSet<PublicKey>) -> Vec<PublicKey> {
+    let mut found = Vec::new();
+
+    for attestation in attestations {
+        let attester = &attestation.attester;
+        if attestation.subject == *subject
+            && attester != subject
+            && trusted.contains(attester)
+            && !found.contains(attester)
+            && attestation.verify()
+        {
+            found.push(attester.clone());
+        }
+    }
+
+    found
+}
+
+/// Everything in an attestation except the signature.
+struct SignedFields<'a> {
+    attester: &'a PublicKey,
+    subject: &'a PublicKey,
+    created_at: u64,
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "attestation";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.attester.bytes())
+            .bytes(&self.subject.bytes())
+            .u64(self.created_at);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+    use crate::pki::Suite;
+
+    #[wasm_bindgen_test]
+    fn test_attestation() {
+        for suite in Suite::ALL {
+            let alice = SecretKey::generate_with(suite);
+            let bob = SecretKey::generate().public_key();
+
+            let attestation = Attestation::new(&alice, &bob, 1000);
+            assert!(attestation.verify());
+
+            let decoded = Attestation::from_bytes(&attestation.to_bytes()).unwrap();
+            assert!(decoded.verify() && decoded.attester == alice.public_key() && decoded.subject == bob);
+
+            let mut redirected = attestation.clone();
+            redirected.subject = SecretKey::generate().public_key();
+            assert!(!redirected.verify());
+
+            let mut forged = attestation;
+            forged.attester = SecretKey::generate().public_key();
+            assert!(!forged.verify());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_vouchers() {
+        let keys: Vec<SecretKey> = (0..4).map(|_| SecretKey::generate()).collect();
+        let subject = SecretKey::generate();
+        let subject_key = subject.public_key();
+
+        // the first two are verified contacts, the others strangers
+        let trusted: HashSet<PublicKey> = keys[..2].iter().map(|k| k.public_key()).collect();
+
+        let attestations = vec![
+            Attestation::new(&keys[1], &subject_key, 1),
+            Attestation::new(&keys[2], &subject_key, 2),
+            Attestation::new(&keys[1], &subject_key, 3),
+            Attestation::new(&keys[0], &keys[3].public_key(), 4),
+            Attestation::new(&subject, &subject_key, 5),
+        ];
+        assert_eq!(vouchers(&subject_key, &attestations, &trusted), vec![keys[1].public_key()]);
+
+        let mut more = attestations.clone();
+        more.push(Attestation::new(&keys[0], &subject_key, 6));
+        assert_eq!(vouchers(&subject_key, &more, &trusted), vec![keys[1].public_key(), keys[0].public_key()]);
+
+        // trusting the subject doesn't let it vouch for itself
+        let mut trusted_subject = trusted.clone();
+        trusted_subject.insert(subject_key.clone());
+        assert_eq!(vouchers(&subject_key, &attestations, &trusted_subject).len(), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_vouchers_ignore_forgeries() {
+        let alice = SecretKey::generate();
+        let mallory = SecretKey::generate();
+        let subject = SecretKey::generate().public_key();
+        let trusted = HashSet::from([alice.public_key()]);
+
+        // mallory can't make it look like alice vouched
+        let mut forged = Attestation::new(&mallory, &subject, 0);
+        forged.attester = alice.public_key();
+        assert!(vouchers(&subject, &[forged], &trusted).is_empty());
+
+        // nor move alice's attestation for someone else onto the subject
+        let mut moved = Attestation::new(&alice, &mallory.public_key(), 0);
+        moved.subject = subject.clone();
+        assert!(vouchers(&subject, &[moved], &trusted).is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+//! Storage for opaque blobs, such as encrypted attachment chunks.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use async_trait::async_trait;
+
+#[derive(Debug)]
+pub struct BlobError(String);
+
+impl BlobError {
+    pub fn new(message: impl fmt::Display) -> Self {
+        Self(message.to_string())
+    }
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "blob storage failed: {}", self.0)
+    }
+}
+
+impl Error for BlobError {}
+
+/// Somewhere blobs can be written once and read back by key. The worker keeps
+/// them in durable object storage, the web client reaches that over http.
+#[async_trait(?Send)]
+pub trait BlobStore {
+    async fn put(&mut self, key: &str, blob: Vec<u8>) -> Result<(), BlobError>;
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError>;
+}
+
+/// Keeps blobs in memory, for tests and local development.
+#[derive(Default)]
+pub struct MemoryBlobStore {
+    blobs: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait(?Send)]
+impl BlobStore for MemoryBlobStore {
+    async fn put(&mut self, key: &str, blob: Vec<u8>) -> Result<(), BlobError> {
+        self.blobs.insert(key.to_string(), blob);
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError> {
+        Ok(self.blobs.get(key).cloned())
+    }
+}
@@ -0,0 +1,259 @@
+//! Contact cards for sharing an identity.
+//!
+//! A [`ContactCard`] is signed by the key it introduces, so a card that went
+//! through anyone's hands can be checked before the key is added as a contact.
+//! The name is only a suggestion, whoever adds the contact picks the nickname.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use bech32::{primitives::decode::{CheckedHrpstring, CheckedHrpstringError}, Bech32m, Hrp};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    error::ParseError,
+    pki::{PublicKey, SecretKey, Signature},
+    signing::{Encoder, Signable},
+    wire::{self, WireError},
+};
+
+/// The prefix of an encoded [`ContactCard`].
+pub const CONTACT_CARD_PREFIX: &str = "murucard";
+
+/// The longest suggested name, in bytes.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// The most devices a card lists, which keeps it short enough to encode.
+pub const MAX_DEVICES: usize = 8;
+
+const CONTACT_CARD_HRP: Hrp = Hrp::parse_unchecked(CONTACT_CARD_PREFIX);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContactCardError {
+    /// The card isn't signed by the key it introduces.
+    InvalidSignature,
+    /// The name is longer than [`MAX_NAME_LENGTH`].
+    NameTooLong,
+    /// The card lists more than [`MAX_DEVICES`] devices.
+    TooManyDevices,
+}
+
+impl fmt::Display for ContactCardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => f.write_str("contact card signature is invalid"),
+            Self::NameTooLong => write!(f, "contact card name is longer than {} bytes", MAX_NAME_LENGTH),
+            Self::TooManyDevices => write!(f, "contact card lists more than {} devices", MAX_DEVICES),
+        }
+    }
+}
+
+impl Error for ContactCardError {}
+
+/// An identity introducing itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactCard {
+    pub key: PublicKey,
+    /// The name the identity suggests being added as.
+    pub name: String,
+    /// SHA-256 of the identity's avatar image, to check one received separately.
+    pub avatar: Option<[u8; 32]>,
+    /// The identity's other devices.
+    pub devices: Vec<PublicKey>,
+    /// Milliseconds since the unix epoch.
+    pub created_at: u64,
+    signature: Signature,
+}
+
+impl ContactCard {
+    pub fn new(
+        secret_key: &SecretKey,
+        name: &str,
+        avatar: Option<[u8; 32]>,
+        devices: &[PublicKey],
+        created_at: u64,
+    ) -> Result<Self, ContactCardError> {
+        check_limits(name, devices)?;
+
+        let key = secret_key.public_key();
+        let signature = secret_key.sign(&SignedFields {
+            key: &key,
+            name,
+            avatar: avatar.as_ref(),
+            devices,
+            created_at,
+        });
+
+        Ok(Self {
+            key,
+            name: name.to_string(),
+            avatar,
+            devices: devices.to_vec(),
+            created_at,
+            signature,
+        })
+    }
+
+    /// Checks the card is within limits and signed by its key, before it is
+    /// trusted.
+    pub fn verify(&self) -> Result<(), ContactCardError> {
+        check_limits(&self.name, &self.devices)?;
+
+        let fields = SignedFields {
+            key: &self.key,
+            name: &self.name,
+            avatar: self.avatar.as_ref(),
+            devices: &self.devices,
+            created_at: self.created_at,
+        };
+        match self.key.verify(&fields, &self.signature) {
+            true => Ok(()),
+            false => Err(ContactCardError::InvalidSignature),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        wire::decode(bytes)
+    }
+}
+
+fn check_limits(name: &str, devices: &[PublicKey]) -> Result<(), ContactCardError> {
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(ContactCardError::NameTooLong);
+    }
+    if devices.len() > MAX_DEVICES {
+        return Err(ContactCardError::TooManyDevices);
+    }
+
+    Ok(())
+}
+
+/// Everything in a contact card except the signature.
+struct SignedFields<'a> {
+    key: &'a PublicKey,
+    name: &'a str,
+    avatar: Option<&'a [u8; 32]>,
+    devices: &'a [PublicKey],
+    created_at: u64,
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "contact card";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.key.bytes())
+            .bytes(self.name.as_bytes())
+            .u8(u8::from(self.avatar.is_some()))
+            .bytes(self.avatar.map_or(&[], |a| a.as_slice()))
+            .u32(self.devices.len() as u32);
+
+        for device in self.devices {
+            encoder.bytes(&device.bytes());
+        }
+
+        encoder.u64(self.created_at);
+    }
+}
+
+/// Contact cards are bech32m encoded with a `murucard` prefix, to be pasted
+/// or saved to a file and read back in.
+impl fmt::Display for ContactCard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bech32::encode_lower_to_fmt::<Bech32m, _>(f, CONTACT_CARD_HRP, &self.to_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl FromStr for ContactCard {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = CheckedHrpstring::new::<Bech32m>(s.trim()).map_err(|e| match e {
+            CheckedHrpstringError::Checksum(_) => ParseError::Checksum,
+            _ => ParseError::Encoding,
+        })?;
+        if encoded.hrp() != CONTACT_CARD_HRP {
+            return Err(ParseError::Prefix(encoded.hrp().to_lowercase()));
+        }
+
+        Self::from_bytes(&encoded.byte_iter().collect::<Vec<u8>>()).map_err(|_| ParseError::Encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+    use crate::pki::Suite;
+
+    #[wasm_bindgen_test]
+    fn test_contact_card() {
+        for suite in Suite::ALL {
+            let secret_key = SecretKey::generate_with(suite);
+            let devices: Vec<PublicKey> = (0..2).map(|_| SecretKey::generate_with(suite).public_key()).collect();
+
+            let card = ContactCard::new(&secret_key, "alice", Some([7; 32]), &devices, 1000).unwrap();
+            assert_eq!(card.verify(), Ok(()));
+
+            let encoded = card.to_string();
+            assert!(encoded.starts_with("murucard1"));
+            let parsed: ContactCard = format!("  {}\n", encoded).parse().unwrap();
+            assert_eq!(parsed.verify(), Ok(()));
+            assert_eq!(parsed.key, secret_key.public_key());
+            assert_eq!((parsed.name.as_str(), parsed.avatar, parsed.devices, parsed.created_at), ("alice", Some([7; 32]), devices, 1000));
+
+            let json = serde_json::to_string(&card).unwrap();
+            assert_eq!(serde_json::from_str::<ContactCard>(&json).unwrap().verify(), Ok(()));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_contact_card_checks() {
+        let secret_key = SecretKey::generate();
+        let card = ContactCard::new(&secret_key, "alice", None, &[], 1000).unwrap();
+
+        // a card can't be passed off as someone else's
+        let mut forged = card.clone();
+        forged.key = SecretKey::generate().public_key();
+        assert_eq!(forged.verify(), Err(ContactCardError::InvalidSignature));
+
+        let mut renamed = card.clone();
+        renamed.name = "bob".to_string();
+        assert_eq!(renamed.verify(), Err(ContactCardError::InvalidSignature));
+
+        let mut extra_device = card.clone();
+        extra_device.devices.push(SecretKey::generate().public_key());
+        assert_eq!(extra_device.verify(), Err(ContactCardError::InvalidSignature));
+
+        let mut avatar = card.clone();
+        avatar.avatar = Some([0; 32]);
+        assert_eq!(avatar.verify(), Err(ContactCardError::InvalidSignature));
+
+        let long_name = "a".repeat(MAX_NAME_LENGTH + 1);
+        assert_eq!(ContactCard::new(&secret_key, &long_name, None, &[], 0).unwrap_err(), ContactCardError::NameTooLong);
+        let devices = vec![SecretKey::generate().public_key(); MAX_DEVICES + 1];
+        assert_eq!(ContactCard::new(&secret_key, "alice", None, &devices, 0).unwrap_err(), ContactCardError::TooManyDevices);
+
+        let mut typo = card.to_string();
+        typo.remove(20);
+        assert_eq!(typo.parse::<ContactCard>().unwrap_err(), ParseError::Checksum);
+        assert_eq!(secret_key.public_key().to_string().parse::<ContactCard>().unwrap_err(), ParseError::Prefix("muru".to_string()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_largest_card_encodes() {
+        let secret_key = SecretKey::generate_with(Suite::Ed25519);
+        let name = "é".repeat(MAX_NAME_LENGTH / 2);
+        let devices: Vec<PublicKey> = (0..MAX_DEVICES).map(|_| SecretKey::generate().public_key()).collect();
+
+        let card = ContactCard::new(&secret_key, &name, Some([0xff; 32]), &devices, u64::MAX).unwrap();
+        assert_eq!(card.to_string().parse::<ContactCard>().unwrap().verify(), Ok(()));
+    }
+}
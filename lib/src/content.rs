@@ -0,0 +1,343 @@
+//! The typed content carried inside the encrypted body of a message.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use rand::Rng;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    attachment::Attachment,
+    device::DeviceCertificate,
+    error::ParseError,
+    group::{GroupMessage, SenderKeyDistribution},
+    sealed::AccessKey,
+    signing::Encoder,
+};
+
+/// The content encoding written by this version of the library.
+pub const CONTENT_VERSION: u8 = 1;
+
+/// The smallest size content is padded to with [`Padding::PowerOfTwo`].
+pub const MIN_PADDED_LEN: usize = 256;
+
+/// Identifies a piece of content so later content can refer back to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageId([u8; 16]);
+
+impl MessageId {
+    pub fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+        let mut arr = [0; 16];
+        rng.fill(&mut arr[..]);
+        Self(arr)
+    }
+
+    pub fn bytes(&self) -> [u8; 16] {
+        self.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        ParseError::check_length(bytes, 16)?;
+
+        Ok(Self(bytes.try_into().expect("length was checked")))
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(&hex::decode(s)?)
+    }
+}
+
+impl Serialize for MessageId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+
+            s.parse().map_err(|e| serde::de::Error::custom(format!("invalid message id: {}", e)))
+        } else {
+            crate::wire::bytes::deserialize(deserializer).map(Self)
+        }
+    }
+}
+
+/// Ordered so that a read receipt supersedes a delivery receipt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Receipt {
+    Delivered,
+    Read,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Content {
+    /// A plain text message.
+    Text { id: MessageId, text: String },
+    /// A text message in reply to an earlier one.
+    Reply { id: MessageId, parent: MessageId, text: String },
+    /// An encrypted file, its chunks are fetched from the worker.
+    Attachment(Attachment),
+    /// An emoji reaction to an earlier message.
+    Reaction { parent: MessageId, emoji: String },
+    /// Replaces the text of an earlier message from the same sender.
+    Edit { parent: MessageId, text: String },
+    /// Tombstones an earlier message from the same sender.
+    Delete { parent: MessageId },
+    /// Acknowledges earlier messages were delivered or read.
+    Receipt { receipt: Receipt, messages: Vec<MessageId> },
+    /// A sender key for a group chat.
+    SenderKey(SenderKeyDistribution),
+    /// A message encrypted with a group sender key, the plaintext is more content.
+    Group(GroupMessage),
+    /// The sender's access key, so sealed messages can be sent to them.
+    Access { key: AccessKey },
+    /// Introduces the device the sender is writing from, so its messages are
+    /// shown as its identity's.
+    Device { certificate: DeviceCertificate },
+    /// Content from a newer version of the library that this one does not
+    /// understand. It is never sent.
+    #[serde(other)]
+    Unknown,
+}
+
+/// How content is padded before it is encrypted, so the ciphertext reveals a
+/// bucket rather than the exact length of the content. Padding is trailing
+/// whitespace after the JSON. The policy is recorded in the signed message
+/// envelope, so the recipient knows how the content was padded before they
+/// decrypt it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Padding {
+    None,
+    /// Pads to the next power of two, and at least `MIN_PADDED_LEN` bytes.
+    #[default]
+    PowerOfTwo,
+    /// Pads to a multiple of the block size.
+    Block(u32),
+}
+
+impl Padding {
+    /// The length `len` bytes of content are padded to.
+    pub fn padded_len(self, len: usize) -> usize {
+        match self {
+            Self::None | Self::Block(0) => len,
+            Self::PowerOfTwo => len.max(MIN_PADDED_LEN).next_power_of_two(),
+            Self::Block(size) => {
+                let size = size as usize;
+                len + (size - len % size) % size
+            }
+        }
+    }
+
+    /// Strips the padding from decrypted content, `None` if it isn't padded
+    /// the way this policy pads.
+    pub fn unpad(self, bytes: &[u8]) -> Option<&[u8]> {
+        if self == Self::None {
+            return Some(bytes);
+        }
+
+        let end = bytes.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+        (self.padded_len(end) == bytes.len()).then(|| &bytes[..end])
+    }
+
+    /// The policy as signed in a message envelope.
+    pub(crate) fn encode(self, encoder: &mut Encoder) {
+        match self {
+            Self::None => encoder.u8(0),
+            Self::PowerOfTwo => encoder.u8(1),
+            Self::Block(size) => encoder.u8(2).u32(size),
+        };
+    }
+}
+
+/// Decrypted bytes that aren't content, with the reason the JSON was rejected.
+#[derive(Debug)]
+pub struct ContentParseError(serde_json::Error);
+
+impl fmt::Display for ContentParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to parse message content: {}", self.0)
+    }
+}
+
+impl Error for ContentParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    v: u8,
+    #[serde(flatten)]
+    content: &'a Content,
+}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    v: u8,
+}
+
+impl Content {
+    pub fn text(text: &str) -> Self {
+        Self::Text { id: MessageId::generate(), text: text.to_string() }
+    }
+
+    pub fn reply(parent: MessageId, text: &str) -> Self {
+        Self::Reply { id: MessageId::generate(), parent, text: text.to_string() }
+    }
+
+    /// The id of this content, if it is something later content can refer to.
+    pub fn id(&self) -> Option<MessageId> {
+        match self {
+            Self::Text { id, .. } | Self::Reply { id, .. } => Some(*id),
+            Self::Attachment(attachment) => Some(attachment.id),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_padded_bytes(Padding::None)
+    }
+
+    pub fn to_padded_bytes(&self, padding: Padding) -> Vec<u8> {
+        let versioned = Versioned { v: CONTENT_VERSION, content: self };
+
+        let mut bytes = serde_json::to_vec(&versioned).expect("content always serializes");
+        bytes.resize(padding.padded_len(bytes.len()), b' ');
+        bytes
+    }
+
+    /// Parses padded or unpadded content.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ContentParseError> {
+        let end = bytes.iter().rposition(|b| *b != b' ').map_or(0, |i| i + 1);
+        let bytes = &bytes[..end];
+
+        let version: VersionOnly = serde_json::from_slice(bytes).map_err(ContentParseError)?;
+
+        match serde_json::from_slice(bytes) {
+            Ok(content) => Ok(content),
+            // a newer version may have changed the shape of a variant we know about
+            Err(_) if version.v > CONTENT_VERSION => Ok(Self::Unknown),
+            Err(e) => Err(ContentParseError(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::{group::Group, pki::SecretKey};
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_content_round_trip() {
+        let parent = MessageId::generate();
+        let identity = SecretKey::generate().public_key();
+        let mut group = Group::create(&identity, [SecretKey::generate().public_key()]);
+
+        let contents = vec![
+            Content::text("hello"),
+            Content::reply(parent, "hi"),
+            Content::Attachment(Attachment::encrypt("cat.png", "image/png", b"meow").unwrap().0),
+            Content::Reaction { parent, emoji: "👍".to_string() },
+            Content::Edit { parent, text: "hello!".to_string() },
+            Content::Delete { parent },
+            Content::Receipt { receipt: Receipt::Read, messages: vec![parent] },
+            Content::SenderKey(group.distribution()),
+            Content::Group(group.encrypt(b"hello")),
+            Content::Access { key: AccessKey::derive(&SecretKey::generate()) },
+            Content::Device { certificate: DeviceCertificate::new(&SecretKey::generate(), &identity, "phone", 0, &[]) },
+        ];
+
+        for content in contents {
+            let bytes = content.to_bytes();
+            let decoded = Content::from_bytes(&bytes).unwrap();
+
+            assert_eq!(bytes, decoded.to_bytes());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_content_padding() {
+        for padding in [Padding::PowerOfTwo, Padding::Block(100)] {
+            let content = Content::text("yes");
+            let yes = content.to_padded_bytes(padding);
+            let no = Content::text("no").to_padded_bytes(padding);
+            assert_eq!(yes.len(), no.len());
+
+            assert_eq!(padding.unpad(&yes), Some(content.to_bytes().as_slice()));
+
+            // content that wasn't padded the way the policy says is refused
+            assert!(padding.unpad(&content.to_bytes()).is_none());
+            assert!(padding.unpad(&[yes.as_slice(), b" "].concat()).is_none());
+        }
+
+        assert_eq!(Content::text("yes").to_padded_bytes(Padding::PowerOfTwo).len(), MIN_PADDED_LEN);
+        assert_eq!(Content::text("yes").to_padded_bytes(Padding::Block(100)).len(), 100);
+
+        let unpadded = Content::text("yes").to_bytes();
+        assert_eq!(Padding::None.unpad(&unpadded), Some(unpadded.as_slice()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_padded_len() {
+        assert_eq!(Padding::None.padded_len(3), 3);
+        assert_eq!(Padding::PowerOfTwo.padded_len(0), MIN_PADDED_LEN);
+        assert_eq!(Padding::PowerOfTwo.padded_len(MIN_PADDED_LEN), MIN_PADDED_LEN);
+        assert_eq!(Padding::PowerOfTwo.padded_len(MIN_PADDED_LEN + 1), 2 * MIN_PADDED_LEN);
+        assert_eq!(Padding::Block(64).padded_len(64), 64);
+        assert_eq!(Padding::Block(64).padded_len(65), 128);
+        assert_eq!(Padding::Block(0).padded_len(65), 65);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_content_is_versioned() {
+        let content: serde_json::Value = serde_json::from_slice(&Content::text("hello").to_bytes()).unwrap();
+
+        assert_eq!(content["v"], CONTENT_VERSION);
+        assert_eq!(content["type"], "text");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_content_unknown_variant() {
+        let content = Content::from_bytes(br#"{"v":1,"type":"poll","question":"?"}"#).unwrap();
+        assert!(matches!(content, Content::Unknown));
+
+        let content = Content::from_bytes(br#"{"v":2,"type":"text","body":{"rich":true}}"#).unwrap();
+        assert!(matches!(content, Content::Unknown));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_content_parse_error() {
+        assert!(Content::from_bytes(b"garbage").is_err());
+        assert!(Content::from_bytes(br#"{"type":"text"}"#).is_err());
+        assert!(Content::from_bytes(br#"{"v":1,"type":"text","text":"missing id"}"#).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Tamper evident conversation history.
+//!
+//! Every message a sender sends to a recipient names the [`MessageHash`] of the
+//! one they sent them before it, so each sender's messages form a hash chain.
+//! Sequence numbers already show that something is missing, the chain also
+//! shows what: the relay can't drop, reorder or swap messages without the
+//! recipient's [`ConversationLog`] noticing a missing link or a fork.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::pki::PublicKey;
+
+/// Identifies a message within a hash chain, the SHA-256 of its signed fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageHash([u8; 32]);
+
+impl MessageHash {
+    pub(crate) fn of(signing_bytes: &[u8]) -> Self {
+        Self(Sha256::digest(signing_bytes).into())
+    }
+
+    pub fn bytes(&self) -> [u8; 32] {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for MessageHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for MessageHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl Serialize for MessageHash {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer {
+        crate::wire::bytes::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageHash {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de> {
+        crate::wire::bytes::deserialize(deserializer).map(Self)
+    }
+}
+
+/// Something wrong with a sender's chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainIssue {
+    /// A message follows one that never arrived, it was dropped or is late.
+    Missing(MessageHash),
+    /// Several messages follow the same one, so the sender's history has
+    /// split. `None` is the start of the chain.
+    Fork(Option<MessageHash>),
+}
+
+impl fmt::Display for ChainIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(hash) => write!(f, "message {} is missing", hash),
+            Self::Fork(Some(hash)) => write!(f, "history forks after message {}", hash),
+            Self::Fork(None) => f.write_str("history has more than one start"),
+        }
+    }
+}
+
+/// The hash chains of messages received from each sender.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConversationLog {
+    // every message seen from a sender, with the message it follows
+    chains: HashMap<PublicKey, HashMap<MessageHash, Option<MessageHash>>>,
+}
+
+impl ConversationLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a verified message from `from`, following `previous`. Messages
+    /// can be recorded in any order, recording one twice has no effect.
+    pub fn record(&mut self, from: &PublicKey, hash: MessageHash, previous: Option<MessageHash>) {
+        self.chains.entry(from.clone()).or_default().insert(hash, previous);
+    }
+
+    /// Everything wrong with the chain from a sender, oldest first where that
+    /// can be told.
+    pub fn issues(&self, from: &PublicKey) -> Vec<ChainIssue> {
+        let links = match self.chains.get(from) {
+            Some(links) => links,
+            None => return Vec::new(),
+        };
+
+        let mut followed: HashMap<Option<MessageHash>, usize> = HashMap::new();
+        for previous in links.values() {
+            *followed.entry(*previous).or_default() += 1;
+        }
+
+        let mut issues = Vec::new();
+        let mut missing = HashSet::new();
+        for previous in links.values().flatten() {
+            if !links.contains_key(previous) && missing.insert(*previous) {
+                issues.push(ChainIssue::Missing(*previous));
+            }
+        }
+        for (previous, count) in followed {
+            if count > 1 {
+                issues.push(ChainIssue::Fork(previous));
+            }
+        }
+
+        issues
+    }
+
+    /// Whether every message from a sender links back to the start of their
+    /// chain without forking.
+    pub fn is_intact(&self, from: &PublicKey) -> bool {
+        self.issues(from).is_empty()
+    }
+
+    /// The newest message from a sender, if their chain is intact.
+    pub fn head(&self, from: &PublicKey) -> Option<MessageHash> {
+        let links = self.chains.get(from)?;
+        if !self.is_intact(from) {
+            return None;
+        }
+
+        let followed: HashSet<&MessageHash> = links.values().flatten().collect();
+        links.keys().find(|hash| !followed.contains(hash)).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::{content::Content, message::{Message, MessageOptions}, pki::SecretKey};
+    use super::*;
+
+    /// Messages from alice to bob, each following the last.
+    fn chain(alice: &SecretKey, bob: &SecretKey, len: u64) -> Vec<Message> {
+        let mut previous = None;
+        (1..=len)
+            .map(|seq| {
+                let options = MessageOptions { previous, ..Default::default() };
+                let message = Message::with_options(&bob.public_key(), alice, &Content::text("hi"), seq, 0, options);
+                previous = Some(message.hash());
+                message
+            })
+            .collect()
+    }
+
+    fn record(log: &mut ConversationLog, message: &Message) {
+        log.record(&message.from, message.hash(), message.previous);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_intact_chain() {
+        let alice = SecretKey::generate();
+        let messages = chain(&alice, &SecretKey::generate(), 4);
+
+        let mut log = ConversationLog::new();
+        // arrival order doesn't matter once everything has arrived
+        for i in [2, 0, 3, 1, 3] {
+            record(&mut log, &messages[i]);
+        }
+
+        assert!(log.is_intact(&alice.public_key()));
+        assert_eq!(log.head(&alice.public_key()), Some(messages[3].hash()));
+        assert!(log.is_intact(&SecretKey::generate().public_key()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_missing_link() {
+        let alice = SecretKey::generate();
+        let messages = chain(&alice, &SecretKey::generate(), 4);
+
+        let mut log = ConversationLog::new();
+        for i in [0, 1, 3] {
+            record(&mut log, &messages[i]);
+        }
+
+        assert_eq!(log.issues(&alice.public_key()), vec![ChainIssue::Missing(messages[2].hash())]);
+        assert_eq!(log.head(&alice.public_key()), None);
+
+        // the late message fills the gap
+        record(&mut log, &messages[2]);
+        assert!(log.is_intact(&alice.public_key()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_fork() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let messages = chain(&alice, &bob, 2);
+
+        let options = MessageOptions { previous: Some(messages[0].hash()), ..Default::default() };
+        let other = Message::with_options(&bob.public_key(), &alice, &Content::text("hi"), 2, 0, options);
+
+        let mut log = ConversationLog::new();
+        for message in messages.iter().chain([&other]) {
+            record(&mut log, message);
+        }
+
+        assert_eq!(log.issues(&alice.public_key()), vec![ChainIssue::Fork(Some(messages[0].hash()))]);
+
+        // a second chain from the start forks at the beginning
+        let mut log = ConversationLog::new();
+        record(&mut log, &chain(&alice, &bob, 1)[0]);
+        record(&mut log, &chain(&alice, &bob, 1)[0]);
+        assert_eq!(log.issues(&alice.public_key()), vec![ChainIssue::Fork(None)]);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_previous_is_signed() {
+        let alice = SecretKey::generate();
+        let mut messages = chain(&alice, &SecretKey::generate(), 3);
+
+        // a relay can't relink a message to hide one it dropped
+        messages[2].previous = Some(messages[0].hash());
+        assert!(!messages[2].verify());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_log_serialize() {
+        let alice = SecretKey::generate();
+        let messages = chain(&alice, &SecretKey::generate(), 2);
+
+        let mut log = ConversationLog::new();
+        record(&mut log, &messages[1]);
+
+        let log: ConversationLog = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(log.issues(&alice.public_key()), vec![ChainIssue::Missing(messages[0].hash())]);
+    }
+}
@@ -0,0 +1,326 @@
+//! Identities spread over several devices.
+//!
+//! The identity key stays on the device it was made on and signs a
+//! [`DeviceCertificate`] for every other device, which has its own key. A new
+//! device shows a [`LinkCode`] proving it holds its key, the identity device
+//! reads it in and certifies it, and the worker hands the certificate to the
+//! new device and fans messages for the identity out to it from then on.
+//! Contacts check a device's certificate before treating its messages as the
+//! identity's.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use bech32::{primitives::decode::{CheckedHrpstring, CheckedHrpstringError}, Bech32m, Hrp};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    error::ParseError,
+    pki::{PublicKey, SecretKey, Signature},
+    signing::{Encoder, Signable},
+    wire::{self, WireError},
+};
+
+/// How long a link code can be used for after it is shown, in milliseconds.
+pub const LINK_CODE_LIFETIME: u64 = 10 * 60 * 1000;
+
+/// The prefix of an encoded [`LinkCode`].
+pub const LINK_CODE_PREFIX: &str = "murulink";
+
+const LINK_CODE_HRP: Hrp = Hrp::parse_unchecked(LINK_CODE_PREFIX);
+
+#[derive(Debug)]
+pub enum DeviceError {
+    /// A link code or certificate isn't signed by the key it claims.
+    InvalidSignature,
+    /// A link code is too old to be used.
+    Expired,
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => f.write_str("device signature is invalid"),
+            Self::Expired => f.write_str("link code has expired"),
+        }
+    }
+}
+
+impl Error for DeviceError {}
+
+/// What a certified device may do for its identity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// The worker fans messages for the identity out to the device.
+    Receive,
+    /// Contacts accept messages from the device as the identity's.
+    Send,
+}
+
+impl Capability {
+    fn tag(self) -> u8 {
+        match self {
+            Self::Receive => 0,
+            Self::Send => 1,
+        }
+    }
+}
+
+/// An identity key vouching for one of its devices.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceCertificate {
+    pub identity: PublicKey,
+    pub device: PublicKey,
+    pub name: String,
+    /// Milliseconds since the unix epoch on the identity device's clock.
+    pub created_at: u64,
+    pub capabilities: Vec<Capability>,
+    signature: Signature,
+}
+
+impl DeviceCertificate {
+    pub fn new(
+        identity: &SecretKey,
+        device: &PublicKey,
+        name: &str,
+        created_at: u64,
+        capabilities: &[Capability],
+    ) -> Self {
+        let identity_key = identity.public_key();
+
+        let signature = identity.sign(&SignedFields {
+            identity: &identity_key,
+            device,
+            name,
+            created_at,
+            capabilities,
+        });
+
+        Self {
+            identity: identity_key,
+            device: device.clone(),
+            name: name.to_string(),
+            created_at,
+            capabilities: capabilities.to_vec(),
+            signature,
+        }
+    }
+
+    /// Certifies the device that showed a link code, with every capability.
+    pub fn link(identity: &SecretKey, code: &LinkCode, now: u64) -> Result<Self, DeviceError> {
+        if !code.verify() {
+            return Err(DeviceError::InvalidSignature);
+        }
+        if now.saturating_sub(code.created_at) > LINK_CODE_LIFETIME {
+            return Err(DeviceError::Expired);
+        }
+
+        Ok(Self::new(identity, &code.device, &code.name, now, &[Capability::Receive, Capability::Send]))
+    }
+
+    pub fn verify(&self) -> bool {
+        self.identity.verify(self, &self.signature)
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    fn signed_fields(&self) -> SignedFields<'_> {
+        SignedFields {
+            identity: &self.identity,
+            device: &self.device,
+            name: &self.name,
+            created_at: self.created_at,
+            capabilities: &self.capabilities,
+        }
+    }
+}
+
+impl Signable for DeviceCertificate {
+    const CONTEXT: &'static str = SignedFields::CONTEXT;
+    const VERSION: u8 = SignedFields::VERSION;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.signed_fields().encode(encoder)
+    }
+}
+
+/// Everything in a device certificate except the signature.
+struct SignedFields<'a> {
+    identity: &'a PublicKey,
+    device: &'a PublicKey,
+    name: &'a str,
+    created_at: u64,
+    capabilities: &'a [Capability],
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "device certificate";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.identity.bytes())
+            .bytes(&self.device.bytes())
+            .bytes(self.name.as_bytes())
+            .u64(self.created_at)
+            .bytes(&self.capabilities.iter().map(|c| c.tag()).collect::<Vec<u8>>());
+    }
+}
+
+/// Shown by a new device to be linked to an identity, signed by the device key
+/// so nobody can get a key they don't hold certified. Usable once, within
+/// [`LINK_CODE_LIFETIME`] of being made.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkCode {
+    pub device: PublicKey,
+    pub name: String,
+    /// Milliseconds since the unix epoch on the new device's clock.
+    pub created_at: u64,
+    signature: Signature,
+}
+
+impl LinkCode {
+    pub fn new(device: &SecretKey, name: &str, created_at: u64) -> Self {
+        let device_key = device.public_key();
+        let signature = device.sign(&LinkRequest { device: &device_key, name, created_at });
+
+        Self {
+            device: device_key,
+            name: name.to_string(),
+            created_at,
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        let request = LinkRequest { device: &self.device, name: &self.name, created_at: self.created_at };
+
+        self.device.verify(&request, &self.signature)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        wire::decode(bytes)
+    }
+}
+
+/// What a new device signs to ask to be linked.
+struct LinkRequest<'a> {
+    device: &'a PublicKey,
+    name: &'a str,
+    created_at: u64,
+}
+
+impl Signable for LinkRequest<'_> {
+    const CONTEXT: &'static str = "link request";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.device.bytes())
+            .bytes(self.name.as_bytes())
+            .u64(self.created_at);
+    }
+}
+
+/// Link codes are bech32m encoded with a `murulink` prefix, so a mangled
+/// paste fails its checksum.
+impl fmt::Display for LinkCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bech32::encode_lower_to_fmt::<Bech32m, _>(f, LINK_CODE_HRP, &self.to_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl FromStr for LinkCode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = CheckedHrpstring::new::<Bech32m>(s.trim()).map_err(|e| match e {
+            CheckedHrpstringError::Checksum(_) => ParseError::Checksum,
+            _ => ParseError::Encoding,
+        })?;
+        if encoded.hrp() != LINK_CODE_HRP {
+            return Err(ParseError::Prefix(encoded.hrp().to_lowercase()));
+        }
+
+        Self::from_bytes(&encoded.byte_iter().collect::<Vec<u8>>()).map_err(|_| ParseError::Encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_link_device() {
+        let identity = SecretKey::generate();
+        let device = SecretKey::generate();
+
+        let code = LinkCode::new(&device, "laptop", 1000);
+        let code: LinkCode = code.to_string().parse().unwrap();
+        assert!(code.to_string().starts_with("murulink1"));
+
+        let certificate = DeviceCertificate::link(&identity, &code, 2000).unwrap();
+        assert!(certificate.verify());
+        assert_eq!(certificate.identity, identity.public_key());
+        assert_eq!(certificate.device, device.public_key());
+        assert_eq!(certificate.name, "laptop");
+        assert!(certificate.can(Capability::Receive) && certificate.can(Capability::Send));
+
+        let json = serde_json::to_string(&certificate).unwrap();
+        assert!(serde_json::from_str::<DeviceCertificate>(&json).unwrap().verify());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_link_code_checks() {
+        let identity = SecretKey::generate();
+        let device = SecretKey::generate();
+
+        let code = LinkCode::new(&device, "laptop", 1000);
+        assert!(matches!(DeviceCertificate::link(&identity, &code, 1000 + LINK_CODE_LIFETIME + 1), Err(DeviceError::Expired)));
+
+        // someone else can't ask for a key they don't hold to be linked
+        let mut stolen = code.clone();
+        stolen.device = SecretKey::generate().public_key();
+        assert!(matches!(DeviceCertificate::link(&identity, &stolen, 1000), Err(DeviceError::InvalidSignature)));
+
+        let mut renamed = code.clone();
+        renamed.name = "evil".to_string();
+        assert!(!renamed.verify());
+
+        let mut typo = code.to_string();
+        typo.pop();
+        assert_eq!(typo.parse::<LinkCode>().unwrap_err(), ParseError::Checksum);
+        assert_eq!(SecretKey::generate().public_key().to_string().parse::<LinkCode>().unwrap_err(), ParseError::Prefix("muru".to_string()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_certificate_is_signed() {
+        let identity = SecretKey::generate();
+        let certificate = DeviceCertificate::new(&identity, &SecretKey::generate().public_key(), "phone", 0, &[Capability::Receive]);
+        assert!(certificate.verify());
+        assert!(!certificate.can(Capability::Send));
+
+        let mut tampered = certificate.clone();
+        tampered.capabilities.push(Capability::Send);
+        assert!(!tampered.verify());
+
+        let mut tampered = certificate.clone();
+        tampered.device = SecretKey::generate().public_key();
+        assert!(!tampered.verify());
+
+        // only the identity can certify its devices
+        let mut tampered = certificate;
+        tampered.identity = SecretKey::generate().public_key();
+        assert!(!tampered.verify());
+    }
+}
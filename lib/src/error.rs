@@ -0,0 +1,219 @@
+//! Errors from across the library.
+//!
+//! Every module has its own error type for the ways its operations fail, and
+//! [`Error`] wraps all of them so callers that don't care which step failed
+//! can use `?` throughout and still show the precise reason.
+
+use std::fmt;
+
+use crate::{
+    attachment::AttachmentError,
+    blob::BlobError,
+    card::ContactCardError,
+    content::ContentParseError,
+    device::DeviceError,
+    group::GroupError,
+    message::DecryptError,
+    pki::KeyFileError,
+    prekey::PrekeyError,
+    replay::ReplayError,
+    sealed::SealedError,
+    session::SessionError,
+    succession::SuccessionError,
+    wire::WireError,
+};
+
+/// Why a key, mnemonic, signature, challenge or id failed to parse.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The string is not valid hex.
+    Hex(hex::FromHexError),
+    /// The bytes are the wrong length for the value.
+    Length { expected: usize, actual: usize },
+    /// The bytes are not a point on the key's curve.
+    InvalidPoint,
+    /// The bytes are not a valid secret scalar.
+    InvalidScalar,
+    /// The bytes are not a valid signature encoding.
+    InvalidSignature,
+    /// A mnemonic has the wrong number of words.
+    WordCount { expected: usize, actual: usize },
+    /// A mnemonic has a word that isn't in the word list.
+    UnknownWord(String),
+    /// A mnemonic or encoded key's checksum doesn't match, something was
+    /// mistyped.
+    Checksum,
+    /// The text is neither hex nor bech32m.
+    Encoding,
+    /// A bech32m encoded value has the wrong prefix.
+    Prefix(String),
+}
+
+impl ParseError {
+    /// Checks `bytes` are `expected` long.
+    pub(crate) fn check_length(bytes: &[u8], expected: usize) -> Result<(), Self> {
+        match bytes.len() {
+            actual if actual == expected => Ok(()),
+            actual => Err(Self::Length { expected, actual }),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hex(e) => write!(f, "invalid hex: {}", e),
+            Self::Length { expected, actual } => write!(f, "expected {} bytes, got {}", expected, actual),
+            Self::InvalidPoint => f.write_str("not a valid public key"),
+            Self::InvalidScalar => f.write_str("not a valid secret key"),
+            Self::InvalidSignature => f.write_str("not a valid signature"),
+            Self::WordCount { expected, actual } => write!(f, "expected {} words, got {}", expected, actual),
+            Self::UnknownWord(word) => write!(f, "\"{}\" is not a mnemonic word", word),
+            Self::Checksum => f.write_str("checksum doesn't match, check for typos"),
+            Self::Encoding => f.write_str("not valid hex or bech32m"),
+            Self::Prefix(prefix) => write!(f, "unexpected prefix \"{}\"", prefix),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Hex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<hex::FromHexError> for ParseError {
+    fn from(e: hex::FromHexError) -> Self {
+        Self::Hex(e)
+    }
+}
+
+/// Any error from the library.
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    KeyFile(KeyFileError),
+    /// A signature doesn't match what it claims to sign.
+    Signature,
+    /// Bytes from the wire couldn't be decoded, including unsupported versions.
+    Wire(WireError),
+    Content(ContentParseError),
+    Decrypt(DecryptError),
+    Sealed(SealedError),
+    Replay(ReplayError),
+    Prekey(PrekeyError),
+    Session(SessionError),
+    Group(GroupError),
+    Attachment(AttachmentError),
+    Blob(BlobError),
+    Device(DeviceError),
+    Succession(SuccessionError),
+    ContactCard(ContactCardError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(e) => write!(f, "{}", e),
+            Self::KeyFile(e) => write!(f, "{}", e),
+            Self::Signature => f.write_str("invalid signature"),
+            Self::Wire(e) => write!(f, "{}", e),
+            Self::Content(e) => write!(f, "{}", e),
+            Self::Decrypt(e) => write!(f, "{}", e),
+            Self::Sealed(e) => write!(f, "{}", e),
+            Self::Replay(e) => write!(f, "{}", e),
+            Self::Prekey(e) => write!(f, "{}", e),
+            Self::Session(e) => write!(f, "{}", e),
+            Self::Group(e) => write!(f, "{}", e),
+            Self::Attachment(e) => write!(f, "{}", e),
+            Self::Blob(e) => write!(f, "{}", e),
+            Self::Device(e) => write!(f, "{}", e),
+            Self::Succession(e) => write!(f, "{}", e),
+            Self::ContactCard(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse(e) => Some(e),
+            Self::KeyFile(e) => Some(e),
+            Self::Signature => None,
+            Self::Wire(e) => Some(e),
+            Self::Content(e) => Some(e),
+            Self::Decrypt(e) => Some(e),
+            Self::Sealed(e) => Some(e),
+            Self::Replay(e) => Some(e),
+            Self::Prekey(e) => Some(e),
+            Self::Session(e) => Some(e),
+            Self::Group(e) => Some(e),
+            Self::Attachment(e) => Some(e),
+            Self::Blob(e) => Some(e),
+            Self::Device(e) => Some(e),
+            Self::Succession(e) => Some(e),
+            Self::ContactCard(e) => Some(e),
+        }
+    }
+}
+
+macro_rules! from {
+    ($($error:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(e: $error) -> Self {
+                    Self::$variant(e)
+                }
+            }
+        )*
+    };
+}
+
+from! {
+    ParseError => Parse,
+    KeyFileError => KeyFile,
+    WireError => Wire,
+    ContentParseError => Content,
+    DecryptError => Decrypt,
+    SealedError => Sealed,
+    ReplayError => Replay,
+    PrekeyError => Prekey,
+    SessionError => Session,
+    GroupError => Group,
+    AttachmentError => Attachment,
+    BlobError => Blob,
+    DeviceError => Device,
+    SuccessionError => Succession,
+    ContactCardError => ContactCard,
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use std::error::Error as _;
+
+    use wasm_bindgen_test::*;
+
+    use crate::{content::Content, message::Message, pki::SecretKey};
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_error_causes() {
+        let e: Error = "zz".parse::<SecretKey>().err().unwrap().into();
+        assert!(matches!(e, Error::Parse(ParseError::Hex(_))));
+        assert!(e.source().unwrap().source().is_some());
+
+        let alice = SecretKey::generate();
+        let message = Message::new(&alice.public_key(), &SecretKey::generate(), &Content::text("hi"), 1, 0);
+
+        let e: Error = message.decrypt(&SecretKey::generate()).err().unwrap().into();
+        assert_eq!(e.to_string(), "message is not addressed to this key");
+
+        let e: Error = Message::from_bytes(&[9]).unwrap_err().into();
+        assert!(matches!(e, Error::Wire(WireError::UnsupportedVersion(9))));
+    }
+}
@@ -0,0 +1,183 @@
+//! Frames exchanged with the worker over the websocket once the handshake is complete.
+
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    device::DeviceCertificate,
+    message::Message,
+    multi::MultiMessage,
+    pki::PublicKey,
+    sealed::{AccessKey, SealedMessage},
+    succession::KeySuccession,
+    message::LegacyMessage,
+    wire::{self, WireError, LEGACY_WIRE_VERSION},
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Frame {
+    /// Client to worker, relay a message to the inbox of its recipient.
+    Send(Message),
+    /// Worker to client, a message from the inbox of the connected key.
+    Deliver(Message),
+    /// Client to worker, the access key that tokens on sealed messages for the
+    /// connected key are checked with.
+    Access(AccessKey),
+    /// Worker to client, a sealed message from the inbox of the connected key.
+    DeliverSealed(SealedMessage),
+    /// Client to worker, relay a message to the inbox of every recipient.
+    SendMulti(MultiMessage),
+    /// Worker to client, a multi recipient message from the inbox of the
+    /// connected key, with the entries for the other recipients left in.
+    DeliverMulti(MultiMessage),
+    /// Client to worker, a certificate for a device linked to the connected
+    /// identity. The worker fans messages for the identity out to it.
+    Certify(DeviceCertificate),
+    /// Worker to client, the certificate linking the connected device to an
+    /// identity.
+    Linked(DeviceCertificate),
+    /// Client to worker, a statement replacing the connected key, to be
+    /// published and passed on to the inboxes of its contacts.
+    Succeed { succession: KeySuccession, contacts: Vec<PublicKey> },
+    /// Worker to client, a contact's key has been replaced.
+    Succeeded(KeySuccession),
+}
+
+impl Frame {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    /// Also reads frames from clients that wrote [`LEGACY_WIRE_VERSION`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        match bytes.first() {
+            Some(&LEGACY_WIRE_VERSION) => wire::decode_version::<LegacyFrame>(bytes, LEGACY_WIRE_VERSION).map(Self::from),
+            _ => wire::decode(bytes),
+        }
+    }
+}
+
+/// The frames there were in [`LEGACY_WIRE_VERSION`].
+#[derive(Deserialize)]
+enum LegacyFrame {
+    Send(LegacyMessage),
+    Deliver(LegacyMessage),
+}
+
+impl From<LegacyFrame> for Frame {
+    fn from(frame: LegacyFrame) -> Self {
+        match frame {
+            LegacyFrame::Send(message) => Self::Send(message.into()),
+            LegacyFrame::Deliver(message) => Self::Deliver(message.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::{content::Content, pki::SecretKey, wire::WIRE_VERSION};
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_frame_round_trip() {
+        let to = SecretKey::generate();
+        let message = Message::new(&to.public_key(), &SecretKey::generate(), &Content::text("hello"), 1, 0);
+
+        let bytes = Frame::Send(message.clone()).to_bytes();
+        assert_eq!(bytes[0], WIRE_VERSION);
+
+        let json = serde_json::to_string(&Frame::Deliver(message)).unwrap();
+
+        for frame in [Frame::from_bytes(&bytes).unwrap(), serde_json::from_str(&json).unwrap()] {
+            match frame {
+                Frame::Send(message) | Frame::Deliver(message) => {
+                    assert!(message.verify());
+                    assert!(matches!(message.decrypt(&to), Ok(Content::Text { text, .. }) if text == "hello"));
+                },
+                _ => panic!("unexpected frame"),
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sealed_frame_round_trip() {
+        let to = SecretKey::generate();
+        let message = Message::new(&to.public_key(), &SecretKey::generate(), &Content::text("hello"), 1, 0);
+        let sealed = SealedMessage::seal(&message, &AccessKey::derive(&to));
+
+        match Frame::from_bytes(&Frame::DeliverSealed(sealed).to_bytes()).unwrap() {
+            Frame::DeliverSealed(sealed) => assert_eq!(sealed.open(&to).unwrap().id, message.id),
+            _ => panic!("unexpected frame"),
+        }
+
+        assert!(matches!(Frame::from_bytes(&Frame::Access(AccessKey::derive(&to)).to_bytes()), Ok(Frame::Access(_))));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_frame_round_trip() {
+        let to = [SecretKey::generate(), SecretKey::generate()];
+        let recipients = [(to[0].public_key(), 1, None), (to[1].public_key(), 5, None)];
+        let message = MultiMessage::new(&recipients, &SecretKey::generate(), &Content::text("hello"), 0, Default::default());
+
+        match Frame::from_bytes(&Frame::SendMulti(message).to_bytes()).unwrap() {
+            Frame::SendMulti(message) => {
+                assert!(message.verify());
+                for secret_key in &to {
+                    assert!(matches!(message.decrypt(secret_key), Ok(Content::Text { text, .. }) if text == "hello"));
+                }
+            }
+            _ => panic!("unexpected frame"),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_device_frame_round_trip() {
+        let device = SecretKey::generate().public_key();
+        let certificate = DeviceCertificate::new(&SecretKey::generate(), &device, "laptop", 0, &[]);
+
+        match Frame::from_bytes(&Frame::Certify(certificate).to_bytes()).unwrap() {
+            Frame::Certify(certificate) => assert!(certificate.verify() && certificate.device == device),
+            _ => panic!("unexpected frame"),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_succession_frame_round_trip() {
+        let old = SecretKey::generate();
+        let new = SecretKey::generate();
+        let contacts = vec![SecretKey::generate().public_key()];
+        let succession = KeySuccession::countersigned(&old, &new, 0);
+
+        match Frame::from_bytes(&Frame::Succeed { succession, contacts: contacts.clone() }.to_bytes()).unwrap() {
+            Frame::Succeed { succession, contacts: to } => {
+                assert!(succession.verify().is_ok() && succession.new == new.public_key());
+                assert_eq!(to, contacts);
+            }
+            _ => panic!("unexpected frame"),
+        }
+    }
+
+    // a message delivered by a worker from wire format 1
+    const LEGACY_FRAME: &str = "010110d919ffde901b6b3ea1bcbfc7aba40b10210255734e6505b6e520e035d1dc576a0407bc39455c1d3039616dda6b015e96c72721037fed922b60145f38b93bb4958982f566474402973d725ce19b4e57d082c754da80e8a7dd823007b5010410bdfb876225defe35fb88a9b67285e052b13566139c413a8d7d3f61544ebe486bcfc4d77af92eb11219826a6ca2d35be5c6fa877c22df2436338192874478cd48bced87490e85634dcce1661ab2620cc7f3b34af0deb50f8703fc44af218f4c24e45c3a721e50bbf9ef995bf25c0086c366cb6efc7b5f4972d4ca6f6d4edf18639e53755679a5bfe46c7d74e02763138772adce456d8ca47048d44435d9cd8fb0bbff4cec3a9aba9404bc1e2376ae730ed0962b40b23ab09d22f3bcfc39bfd7c562fe645da871c0a7a26ba6f4d914f6032607cc165fec32adaf8937768a0ca9c70143d69f9ba9994101d25c5e2b032c28a5fc1b1d";
+
+    #[wasm_bindgen_test]
+    fn test_frame_legacy() {
+        match Frame::from_bytes(&hex::decode(LEGACY_FRAME).unwrap()).unwrap() {
+            Frame::Deliver(message) => {
+                assert!(message.verify());
+                assert_eq!(message.seq, 7);
+            }
+            _ => panic!("unexpected frame"),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_frame_parse_error() {
+        assert!(matches!(Frame::from_bytes(b""), Err(WireError::Empty)));
+        assert!(matches!(Frame::from_bytes(b"garbage"), Err(WireError::UnsupportedVersion(b'g'))));
+        assert!(matches!(Frame::from_bytes(&[WIRE_VERSION, 9, 9]), Err(WireError::Decode)));
+    }
+}
@@ -0,0 +1,591 @@
+//! Sender key encryption for group chats.
+//!
+//! Each member of a group holds a sending chain, and gives every other member a
+//! copy of it in a [`SenderKeyDistribution`] sent over a pairwise encrypted
+//! message. A group message is then encrypted once with the next key from the
+//! sender's chain and signed with a per chain signing key, no matter how many
+//! members there are.
+//!
+//! Membership changes bump the group's epoch. When someone is removed everyone
+//! throws away their sending chain and distributes a new one to the remaining
+//! members, so the removed member can't read anything sent afterwards.
+
+use std::{collections::{HashMap, HashSet}, error::Error, fmt};
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    pki::{PublicKey, SecretKey, Signature},
+    session::{kdf_chain, open, seal},
+    signing::{Encoder, Signable},
+};
+
+/// Maximum number of message keys that will be skipped in a sender's chain.
+pub const MAX_SKIP: u32 = 1000;
+
+/// Maximum number of skipped message keys kept per sender for late messages.
+const MAX_SKIPPED_KEYS: usize = 1000;
+
+#[derive(Debug)]
+pub enum GroupError {
+    /// The message or distribution is for a different group.
+    WrongGroup,
+    /// The distribution does not list both the sender and the receiver as members.
+    NotMember,
+    /// A newer membership list no longer contains this member.
+    Removed,
+    /// No sender key has been received from the sender of the message.
+    UnknownSender,
+    /// The message was encrypted with a sender key that has since been replaced.
+    StaleKey,
+    /// The message signature does not match the sender key.
+    InvalidSignature,
+    /// The message has already been decrypted.
+    Duplicate,
+    /// The message is further ahead in the sender's chain than [`MAX_SKIP`].
+    TooManySkipped,
+    /// The ciphertext failed authentication.
+    Decrypt,
+}
+
+impl fmt::Display for GroupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongGroup => f.write_str("message is for a different group"),
+            Self::NotMember => f.write_str("not a member of the group"),
+            Self::Removed => f.write_str("removed from the group"),
+            Self::UnknownSender => f.write_str("no sender key for the sender"),
+            Self::StaleKey => f.write_str("message uses a replaced sender key"),
+            Self::InvalidSignature => f.write_str("group message signature is invalid"),
+            Self::Duplicate => f.write_str("message has already been received"),
+            Self::TooManySkipped => f.write_str("too many messages skipped"),
+            Self::Decrypt => f.write_str("failed to decrypt group message"),
+        }
+    }
+}
+
+impl Error for GroupError {}
+
+/// A copy of a member's sending chain, to be sent to each other member over a
+/// pairwise encrypted [`crate::message::Message`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SenderKeyDistribution {
+    pub group_id: String,
+    pub epoch: u32,
+    /// Every member of the group, including the sender.
+    pub members: Vec<PublicKey>,
+    key_id: u32,
+    iteration: u32,
+    chain_key: [u8; 32],
+    signing_key: PublicKey,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMessage {
+    pub group_id: String,
+    pub sender: PublicKey,
+    key_id: u32,
+    iteration: u32,
+    #[serde(with = "crate::wire::bytes")]
+    ciphertext: Vec<u8>,
+    signature: Signature,
+}
+
+impl GroupMessage {
+    fn header(group_id: &str, sender: &PublicKey, key_id: u32, iteration: u32) -> Vec<u8> {
+        [
+            group_id.as_bytes(),
+            sender.bytes().as_slice(),
+            &key_id.to_be_bytes(),
+            &iteration.to_be_bytes(),
+        ].concat()
+    }
+
+    fn signed_fields(&self) -> SignedFields<'_> {
+        SignedFields {
+            group_id: &self.group_id,
+            sender: &self.sender,
+            key_id: self.key_id,
+            iteration: self.iteration,
+            ciphertext: &self.ciphertext,
+        }
+    }
+}
+
+impl Signable for GroupMessage {
+    const CONTEXT: &'static str = SignedFields::CONTEXT;
+    const VERSION: u8 = SignedFields::VERSION;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.signed_fields().encode(encoder)
+    }
+}
+
+/// Everything in a group message except the signature.
+struct SignedFields<'a> {
+    group_id: &'a str,
+    sender: &'a PublicKey,
+    key_id: u32,
+    iteration: u32,
+    ciphertext: &'a [u8],
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "group message";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(self.group_id.as_bytes())
+            .bytes(&self.sender.bytes())
+            .u32(self.key_id)
+            .u32(self.iteration)
+            .bytes(self.ciphertext);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SendingChain {
+    key_id: u32,
+    iteration: u32,
+    chain_key: [u8; 32],
+    signing_key: SecretKey,
+}
+
+impl SendingChain {
+    fn generate() -> Self {
+        let mut rng = rand::thread_rng();
+
+        Self {
+            key_id: rng.gen(),
+            iteration: 0,
+            chain_key: rng.gen(),
+            signing_key: SecretKey::generate(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ReceivingChain {
+    key_id: u32,
+    iteration: u32,
+    chain_key: [u8; 32],
+    signing_key: PublicKey,
+    skipped: Vec<(u32, [u8; 32])>,
+}
+
+impl ReceivingChain {
+    fn message_key(&mut self, iteration: u32) -> Result<[u8; 32], GroupError> {
+        if iteration < self.iteration {
+            let i = self
+                .skipped
+                .iter()
+                .position(|(n, _)| *n == iteration)
+                .ok_or(GroupError::Duplicate)?;
+            return Ok(self.skipped.remove(i).1);
+        }
+
+        if iteration - self.iteration > MAX_SKIP {
+            return Err(GroupError::TooManySkipped);
+        }
+
+        loop {
+            let (chain_key, message_key) = kdf_chain(&self.chain_key);
+            self.chain_key = chain_key;
+            self.iteration += 1;
+
+            if self.iteration - 1 == iteration {
+                return Ok(message_key);
+            }
+
+            self.skipped.push((self.iteration - 1, message_key));
+            if self.skipped.len() > MAX_SKIPPED_KEYS {
+                self.skipped.remove(0);
+            }
+        }
+    }
+}
+
+/// One member's view of a group chat.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Group {
+    id: String,
+    identity: PublicKey,
+    epoch: u32,
+    members: HashSet<PublicKey>,
+    sending: SendingChain,
+    receiving: HashMap<PublicKey, ReceivingChain>,
+    // distributions from members we haven't been told about yet
+    pending: HashMap<PublicKey, SenderKeyDistribution>,
+}
+
+impl Group {
+    /// Creates a new group. [`Group::distribution`] must then be sent to every member.
+    pub fn create(identity: &PublicKey, members: impl IntoIterator<Item = PublicKey>) -> Self {
+        let id: [u8; 16] = rand::thread_rng().gen();
+
+        Self {
+            id: hex::encode(id),
+            identity: identity.clone(),
+            epoch: 0,
+            members: members.into_iter().filter(|m| m != identity).collect(),
+            sending: SendingChain::generate(),
+            receiving: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Joins a group from the first distribution received for it. Our own
+    /// [`Group::distribution`] must then be sent to every member.
+    pub fn join(identity: &PublicKey, from: &PublicKey, distribution: SenderKeyDistribution) -> Result<Self, GroupError> {
+        if !distribution.members.contains(identity) || !distribution.members.contains(from) {
+            return Err(GroupError::NotMember);
+        }
+
+        let mut group = Self {
+            id: distribution.group_id.clone(),
+            identity: identity.clone(),
+            epoch: distribution.epoch,
+            members: distribution.members.iter().filter(|m| *m != identity).cloned().collect(),
+            sending: SendingChain::generate(),
+            receiving: HashMap::new(),
+            pending: HashMap::new(),
+        };
+        group.install(from, distribution);
+
+        Ok(group)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The other members of the group.
+    pub fn members(&self) -> impl Iterator<Item = &PublicKey> {
+        self.members.iter()
+    }
+
+    pub fn distribution(&self) -> SenderKeyDistribution {
+        SenderKeyDistribution {
+            group_id: self.id.clone(),
+            epoch: self.epoch,
+            members: self.members.iter().chain([&self.identity]).cloned().collect(),
+            key_id: self.sending.key_id,
+            iteration: self.sending.iteration,
+            chain_key: self.sending.chain_key,
+            signing_key: self.sending.signing_key.public_key(),
+        }
+    }
+
+    /// Adds a member. [`Group::distribution`] must then be sent to every member
+    /// so the new member gets our sender key and the others learn about them.
+    pub fn add_member(&mut self, public_key: PublicKey) {
+        if public_key != self.identity && self.members.insert(public_key) {
+            self.epoch += 1;
+        }
+    }
+
+    /// Removes a member and replaces our sender key. [`Group::distribution`]
+    /// must then be sent to every remaining member.
+    pub fn remove_member(&mut self, public_key: &PublicKey) {
+        if self.members.remove(public_key) {
+            self.epoch += 1;
+            self.receiving.remove(public_key);
+            self.sending = SendingChain::generate();
+        }
+    }
+
+    /// Processes a distribution sent by `from`, returning the members that need
+    /// to be sent our current [`Group::distribution`] as a result.
+    pub fn process_distribution(&mut self, from: &PublicKey, distribution: SenderKeyDistribution) -> Result<Vec<PublicKey>, GroupError> {
+        if distribution.group_id != self.id {
+            return Err(GroupError::WrongGroup);
+        }
+
+        if !self.members.contains(from) {
+            // the membership update adding them may not have arrived yet
+            self.pending.insert(from.clone(), distribution);
+            return Ok(Vec::new());
+        }
+
+        let mut needs_distribution = Vec::new();
+
+        if distribution.epoch > self.epoch {
+            if !distribution.members.contains(&self.identity) {
+                self.members.clear();
+                self.receiving.clear();
+                return Err(GroupError::Removed);
+            }
+
+            let members: HashSet<PublicKey> = distribution
+                .members
+                .iter()
+                .filter(|m| **m != self.identity)
+                .cloned()
+                .collect();
+            let removed: Vec<PublicKey> = self.members.difference(&members).cloned().collect();
+            let added: Vec<PublicKey> = members.difference(&self.members).cloned().collect();
+
+            self.epoch = distribution.epoch;
+            self.members = members;
+
+            if removed.is_empty() {
+                needs_distribution.extend(added.iter().cloned());
+            } else {
+                for public_key in removed.iter() {
+                    self.receiving.remove(public_key);
+                }
+                self.sending = SendingChain::generate();
+                needs_distribution.extend(self.members.iter().cloned());
+            }
+
+            for public_key in added {
+                if let Some(pending) = self.pending.remove(&public_key) {
+                    self.install(&public_key, pending);
+                }
+            }
+        }
+
+        // a member leaving sends a distribution without themselves in it
+        if self.members.contains(from) {
+            self.install(from, distribution);
+        }
+
+        Ok(needs_distribution)
+    }
+
+    fn install(&mut self, from: &PublicKey, distribution: SenderKeyDistribution) {
+        if let Some(existing) = self.receiving.get(from) {
+            if existing.key_id == distribution.key_id {
+                return;
+            }
+        }
+
+        self.receiving.insert(from.clone(), ReceivingChain {
+            key_id: distribution.key_id,
+            iteration: distribution.iteration,
+            chain_key: distribution.chain_key,
+            signing_key: distribution.signing_key,
+            skipped: Vec::new(),
+        });
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> GroupMessage {
+        let (chain_key, message_key) = kdf_chain(&self.sending.chain_key);
+        let iteration = self.sending.iteration;
+        self.sending.chain_key = chain_key;
+        self.sending.iteration += 1;
+
+        let header = GroupMessage::header(&self.id, &self.identity, self.sending.key_id, iteration);
+        let ciphertext = seal(&message_key, &header, plaintext);
+
+        let signature = self.sending.signing_key.sign(&SignedFields {
+            group_id: &self.id,
+            sender: &self.identity,
+            key_id: self.sending.key_id,
+            iteration,
+            ciphertext: &ciphertext,
+        });
+
+        GroupMessage {
+            group_id: self.id.clone(),
+            sender: self.identity.clone(),
+            key_id: self.sending.key_id,
+            iteration,
+            ciphertext,
+            signature,
+        }
+    }
+
+    pub fn decrypt(&mut self, message: &GroupMessage) -> Result<Vec<u8>, GroupError> {
+        if message.group_id != self.id {
+            return Err(GroupError::WrongGroup);
+        }
+
+        let chain = self.receiving.get_mut(&message.sender).ok_or(GroupError::UnknownSender)?;
+        if chain.key_id != message.key_id {
+            return Err(GroupError::StaleKey);
+        }
+
+        // only authentic messages may advance the chain
+        if !chain.signing_key.verify(message, &message.signature) {
+            return Err(GroupError::InvalidSignature);
+        }
+
+        let message_key = chain.message_key(message.iteration)?;
+        let header = GroupMessage::header(&message.group_id, &message.sender, message.key_id, message.iteration);
+
+        open(&message_key, &header, &message.ciphertext).ok_or(GroupError::Decrypt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    fn identities(n: usize) -> Vec<PublicKey> {
+        (0..n).map(|_| SecretKey::generate().public_key()).collect()
+    }
+
+    /// Sets up a group where every member has every other member's sender key.
+    fn setup(identities: &[PublicKey]) -> Vec<Group> {
+        let mut groups = vec![Group::create(&identities[0], identities[1..].iter().cloned())];
+
+        for identity in identities[1..].iter() {
+            groups.push(Group::join(identity, &identities[0], groups[0].distribution()).unwrap());
+        }
+
+        exchange(identities, &mut groups);
+
+        groups
+    }
+
+    fn exchange(identities: &[PublicKey], groups: &mut [Group]) {
+        for i in 0..groups.len() {
+            let distribution = groups[i].distribution();
+            for (j, group) in groups.iter_mut().enumerate() {
+                if i != j {
+                    group.process_distribution(&identities[i], distribution.clone()).unwrap();
+                }
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_round_trip() {
+        let identities = identities(4);
+        let mut groups = setup(&identities);
+
+        for round in 0..3 {
+            for i in 0..groups.len() {
+                let text = format!("{} says {}", i, round);
+                let message = groups[i].encrypt(text.as_bytes());
+
+                for (j, group) in groups.iter_mut().enumerate() {
+                    if i != j {
+                        assert_eq!(group.decrypt(&message).unwrap(), text.as_bytes());
+                    }
+                }
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_out_of_order() {
+        let identities = identities(2);
+        let mut groups = setup(&identities);
+
+        let messages: Vec<GroupMessage> = (0..5).map(|i| groups[0].encrypt(&[i])).collect();
+
+        for i in [4, 1, 0, 3, 2] {
+            assert_eq!(groups[1].decrypt(&messages[i]).unwrap(), [i as u8]);
+        }
+        assert!(matches!(groups[1].decrypt(&messages[2]), Err(GroupError::Duplicate)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_forged_message() {
+        let identities = identities(3);
+        let mut groups = setup(&identities);
+
+        let mut message = groups[0].encrypt(b"hello");
+        message.ciphertext[0] ^= 1;
+        assert!(matches!(groups[1].decrypt(&message), Err(GroupError::InvalidSignature)));
+
+        // a member can't impersonate another with their own sender key
+        let mut message = groups[2].encrypt(b"from 0, honest");
+        message.sender = identities[0].clone();
+        assert!(groups[1].decrypt(&message).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_new_member_cant_read_history() {
+        let mut identities = identities(2);
+        let mut groups = setup(&identities);
+
+        let before = groups[0].encrypt(b"before");
+        groups[1].decrypt(&before).unwrap();
+
+        let newcomer = SecretKey::generate().public_key();
+        groups[0].add_member(newcomer.clone());
+        let mut joined = Group::join(&newcomer, &identities[0], groups[0].distribution()).unwrap();
+        let distribution = groups[0].distribution();
+        let needs = groups[1].process_distribution(&identities[0], distribution).unwrap();
+        assert_eq!(needs, vec![newcomer.clone()]);
+
+        identities.push(newcomer);
+        groups.push(joined.clone());
+        exchange(&identities, &mut groups);
+
+        assert!(matches!(joined.decrypt(&before), Err(GroupError::Duplicate)));
+
+        let after = groups[0].encrypt(b"after");
+        assert_eq!(groups[2].decrypt(&after).unwrap(), b"after");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_remove_member_rekeys() {
+        let identities = identities(3);
+        let mut groups = setup(&identities);
+
+        // member 0 removes member 2
+        groups[0].remove_member(&identities[2]);
+        let distribution = groups[0].distribution();
+        let needs = groups[1].process_distribution(&identities[0], distribution).unwrap();
+        assert_eq!(needs, vec![identities[0].clone()]);
+        let distribution = groups[1].distribution();
+        groups[0].process_distribution(&identities[1], distribution).unwrap();
+
+        let distribution = groups[0].distribution();
+        assert!(matches!(
+            groups[2].process_distribution(&identities[0], distribution),
+            Err(GroupError::Removed)
+        ));
+
+        let from_0 = groups[0].encrypt(b"secret from 0");
+        let from_1 = groups[1].encrypt(b"secret from 1");
+
+        assert_eq!(groups[1].decrypt(&from_0).unwrap(), b"secret from 0");
+        assert_eq!(groups[0].decrypt(&from_1).unwrap(), b"secret from 1");
+
+        // a removed member that kept the old state can't read the new keys
+        let mut stale = setup(&identities).remove(2);
+        stale.id = groups[0].id.clone();
+        assert!(stale.decrypt(&from_0).is_err());
+        assert!(matches!(groups[2].decrypt(&from_1), Err(GroupError::UnknownSender)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_pending_distribution() {
+        let identities = identities(2);
+        let mut groups = setup(&identities);
+
+        let newcomer = SecretKey::generate().public_key();
+        groups[0].add_member(newcomer.clone());
+        let mut joined = Group::join(&newcomer, &identities[0], groups[0].distribution()).unwrap();
+
+        // the newcomer's key reaches member 1 before member 0's update does
+        groups[1].process_distribution(&newcomer, joined.distribution()).unwrap();
+        let distribution = groups[0].distribution();
+        groups[1].process_distribution(&identities[0], distribution).unwrap();
+
+        let message = joined.encrypt(b"hi all");
+        assert_eq!(groups[1].decrypt(&message).unwrap(), b"hi all");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_group_serialize() {
+        let identities = identities(2);
+        let mut groups = setup(&identities);
+
+        let message = groups[0].encrypt(b"hello");
+        let mut restored: Group = serde_json::from_str(&serde_json::to_string(&groups[1]).unwrap()).unwrap();
+
+        assert_eq!(restored.decrypt(&message).unwrap(), b"hello");
+    }
+}
@@ -1,13 +1,10 @@
 use rand::Rng;
 
-use crate::pki::{SecretKey, PublicKey, Signature};
+use crate::{error::ParseError, pki::{SecretKey, PublicKey, Signature}, signing::{Encoder, Signable}};
 
 #[derive(Debug)]
 pub struct Challenge([u8; 32]);
 
-#[derive(Debug)]
-pub struct ChallengeParseError;
-
 impl Challenge {
     pub fn new() -> Self {
         let mut rng = rand::thread_rng();
@@ -17,21 +14,32 @@ impl Challenge {
     }
 
     pub fn sign(&self, secret_key: &SecretKey) -> Signature {
-        secret_key.sign(&self.0)
+        secret_key.sign(self)
     }
 
 
     pub fn verify(&self, public_key: &PublicKey, signature: &Signature) -> bool {
-        public_key.verify(&self.0, signature)
+        public_key.verify(self, signature)
     }
 
     pub fn bytes(&self) -> [u8; 32] {
         self.0
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ChallengeParseError> {
-        bytes.try_into().map_err(|_| ChallengeParseError {}).map(Self)
-    } 
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        ParseError::check_length(bytes, 32)?;
+
+        Ok(Self(bytes.try_into().expect("length was checked")))
+    }
+}
+
+impl Signable for Challenge {
+    const CONTEXT: &'static str = "challenge";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bytes(&self.0);
+    }
 }
 
 impl Default for Challenge {
@@ -46,30 +54,42 @@ mod tests {
 
     use wasm_bindgen_test::*;
 
-    use crate::pki::SecretKey;
+    use crate::pki::{SecretKey, Suite};
     use super::*;
 
     #[wasm_bindgen_test]
     fn test_handshake_success() {
-        let secret = SecretKey::generate();
-        let public = secret.public_key();
+        for suite in Suite::ALL {
+            let secret = SecretKey::generate_with(suite);
+            let public = secret.public_key();
 
-        let challenge = Challenge::new();
-        let sig = challenge.sign(&secret);
-        let verified = challenge.verify(&public, &sig);
+            let challenge = Challenge::new();
+            let sig = challenge.sign(&secret);
+            let sig = Signature::from_bytes(&sig.bytes()).unwrap();
+            let verified = challenge.verify(&public, &sig);
 
-        assert!(verified);
+            assert!(verified);
+        }
     }
 
     #[wasm_bindgen_test]
     fn test_handshake_fail() {
-        let secret = SecretKey::generate();
-        let public = SecretKey::generate().public_key(); // public key from a different private key
+        for suite in Suite::ALL {
+            let secret = SecretKey::generate_with(suite);
+            let public = SecretKey::generate_with(suite).public_key(); // public key from a different private key
 
-        let challenge = Challenge::new();
-        let sig = challenge.sign(&secret);
-        let verified = challenge.verify(&public, &sig);
+            let challenge = Challenge::new();
+            let sig = challenge.sign(&secret);
+            let verified = challenge.verify(&public, &sig);
 
-        assert!(!verified);
+            assert!(!verified);
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_challenge_parse() {
+        let challenge = Challenge::new();
+        assert_eq!(Challenge::from_bytes(&challenge.bytes()).unwrap().bytes(), challenge.bytes());
+        assert_eq!(Challenge::from_bytes(&[0; 31]).unwrap_err(), ParseError::Length { expected: 32, actual: 31 });
     }
 }
@@ -1,3 +1,25 @@
+pub mod attachment;
+pub mod attestation;
+pub mod blob;
+pub mod card;
+pub mod content;
+pub mod conversation;
+pub mod device;
+pub mod error;
+pub mod frame;
+pub mod group;
 pub mod handshake;
 pub mod message;
+pub mod multi;
 pub mod pki;
+pub mod prekey;
+pub mod replay;
+pub mod revocation;
+pub mod safety;
+pub mod sealed;
+pub mod session;
+pub mod signing;
+pub mod succession;
+pub mod wire;
+
+pub use error::Error;
@@ -1,48 +1,312 @@
-use std::error::Error;
+use std::fmt;
 
 use serde::{Serialize, Deserialize};
 
-use crate::pki::{PublicKey, Signature, SecretKey};
+use crate::{
+    content::{Content, ContentParseError, MessageId, Padding},
+    conversation::MessageHash,
+    error::Error,
+    pki::{PublicKey, Signature, SecretKey},
+    signing::{Encoder, Signable},
+    wire::{self, WireError, LEGACY_WIRE_VERSION},
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
+    pub id: MessageId,
     pub to: PublicKey,
     pub from: PublicKey,
+    /// Milliseconds since the unix epoch on the sender's clock.
+    pub timestamp: u64,
+    /// Counts up from 1 for each message the sender sends to the recipient.
+    pub seq: u64,
+    /// Milliseconds after `timestamp` when the message disappears, if ever.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// The message the sender sent the recipient before this one.
+    #[serde(default)]
+    pub previous: Option<MessageHash>,
+    /// How the content was padded before it was encrypted.
+    #[serde(default = "unpadded")]
+    pub padding: Padding,
+    #[serde(with = "crate::wire::bytes")]
     ciphertext: Vec<u8>,
-    signature: Signature,    
+    signature: Signature,
+}
+
+// messages from before the policy was recorded weren't padded
+fn unpadded() -> Padding {
+    Padding::None
+}
+
+/// A message as laid out in [`LEGACY_WIRE_VERSION`], before messages could
+/// expire, name the one before them or record their padding.
+#[derive(Deserialize)]
+pub(crate) struct LegacyMessage {
+    id: MessageId,
+    to: PublicKey,
+    from: PublicKey,
+    timestamp: u64,
+    seq: u64,
+    #[serde(with = "crate::wire::bytes")]
+    ciphertext: Vec<u8>,
+    signature: Signature,
+}
+
+impl From<LegacyMessage> for Message {
+    fn from(message: LegacyMessage) -> Self {
+        Self {
+            id: message.id,
+            to: message.to,
+            from: message.from,
+            timestamp: message.timestamp,
+            seq: message.seq,
+            expires_in: None,
+            previous: None,
+            padding: Padding::None,
+            ciphertext: message.ciphertext,
+            signature: message.signature,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecryptError {
+    /// The secret key does not belong to the recipient of the message.
+    WrongRecipient,
+    /// The ciphertext failed authentication, it has been tampered with or was
+    /// encrypted to a different key.
+    Ciphertext,
+    /// The decrypted plaintext isn't padded the way the envelope says.
+    Padding,
+    /// The decrypted plaintext is not valid content.
+    Content(ContentParseError),
+}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongRecipient => f.write_str("message is not addressed to this key"),
+            Self::Ciphertext => f.write_str("failed to decrypt message ciphertext"),
+            Self::Padding => f.write_str("decrypted message doesn't match its padding policy"),
+            Self::Content(e) => write!(f, "decrypted message is not valid: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecryptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Content(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for a new message beyond its content.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessageOptions {
+    pub padding: Padding,
+    /// Milliseconds after it is sent when the message disappears.
+    pub expires_in: Option<u64>,
+    /// The hash of the last message sent to the same recipient, to chain them.
+    pub previous: Option<MessageHash>,
 }
 
 impl Message {
-    pub fn new(to: &PublicKey, secret_key: &SecretKey, plaintext: &str)-> Self {
-        // TODO encrypt this
-        let ciphertext = plaintext.as_bytes().to_vec();
+    /// A message with content padded to the default policy, that doesn't expire.
+    pub fn new(to: &PublicKey, secret_key: &SecretKey, content: &Content, seq: u64, timestamp: u64) -> Self {
+        Self::with_options(to, secret_key, content, seq, timestamp, MessageOptions::default())
+    }
 
+    pub fn with_options(
+        to: &PublicKey,
+        secret_key: &SecretKey,
+        content: &Content,
+        seq: u64,
+        timestamp: u64,
+        options: MessageOptions,
+    ) -> Self {
+        // ECIES to the recipient, only the holder of their secret key can read this.
+        let ciphertext = to.encrypt(&content.to_padded_bytes(options.padding));
+
+        let id = MessageId::generate();
         let from = secret_key.public_key();
 
-        let signature = secret_key.sign(&Self::sig_material(to, &from, &ciphertext));
+        let signature = secret_key.sign(&SignedFields {
+            id: &id,
+            to,
+            from: &from,
+            timestamp,
+            seq,
+            expires_in: options.expires_in,
+            previous: options.previous,
+            padding: options.padding,
+            ciphertext: &ciphertext,
+        });
 
         Self {
+            id,
             to: to.clone(),
             from,
+            timestamp,
+            seq,
+            expires_in: options.expires_in,
+            previous: options.previous,
+            padding: options.padding,
             ciphertext,
             signature
         }
     }
 
+    /// Older clients signed fewer fields, their messages verify as long as
+    /// none of the fields they didn't sign are set.
     pub fn verify(&self) -> bool {
-        self.from.verify( &Self::sig_material(&self.to, &self.from, &self.ciphertext), &self.signature)
+        self.from.verify(self, &self.signature) || self.is_legacy() && self.from.verify(&self.legacy_fields(), &self.signature)
     }
 
-    pub fn decrypt(&self) -> Result<String, Box<dyn Error>> {
-        Ok(std::str::from_utf8(&self.ciphertext)?.to_string())
+    fn is_legacy(&self) -> bool {
+        self.expires_in.is_none() && self.previous.is_none() && self.padding == Padding::None
     }
 
-    fn sig_material(to: &PublicKey, from: &PublicKey, ciphertext: &[u8]) -> Vec<u8> {
-        return [
-            to.bytes().as_slice(),
-            from.bytes().as_slice(),
-            ciphertext,
-        ].concat()
+    /// Identifies the message in the sender's chain, the next message they send
+    /// the recipient names it as `previous`.
+    pub fn hash(&self) -> MessageHash {
+        MessageHash::of(&self.signing_bytes())
+    }
+
+    /// Milliseconds since the unix epoch when the message disappears, on the
+    /// sender's clock.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_in.map(|expires_in| self.timestamp.saturating_add(expires_in))
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at().is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Verifies the signature and decrypts the content.
+    pub fn open(&self, secret_key: &SecretKey) -> Result<Content, Error> {
+        if !self.verify() {
+            return Err(Error::Signature);
+        }
+
+        Ok(self.decrypt(secret_key)?)
+    }
+
+    pub fn decrypt(&self, secret_key: &SecretKey) -> Result<Content, DecryptError> {
+        if secret_key.public_key() != self.to {
+            return Err(DecryptError::WrongRecipient);
+        }
+
+        let plaintext = secret_key.decrypt(&self.ciphertext).ok_or(DecryptError::Ciphertext)?;
+        let plaintext = self.padding.unpad(&plaintext).ok_or(DecryptError::Padding)?;
+
+        Content::from_bytes(plaintext).map_err(DecryptError::Content)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    /// Also reads messages from clients that wrote [`LEGACY_WIRE_VERSION`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        match bytes.first() {
+            Some(&LEGACY_WIRE_VERSION) => wire::decode_version::<LegacyMessage>(bytes, LEGACY_WIRE_VERSION).map(Self::from),
+            _ => wire::decode(bytes),
+        }
+    }
+
+    fn signed_fields(&self) -> SignedFields<'_> {
+        SignedFields {
+            id: &self.id,
+            to: &self.to,
+            from: &self.from,
+            timestamp: self.timestamp,
+            seq: self.seq,
+            expires_in: self.expires_in,
+            previous: self.previous,
+            padding: self.padding,
+            ciphertext: &self.ciphertext,
+        }
+    }
+
+    fn legacy_fields(&self) -> LegacySignedFields<'_> {
+        LegacySignedFields {
+            id: &self.id,
+            to: &self.to,
+            from: &self.from,
+            timestamp: self.timestamp,
+            seq: self.seq,
+            ciphertext: &self.ciphertext,
+        }
+    }
+}
+
+impl Signable for Message {
+    const CONTEXT: &'static str = SignedFields::CONTEXT;
+    const VERSION: u8 = SignedFields::VERSION;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.signed_fields().encode(encoder)
+    }
+}
+
+/// Everything in a message except the signature, so it can be signed before
+/// the message exists.
+struct SignedFields<'a> {
+    id: &'a MessageId,
+    to: &'a PublicKey,
+    from: &'a PublicKey,
+    timestamp: u64,
+    seq: u64,
+    expires_in: Option<u64>,
+    previous: Option<MessageHash>,
+    padding: Padding,
+    ciphertext: &'a [u8],
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "message";
+    const VERSION: u8 = 4;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.id.bytes())
+            .bytes(&self.to.bytes())
+            .bytes(&self.from.bytes())
+            .u64(self.timestamp)
+            .u64(self.seq)
+            .u8(u8::from(self.expires_in.is_some()))
+            .u64(self.expires_in.unwrap_or_default())
+            .bytes(self.previous.as_ref().map_or(&[], AsRef::as_ref));
+        self.padding.encode(encoder);
+        encoder.bytes(self.ciphertext);
+    }
+}
+
+/// What clients that wrote [`LEGACY_WIRE_VERSION`] signed.
+struct LegacySignedFields<'a> {
+    id: &'a MessageId,
+    to: &'a PublicKey,
+    from: &'a PublicKey,
+    timestamp: u64,
+    seq: u64,
+    ciphertext: &'a [u8],
+}
+
+impl Signable for LegacySignedFields<'_> {
+    const CONTEXT: &'static str = "message";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.id.bytes())
+            .bytes(&self.to.bytes())
+            .bytes(&self.from.bytes())
+            .u64(self.timestamp)
+            .u64(self.seq)
+            .bytes(self.ciphertext);
     }
 }
 
@@ -52,6 +316,7 @@ mod tests {
 
     use wasm_bindgen_test::*;
 
+    use crate::pki::Suite;
     use super::*;
 
     #[wasm_bindgen_test]
@@ -61,7 +326,7 @@ mod tests {
 
         let plaintext = "The quick brown fox jumps over the lazy dog";
 
-        let message = Message::new(&to_public, &from_secret, plaintext);
+        let message = Message::new(&to_public, &from_secret, &Content::text(plaintext), 1, 0);
 
         let verified = message.verify();
 
@@ -70,15 +335,255 @@ mod tests {
 
     #[wasm_bindgen_test]
     fn test_message_decrypt() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        let plaintext = "The quick brown fox jumps over the lazy dog";
+
+        let message = Message::new(&to_secret.public_key(), &from_secret, &Content::text(plaintext), 1, 0);
+
+        match message.decrypt(&to_secret).unwrap() {
+            Content::Text { text, .. } => assert_eq!(plaintext, text),
+            _ => panic!("wrong content"),
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_every_suite() {
+        // the sender and recipient don't need to share a suite
+        for from_suite in Suite::ALL {
+            for to_suite in Suite::ALL {
+                let to_secret = SecretKey::generate_with(to_suite);
+                let from_secret = SecretKey::generate_with(from_suite);
+
+                let message = Message::new(&to_secret.public_key(), &from_secret, &Content::text("hello"), 1, 0);
+                let message = Message::from_bytes(&message.to_bytes()).unwrap();
+
+                assert!(message.verify());
+                assert!(matches!(message.decrypt(&to_secret), Ok(Content::Text { text, .. }) if text == "hello"));
+                assert!(matches!(message.decrypt(&from_secret), Err(DecryptError::WrongRecipient)));
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_is_encrypted() {
         let to_public =  SecretKey::generate().public_key();
         let from_secret = SecretKey::generate();
 
         let plaintext = "The quick brown fox jumps over the lazy dog";
 
-        let message = Message::new(&to_public, &from_secret, plaintext);
+        let message = Message::new(&to_public, &from_secret, &Content::text(plaintext), 1, 0);
+
+        assert!(!message
+            .ciphertext
+            .windows(plaintext.len())
+            .any(|w| w == plaintext.as_bytes()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_hides_length() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        for padding in [Padding::PowerOfTwo, Padding::Block(128)] {
+            let yes = Message::with_options(&to_secret.public_key(), &from_secret, &Content::text("yes"), 1, 0, MessageOptions { padding, ..Default::default() });
+            let no = Message::with_options(&to_secret.public_key(), &from_secret, &Content::text("no"), 2, 0, MessageOptions { padding, ..Default::default() });
+
+            assert_eq!(yes.ciphertext.len(), no.ciphertext.len());
+            assert!(matches!(no.decrypt(&to_secret), Ok(Content::Text { text, .. }) if text == "no"));
+        }
+
+        let yes = Message::with_options(&to_secret.public_key(), &from_secret, &Content::text("yes"), 1, 0, MessageOptions { padding: Padding::None, ..Default::default() });
+        let no = Message::with_options(&to_secret.public_key(), &from_secret, &Content::text("no"), 2, 0, MessageOptions { padding: Padding::None, ..Default::default() });
+        assert_ne!(yes.ciphertext.len(), no.ciphertext.len());
+        assert!(matches!(yes.decrypt(&to_secret), Ok(Content::Text { text, .. }) if text == "yes"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_padding_in_envelope() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        let options = MessageOptions { padding: Padding::Block(128), ..Default::default() };
+        let message = Message::with_options(&to_secret.public_key(), &from_secret, &Content::text("yes"), 1, 0, options);
+        let decoded = Message::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(decoded.padding, Padding::Block(128));
+
+        // the policy is signed, and the content has to match it
+        let mut tampered = decoded;
+        tampered.padding = Padding::PowerOfTwo;
+        assert!(!tampered.verify());
+        assert!(matches!(tampered.decrypt(&to_secret), Err(DecryptError::Padding)));
+    }
+
+    // written by a client from wire format 1, to `LEGACY_TO`
+    const LEGACY_TO: &str = "a50b25c890134960caf612fa7a0e87e57314c179593e0852855d289fc2e1943c";
+    const LEGACY_FROM: &str = "037fed922b60145f38b93bb4958982f566474402973d725ce19b4e57d082c754da";
+    const LEGACY_MESSAGE: &str = "0110d919ffde901b6b3ea1bcbfc7aba40b10210255734e6505b6e520e035d1dc576a0407bc39455c1d3039616dda6b015e96c72721037fed922b60145f38b93bb4958982f566474402973d725ce19b4e57d082c754da80e8a7dd823007b5010410bdfb876225defe35fb88a9b67285e052b13566139c413a8d7d3f61544ebe486bcfc4d77af92eb11219826a6ca2d35be5c6fa877c22df2436338192874478cd48bced87490e85634dcce1661ab2620cc7f3b34af0deb50f8703fc44af218f4c24e45c3a721e50bbf9ef995bf25c0086c366cb6efc7b5f4972d4ca6f6d4edf18639e53755679a5bfe46c7d74e02763138772adce456d8ca47048d44435d9cd8fb0bbff4cec3a9aba9404bc1e2376ae730ed0962b40b23ab09d22f3bcfc39bfd7c562fe645da871c0a7a26ba6f4d914f6032607cc165fec32adaf8937768a0ca9c70143d69f9ba9994101d25c5e2b032c28a5fc1b1d";
+    const LEGACY_JSON: &str = r#"{"id":"d919ffde901b6b3ea1bcbfc7aba40b10","to":"0255734e6505b6e520e035d1dc576a0407bc39455c1d3039616dda6b015e96c727","from":"037fed922b60145f38b93bb4958982f566474402973d725ce19b4e57d082c754da","timestamp":1650000000000,"seq":7,"ciphertext":"0410bdfb876225defe35fb88a9b67285e052b13566139c413a8d7d3f61544ebe486bcfc4d77af92eb11219826a6ca2d35be5c6fa877c22df2436338192874478cd48bced87490e85634dcce1661ab2620cc7f3b34af0deb50f8703fc44af218f4c24e45c3a721e50bbf9ef995bf25c0086c366cb6efc7b5f4972d4ca6f6d4edf18639e53755679a5bfe46c7d74e02763138772adce456d8ca47048d44435d9cd8fb0bbff4cec3a9aba9404bc1e2376ae730ed0962b","signature":"b23ab09d22f3bcfc39bfd7c562fe645da871c0a7a26ba6f4d914f6032607cc165fec32adaf8937768a0ca9c70143d69f9ba9994101d25c5e2b032c28a5fc1b1d"}"#;
+
+    #[wasm_bindgen_test]
+    fn test_message_legacy() {
+        let to_secret: SecretKey = LEGACY_TO.parse().unwrap();
+        let from: PublicKey = LEGACY_FROM.parse().unwrap();
+
+        let binary = Message::from_bytes(&hex::decode(LEGACY_MESSAGE).unwrap()).unwrap();
+        let json: Message = serde_json::from_str(LEGACY_JSON).unwrap();
+
+        for message in [binary, json] {
+            assert!(message.verify());
+            assert_eq!((message.from.clone(), message.seq, message.timestamp), (from.clone(), 7, 1_650_000_000_000));
+            assert_eq!((message.expires_in, message.previous, message.padding), (None, None, Padding::None));
+            assert!(matches!(message.decrypt(&to_secret), Ok(Content::Text { text, .. }) if text == "hello from v1"));
+
+            // written again in the current format, it still verifies
+            let decoded = Message::from_bytes(&message.to_bytes()).unwrap();
+            assert_eq!(message.to_bytes()[0], wire::WIRE_VERSION);
+            assert!(decoded.verify());
+
+            // the fields it didn't sign can't be added
+            let mut expiring = message.clone();
+            expiring.expires_in = Some(1);
+            assert!(!expiring.verify());
+
+            let mut padded = message;
+            padded.padding = Padding::PowerOfTwo;
+            assert!(!padded.verify());
+        }
+
+        // a current message isn't accepted in the legacy layout
+        let message = Message::new(&to_secret.public_key(), &SecretKey::generate(), &Content::text("hello"), 1, 0);
+        let mut bytes = message.to_bytes();
+        bytes[0] = LEGACY_WIRE_VERSION;
+        assert!(Message::from_bytes(&bytes).map_or(true, |m| !m.verify()));
+        bytes[0] = 2;
+        assert!(matches!(Message::from_bytes(&bytes), Err(WireError::UnsupportedVersion(2))));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_decrypt_wrong_recipient() {
+        let to_public = SecretKey::generate().public_key();
+        let from_secret = SecretKey::generate();
+        let other_secret = SecretKey::generate();
+
+        let message = Message::new(&to_public, &from_secret, &Content::text("hello"), 1, 0);
+
+        assert!(matches!(message.decrypt(&other_secret), Err(DecryptError::WrongRecipient)));
+        assert!(matches!(message.decrypt(&from_secret), Err(DecryptError::WrongRecipient)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_open() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        let message = Message::new(&to_secret.public_key(), &from_secret, &Content::text("hello"), 1, 0);
+        assert!(matches!(message.open(&to_secret), Ok(Content::Text { text, .. }) if text == "hello"));
+        assert!(matches!(message.open(&from_secret), Err(Error::Decrypt(DecryptError::WrongRecipient))));
+
+        let mut replayed = message;
+        replayed.seq += 1;
+        assert!(matches!(replayed.open(&to_secret), Err(Error::Signature)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_decrypt_tampered() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        let mut message = Message::new(&to_secret.public_key(), &from_secret, &Content::text("hello"), 1, 0);
+        let last = message.ciphertext.len() - 1;
+        message.ciphertext[last] ^= 1;
+
+        assert!(!message.verify());
+        assert!(matches!(message.decrypt(&to_secret), Err(DecryptError::Ciphertext)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_decrypt_invalid_content() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        let options = MessageOptions { padding: Padding::None, ..Default::default() };
+        let mut message = Message::with_options(&to_secret.public_key(), &from_secret, &Content::text("hello"), 1, 0, options);
+        message.ciphertext = to_secret.public_key().encrypt(b"not content");
+
+        assert!(matches!(message.decrypt(&to_secret), Err(DecryptError::Content(_))));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_encodings_round_trip() {
+        let to_secret = SecretKey::generate();
+        let from_secret = SecretKey::generate();
+
+        let message = Message::new(&to_secret.public_key(), &from_secret, &Content::text("hello"), 1, 0);
+
+        let json = serde_json::to_string(&message).unwrap();
+        assert!(json.contains(&hex::encode(message.from.bytes())));
+        assert!(json.contains(&hex::encode(&message.ciphertext)));
+
+        let binary = message.to_bytes();
+        assert!(binary.len() < json.len() / 2);
+
+        for decoded in [serde_json::from_str::<Message>(&json).unwrap(), Message::from_bytes(&binary).unwrap()] {
+            assert!(decoded.verify());
+            assert_eq!(decoded.id, message.id);
+            assert!(matches!(decoded.decrypt(&to_secret), Ok(Content::Text { text, .. }) if text == "hello"));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_metadata_is_signed() {
+        let to_public = SecretKey::generate().public_key();
+        let from_secret = SecretKey::generate();
+
+        let message = Message::new(&to_public, &from_secret, &Content::text("hello"), 7, 1_660_000_000_000);
+        assert!(message.verify());
+
+        let mut replayed = message.clone();
+        replayed.seq += 1;
+        assert!(!replayed.verify());
+
+        let mut replayed = message.clone();
+        replayed.timestamp += 1;
+        assert!(!replayed.verify());
+
+        let mut replayed = message.clone();
+        replayed.id = MessageId::generate();
+        assert!(!replayed.verify());
+
+        let mut replayed = message;
+        replayed.expires_in = Some(1);
+        assert!(!replayed.verify());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_message_expiry() {
+        let to_public = SecretKey::generate().public_key();
+        let from_secret = SecretKey::generate();
+        let sent = 1_660_000_000_000;
+
+        let message = Message::new(&to_public, &from_secret, &Content::text("hello"), 1, sent);
+        assert_eq!(message.expires_at(), None);
+        assert!(!message.is_expired(u64::MAX));
+
+        let options = MessageOptions { expires_in: Some(60_000), ..Default::default() };
+        let message = Message::with_options(&to_public, &from_secret, &Content::text("hello"), 1, sent, options);
+        assert!(message.verify());
+        assert_eq!(message.expires_at(), Some(sent + 60_000));
+        assert!(!message.is_expired(sent));
+        assert!(!message.is_expired(sent + 59_999));
+        assert!(message.is_expired(sent + 60_000));
 
-        let decrypted = message.decrypt().unwrap();
+        let decoded = Message::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(decoded.expires_in, Some(60_000));
+        assert!(decoded.verify());
 
-        assert_eq!(plaintext, decrypted);
+        // expiry can't be pushed back without breaking the signature
+        let mut extended = message;
+        extended.expires_in = Some(u64::MAX);
+        assert!(!extended.is_expired(sent + 60_000));
+        assert!(!extended.verify());
     }
 }
@@ -0,0 +1,403 @@
+//! Messages addressed to several recipients at once.
+//!
+//! The content is encrypted once with a fresh content key, and only that key
+//! is encrypted to each recipient, so a message to a large group costs one
+//! copy of the body rather than one per member. A single signature covers the
+//! body and every recipient, and the worker can check it and fan the message
+//! out to each recipient's inbox without being able to read it.
+//!
+//! Recipients with linked devices get the content key wrapped for each of
+//! their devices too, and the worker fans the message out to them.
+
+use std::collections::HashMap;
+
+use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm, Nonce};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    content::{Content, MessageId, Padding},
+    conversation::MessageHash,
+    message::{DecryptError, MessageOptions},
+    pki::{PublicKey, Signature, SecretKey},
+    signing::{Encoder, Signable},
+    wire::{self, WireError},
+};
+
+/// A recipient of a [`MultiMessage`], with their own sequence number and copy
+/// of the content key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipient {
+    pub to: PublicKey,
+    /// Counts up from 1 for each message the sender sends to this recipient,
+    /// shared with single recipient messages.
+    pub seq: u64,
+    /// The message the sender sent this recipient before this one.
+    pub previous: Option<MessageHash>,
+    #[serde(with = "crate::wire::bytes")]
+    wrapped_key: Vec<u8>,
+    /// The recipient's linked devices, each with its own copy of the content key.
+    #[serde(default)]
+    pub devices: Vec<DeviceKey>,
+}
+
+/// The content key wrapped for one of a recipient's devices.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceKey {
+    pub device: PublicKey,
+    #[serde(with = "crate::wire::bytes")]
+    wrapped_key: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiMessage {
+    pub id: MessageId,
+    pub from: PublicKey,
+    /// Milliseconds since the unix epoch on the sender's clock.
+    pub timestamp: u64,
+    /// Milliseconds after `timestamp` when the message disappears, if ever.
+    pub expires_in: Option<u64>,
+    pub recipients: Vec<Recipient>,
+    /// How the content was padded before it was encrypted.
+    pub padding: Padding,
+    #[serde(with = "crate::wire::bytes")]
+    ciphertext: Vec<u8>,
+    signature: Signature,
+}
+
+impl MultiMessage {
+    /// Encrypts content once for every recipient, each given with the next
+    /// sequence number for them and the last message sent to them.
+    pub fn new(
+        recipients: &[(PublicKey, u64, Option<MessageHash>)],
+        secret_key: &SecretKey,
+        content: &Content,
+        timestamp: u64,
+        options: MessageOptions,
+    ) -> Self {
+        Self::with_devices(recipients, &HashMap::new(), secret_key, content, timestamp, options)
+    }
+
+    /// [`MultiMessage::new`], also readable by the devices linked to each
+    /// recipient's identity.
+    pub fn with_devices(
+        recipients: &[(PublicKey, u64, Option<MessageHash>)],
+        devices: &HashMap<PublicKey, Vec<PublicKey>>,
+        secret_key: &SecretKey,
+        content: &Content,
+        timestamp: u64,
+        options: MessageOptions,
+    ) -> Self {
+        let mut key = [0; 32];
+        rand::thread_rng().fill(&mut key[..]);
+
+        let id = MessageId::generate();
+
+        // the key is only ever used for this one body, so a fixed nonce is safe
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(&Nonce::default(), Payload { msg: &content.to_padded_bytes(options.padding), aad: &id.bytes() })
+            .expect("aes-gcm encryption does not fail");
+
+        let recipients = recipients
+            .iter()
+            .map(|(to, seq, previous)| Recipient {
+                to: to.clone(),
+                seq: *seq,
+                previous: *previous,
+                wrapped_key: wrap(to, &key),
+                devices: devices
+                    .get(to)
+                    .into_iter()
+                    .flatten()
+                    .map(|device| DeviceKey { device: device.clone(), wrapped_key: wrap(device, &key) })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+
+        let from = secret_key.public_key();
+
+        let signature = secret_key.sign(&SignedFields {
+            id: &id,
+            from: &from,
+            timestamp,
+            expires_in: options.expires_in,
+            recipients: &recipients,
+            padding: options.padding,
+            ciphertext: &ciphertext,
+        });
+
+        Self {
+            id,
+            from,
+            timestamp,
+            expires_in: options.expires_in,
+            recipients,
+            padding: options.padding,
+            ciphertext,
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> bool {
+        self.from.verify(self, &self.signature)
+    }
+
+    /// Identifies the message in the sender's chain to every recipient.
+    pub fn hash(&self) -> MessageHash {
+        MessageHash::of(&self.signing_bytes())
+    }
+
+    pub fn recipient(&self, public_key: &PublicKey) -> Option<&Recipient> {
+        self.recipients.iter().find(|r| r.to == *public_key)
+    }
+
+    /// Milliseconds since the unix epoch when the message disappears, on the
+    /// sender's clock.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_in.map(|expires_in| self.timestamp.saturating_add(expires_in))
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at().is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Whether `device` is one of the linked devices the message is for.
+    pub fn is_for_device(&self, device: &PublicKey) -> bool {
+        self.device_key(device).is_some()
+    }
+
+    fn device_key(&self, device: &PublicKey) -> Option<&DeviceKey> {
+        self.recipients.iter().flat_map(|r| &r.devices).find(|d| d.device == *device)
+    }
+
+    /// Decrypts the content as a recipient, or as one of their linked devices.
+    pub fn decrypt(&self, secret_key: &SecretKey) -> Result<Content, DecryptError> {
+        let public_key = secret_key.public_key();
+        let wrapped_key = match (self.recipient(&public_key), self.device_key(&public_key)) {
+            (Some(recipient), _) => &recipient.wrapped_key,
+            (None, Some(device)) => &device.wrapped_key,
+            (None, None) => return Err(DecryptError::WrongRecipient),
+        };
+
+        let key: [u8; 32] = secret_key
+            .decrypt(wrapped_key)
+            .and_then(|key| key.try_into().ok())
+            .ok_or(DecryptError::Ciphertext)?;
+
+        let plaintext = Aes256Gcm::new(&key.into())
+            .decrypt(&Nonce::default(), Payload { msg: &self.ciphertext, aad: &self.id.bytes() })
+            .map_err(|_| DecryptError::Ciphertext)?;
+        let plaintext = self.padding.unpad(&plaintext).ok_or(DecryptError::Padding)?;
+
+        Content::from_bytes(plaintext).map_err(DecryptError::Content)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        wire::decode(bytes)
+    }
+
+    fn signed_fields(&self) -> SignedFields<'_> {
+        SignedFields {
+            id: &self.id,
+            from: &self.from,
+            timestamp: self.timestamp,
+            expires_in: self.expires_in,
+            recipients: &self.recipients,
+            padding: self.padding,
+            ciphertext: &self.ciphertext,
+        }
+    }
+}
+
+fn wrap(to: &PublicKey, key: &[u8; 32]) -> Vec<u8> {
+    to.encrypt(key)
+}
+
+impl Signable for MultiMessage {
+    const CONTEXT: &'static str = SignedFields::CONTEXT;
+    const VERSION: u8 = SignedFields::VERSION;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.signed_fields().encode(encoder)
+    }
+}
+
+/// Everything in a multi recipient message except the signature.
+struct SignedFields<'a> {
+    id: &'a MessageId,
+    from: &'a PublicKey,
+    timestamp: u64,
+    expires_in: Option<u64>,
+    recipients: &'a [Recipient],
+    padding: Padding,
+    ciphertext: &'a [u8],
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "multi message";
+    const VERSION: u8 = 4;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.id.bytes())
+            .bytes(&self.from.bytes())
+            .u64(self.timestamp)
+            .u8(u8::from(self.expires_in.is_some()))
+            .u64(self.expires_in.unwrap_or_default())
+            .u32(self.recipients.len() as u32);
+
+        for recipient in self.recipients {
+            encoder
+                .bytes(&recipient.to.bytes())
+                .u64(recipient.seq)
+                .bytes(recipient.previous.as_ref().map_or(&[], AsRef::as_ref))
+                .bytes(&recipient.wrapped_key)
+                .u32(recipient.devices.len() as u32);
+
+            for device in &recipient.devices {
+                encoder.bytes(&device.device.bytes()).bytes(&device.wrapped_key);
+            }
+        }
+
+        self.padding.encode(encoder);
+        encoder.bytes(self.ciphertext);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+    use crate::pki::Suite;
+
+    fn recipients(keys: &[SecretKey]) -> Vec<(PublicKey, u64, Option<MessageHash>)> {
+        keys.iter().enumerate().map(|(i, k)| (k.public_key(), i as u64 + 1, None)).collect()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_message_decrypt() {
+        let from = SecretKey::generate();
+        let to: Vec<SecretKey> = (0..3).map(|_| SecretKey::generate()).collect();
+
+        let message = MultiMessage::new(&recipients(&to), &from, &Content::text("hello"), 0, MessageOptions::default());
+        let message = MultiMessage::from_bytes(&message.to_bytes()).unwrap();
+        assert!(message.verify());
+
+        for (i, secret_key) in to.iter().enumerate() {
+            assert_eq!(message.recipient(&secret_key.public_key()).unwrap().seq, i as u64 + 1);
+            assert!(matches!(message.decrypt(secret_key), Ok(Content::Text { text, .. }) if text == "hello"));
+        }
+
+        assert!(matches!(message.decrypt(&from), Err(DecryptError::WrongRecipient)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_message_mixed_suites() {
+        let from = SecretKey::generate_with(Suite::Ed25519);
+        let to: Vec<SecretKey> = Suite::ALL.iter().chain(Suite::ALL.iter()).map(|s| SecretKey::generate_with(*s)).collect();
+
+        let message = MultiMessage::new(&recipients(&to), &from, &Content::text("hello"), 0, MessageOptions::default());
+        let message = MultiMessage::from_bytes(&message.to_bytes()).unwrap();
+        assert!(message.verify());
+
+        for (i, secret_key) in to.iter().enumerate() {
+            assert_eq!(message.recipient(&secret_key.public_key()).unwrap().seq, i as u64 + 1);
+            assert!(matches!(message.decrypt(secret_key), Ok(Content::Text { text, .. }) if text == "hello"));
+        }
+
+        assert!(matches!(message.decrypt(&from), Err(DecryptError::WrongRecipient)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_message_body_is_shared() {
+        let from = SecretKey::generate();
+        let few: Vec<SecretKey> = (0..2).map(|_| SecretKey::generate()).collect();
+        let many: Vec<SecretKey> = (0..20).map(|_| SecretKey::generate()).collect();
+
+        let text = "a".repeat(4096);
+        let options = MessageOptions { padding: crate::content::Padding::None, ..Default::default() };
+        let to_few = MultiMessage::new(&recipients(&few), &from, &Content::text(&text), 0, options);
+        let to_many = MultiMessage::new(&recipients(&many), &from, &Content::text(&text), 0, options);
+
+        // only the wrapped keys grow with the number of recipients
+        assert_eq!(to_few.ciphertext.len(), to_many.ciphertext.len());
+        let per_recipient = (to_many.to_bytes().len() - to_few.to_bytes().len()) / 18;
+        assert!(per_recipient < 256);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_message_is_signed() {
+        let from = SecretKey::generate();
+        let to: Vec<SecretKey> = (0..2).map(|_| SecretKey::generate()).collect();
+
+        let message = MultiMessage::new(&recipients(&to), &from, &Content::text("hello"), 0, MessageOptions::default());
+
+        let mut tampered = message.clone();
+        tampered.recipients[0].seq += 1;
+        assert!(!tampered.verify());
+
+        let mut tampered = message.clone();
+        tampered.recipients[0].previous = Some(message.hash());
+        assert!(!tampered.verify());
+
+        let mut tampered = message.clone();
+        tampered.recipients.pop();
+        assert!(!tampered.verify());
+
+        let mut tampered = message.clone();
+        tampered.recipients[1].wrapped_key = message.recipients[0].wrapped_key.clone();
+        assert!(!tampered.verify());
+
+        let mut tampered = message.clone();
+        tampered.expires_in = Some(1);
+        assert!(!tampered.verify());
+
+        let mut tampered = message;
+        let last = tampered.ciphertext.len() - 1;
+        tampered.ciphertext[last] ^= 1;
+        assert!(!tampered.verify());
+        assert!(matches!(tampered.decrypt(&to[0]), Err(DecryptError::Ciphertext)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_message_to_devices() {
+        let from = SecretKey::generate();
+        let to: Vec<SecretKey> = (0..2).map(|_| SecretKey::generate()).collect();
+        let laptop = SecretKey::generate();
+
+        let devices = HashMap::from([(to[0].public_key(), vec![laptop.public_key()])]);
+        let message = MultiMessage::with_devices(&recipients(&to), &devices, &from, &Content::text("hello"), 0, MessageOptions::default());
+        let message = MultiMessage::from_bytes(&message.to_bytes()).unwrap();
+        assert!(message.verify());
+
+        assert!(message.is_for_device(&laptop.public_key()));
+        assert!(!message.is_for_device(&to[1].public_key()));
+        assert!(message.recipient(&laptop.public_key()).is_none());
+        for secret_key in to.iter().chain([&laptop]) {
+            assert!(matches!(message.decrypt(secret_key), Ok(Content::Text { text, .. }) if text == "hello"));
+        }
+
+        // the devices are signed too
+        let mut tampered = message;
+        tampered.recipients[0].devices.clear();
+        assert!(!tampered.verify());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_multi_message_wrapped_key_for_someone_else() {
+        let from = SecretKey::generate();
+        let to: Vec<SecretKey> = (0..2).map(|_| SecretKey::generate()).collect();
+
+        let mut message = MultiMessage::new(&recipients(&to), &from, &Content::text("hello"), 0, MessageOptions::default());
+        message.recipients[1].wrapped_key = message.recipients[0].wrapped_key.clone();
+
+        assert!(matches!(message.decrypt(&to[1]), Err(DecryptError::Ciphertext)));
+    }
+}
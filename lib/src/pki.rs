@@ -1,22 +1,164 @@
-use k256::{elliptic_curve::sec1::ToEncodedPoint, ecdsa::{self, SigningKey, VerifyingKey, signature::{Signer, Verifier}}};
+use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Version};
+use bech32::{primitives::decode::{CheckedHrpstring, CheckedHrpstringError}, Bech32m, Hrp};
+use bip39::{Language, Mnemonic};
+use rand::Rng;
 use serde::{
     de::{self, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
 };
 use std::{
-    fmt::{self, Debug, Display},
+    fmt::{self, Debug},
     hash::{Hash, Hasher},
     str::FromStr,
 };
 
+use crate::{error::ParseError, signing::Signable};
+
+pub mod suite;
+
+pub use suite::{CipherSuite, Ed25519, Secp256k1, Suite};
+
+/// Marks Ed25519 keys, secret keys and signatures in their encoding.
+/// secp256k1 ones go without, their SEC1 tag already tells them apart and
+/// they stay readable by older versions.
+pub const ED25519_TAG: u8 = 0xed;
+
+/// The word in front of an Ed25519 key's mnemonic, which isn't in the BIP39
+/// word list so can't be mistaken for the start of a secp256k1 one.
+const ED25519_MNEMONIC_WORD: &str = "ed25519";
+
 #[derive(Debug, Clone, std::cmp::Eq)]
-pub struct PublicKey(k256::PublicKey);
+pub struct PublicKey(PublicKeyInner);
 
 #[derive(PartialEq, Clone)]
-pub struct SecretKey(k256::SecretKey);
+pub struct SecretKey(SecretKeyInner);
 
-#[derive(Debug)]
-pub struct Signature(ecdsa::Signature);
+#[derive(Debug, Clone)]
+pub struct Signature(SignatureInner);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PublicKeyInner {
+    Secp256k1(<Secp256k1 as CipherSuite>::PublicKey),
+    Ed25519(<Ed25519 as CipherSuite>::PublicKey),
+}
+
+#[derive(PartialEq, Clone)]
+enum SecretKeyInner {
+    Secp256k1(<Secp256k1 as CipherSuite>::SecretKey),
+    Ed25519(<Ed25519 as CipherSuite>::SecretKey),
+}
+
+#[derive(Debug, Clone)]
+enum SignatureInner {
+    Secp256k1(<Secp256k1 as CipherSuite>::Signature),
+    Ed25519(<Ed25519 as CipherSuite>::Signature),
+}
+
+/// Runs `$body` with `$S` naming the [`CipherSuite`] of a [`Suite`], or of
+/// the variant an inner key enum holds with `$k` bound to the suite's own
+/// value. The only match over the suites, the methods below are written once
+/// against the trait.
+macro_rules! with_suite {
+    ($inner:ident($value:expr) => |$k:pat, $S:ident| $body:expr) => {
+        match $value {
+            $inner::Secp256k1($k) => { type $S = Secp256k1; $body }
+            $inner::Ed25519($k) => { type $S = Ed25519; $body }
+        }
+    };
+    ($suite:expr => |$S:ident| $body:expr) => {
+        match $suite {
+            Suite::Secp256k1 => { type $S = Secp256k1; $body }
+            Suite::Ed25519 => { type $S = Ed25519; $body }
+        }
+    };
+}
+
+/// How a suite is marked in encodings, and which variant of the inner key
+/// enums holds it.
+trait Variant: CipherSuite {
+    /// In front of the suite's encodings, `None` for the suite that goes
+    /// without.
+    const TAG: Option<u8>;
+    /// In front of the suite's mnemonics, `None` for the suite that goes
+    /// without.
+    const MNEMONIC_WORD: Option<&'static str>;
+
+    fn public_key_inner(key: Self::PublicKey) -> PublicKeyInner;
+    fn secret_key_inner(key: Self::SecretKey) -> SecretKeyInner;
+    fn signature_inner(signature: Self::Signature) -> SignatureInner;
+
+    // `None` for another suite's
+    fn as_public_key(key: &PublicKeyInner) -> Option<&Self::PublicKey>;
+    fn as_signature(signature: &SignatureInner) -> Option<&Self::Signature>;
+}
+
+macro_rules! variant {
+    ($suite:ident, $tag:expr, $mnemonic_word:expr) => {
+        impl Variant for $suite {
+            const TAG: Option<u8> = $tag;
+            const MNEMONIC_WORD: Option<&'static str> = $mnemonic_word;
+
+            fn public_key_inner(key: Self::PublicKey) -> PublicKeyInner {
+                PublicKeyInner::$suite(key)
+            }
+
+            fn secret_key_inner(key: Self::SecretKey) -> SecretKeyInner {
+                SecretKeyInner::$suite(key)
+            }
+
+            fn signature_inner(signature: Self::Signature) -> SignatureInner {
+                SignatureInner::$suite(signature)
+            }
+
+            fn as_public_key(key: &PublicKeyInner) -> Option<&Self::PublicKey> {
+                match key {
+                    PublicKeyInner::$suite(key) => Some(key),
+                    _ => None,
+                }
+            }
+
+            fn as_signature(signature: &SignatureInner) -> Option<&Self::Signature> {
+                match signature {
+                    SignatureInner::$suite(signature) => Some(signature),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+variant!(Secp256k1, None, None);
+variant!(Ed25519, Some(ED25519_TAG), Some(ED25519_MNEMONIC_WORD));
+
+// the suite whose encodings and mnemonics go unmarked
+fn unmarked_suite() -> Suite {
+    Suite::ALL
+        .into_iter()
+        .find(|suite| with_suite!(suite => |S| S::TAG).is_none())
+        .expect("one suite goes without a tag")
+}
+
+// the suite's tag, if it has one, and its own encoding
+fn tagged<S: Variant>(bytes: &[u8]) -> Vec<u8> {
+    S::TAG.into_iter().chain(bytes.iter().copied()).collect()
+}
+
+// the suite `bytes` are in and its own encoding of them: the suite whose tag
+// they start with if `length` bytes follow it, the unmarked suite otherwise
+fn untagged(bytes: &[u8], length: usize) -> (Suite, &[u8]) {
+    let tagged = match bytes.split_first() {
+        Some((&first, rest)) if rest.len() == length => {
+            Suite::ALL.into_iter().find(|suite| with_suite!(suite => |S| S::TAG) == Some(first))
+        }
+        _ => None,
+    };
+
+    match tagged {
+        Some(suite) => (suite, &bytes[1..]),
+        None => (unmarked_suite(), bytes),
+    }
+}
 
 struct SignatureVisitor;
 
@@ -24,17 +166,21 @@ impl<'de> Visitor<'de> for SignatureVisitor {
     type Value = Signature;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a secp256k1 signature")
+        formatter.write_str("a signature")
     }
 
     fn visit_str<E>(self, value: &str) -> Result<Signature, E>
     where
         E: de::Error,
     {
-        let bytes = hex::decode(value).map_err(|e| de::Error::custom(e))?;
-        let sig = Signature::from_bytes(&bytes).map_err(|e| de::Error::custom(e))?;
+        Signature::from_str(value).map_err(de::Error::custom)
+    }
 
-        Ok(sig)
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Signature, E>
+    where
+        E: de::Error,
+    {
+        Signature::from_bytes(value).map_err(de::Error::custom)
     }
 }
 
@@ -42,7 +188,11 @@ impl<'de> Deserialize<'de> for Signature {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de> {
-        deserializer.deserialize_str(SignatureVisitor)
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(SignatureVisitor)
+        } else {
+            deserializer.deserialize_bytes(SignatureVisitor)
+        }
     }
 
     fn deserialize_in_place<D>(deserializer: D, place: &mut Self) -> Result<(), D::Error>
@@ -59,72 +209,296 @@ impl Serialize for Signature {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer {
-        serializer.collect_str(&hex::encode(self.bytes()))
+        if serializer.is_human_readable() {
+            serializer.collect_str(&hex::encode(self.bytes()))
+        } else {
+            serializer.serialize_bytes(&self.bytes())
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct PublicKeyParseError;
+struct PublicKeyVisitor;
 
-#[derive(Debug)]
-pub struct SecretKeyParseError;
+struct SecretKeyVisitor;
 
-#[derive(Debug)]
-pub struct SignatureParseError;
+impl PublicKey {
+    pub fn suite(&self) -> Suite {
+        with_suite!(PublicKeyInner(&self.0) => |_, S| S::SUITE)
+    }
 
-impl Display for SignatureParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("failed to parse signature")
+    /// A compressed SEC1 point for secp256k1, the tagged key for Ed25519.
+    pub fn bytes(&self) -> [u8; 33] {
+        let bytes = with_suite!(PublicKeyInner(&self.0) => |k, S| tagged::<S>(&S::public_key_bytes(k)));
+
+        bytes.try_into().expect("public keys encode to 33 bytes")
     }
-}
 
-struct PublicKeyVisitor;
+    /// Parses a SEC1 encoded secp256k1 key, compressed or not, or a tagged
+    /// Ed25519 key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let (suite, key) = untagged(bytes, 32);
 
-impl PublicKey {
-    pub fn bytes(&self) -> [u8; 33] {
-        self.0.to_encoded_point(true).as_bytes().try_into().unwrap()
+        with_suite!(suite => |S| Ok(Self(S::public_key_inner(S::public_key_from_bytes(key)?))))
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PublicKeyParseError> {
-        k256::PublicKey::from_sec1_bytes(
-            bytes
-        ).map_err(|_| PublicKeyParseError {}).map(Self)
+    /// Verifies a signature made by [`SecretKey::sign`], only for the same kind of object.
+    pub fn verify<T: Signable>(&self, object: &T, signature: &Signature) -> bool {
+        let message = object.signing_bytes();
+
+        with_suite!(PublicKeyInner(&self.0) => |k, S| {
+            S::as_signature(&signature.0).is_some_and(|s| S::verify(k, &message, s))
+        })
     }
 
-    pub fn verify(&self, bytes: &[u8], signature: &Signature) -> bool {
-        VerifyingKey::from(&self.0).verify(bytes, &signature.0).is_ok()
+    /// Encrypts to this key with a fresh ephemeral key, so only the holder of
+    /// the secret key can [`SecretKey::decrypt`] it.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        with_suite!(PublicKeyInner(&self.0) => |k, S| S::encrypt(k, plaintext))
     }
 }
 
 impl SecretKey {
+    /// A new secp256k1 key.
     pub fn generate() -> Self {
-        let rng = rand::thread_rng();
-        let secret_key = k256::SecretKey::random(rng);
-        Self(secret_key)
+        Self::generate_with(Suite::Secp256k1)
+    }
+
+    pub fn generate_with(suite: Suite) -> Self {
+        with_suite!(suite => |S| Self(S::secret_key_inner(S::generate())))
+    }
+
+    pub fn suite(&self) -> Suite {
+        with_suite!(SecretKeyInner(&self.0) => |_, S| S::SUITE)
     }
 
     pub fn public_key(&self) -> PublicKey {
-        PublicKey(self.0.public_key())
+        with_suite!(SecretKeyInner(&self.0) => |k, S| PublicKey(S::public_key_inner(S::public_key(k))))
     }
 
-    pub fn sign(&self, bytes: &[u8]) -> Signature {
-        let sig: ecdsa::Signature = SigningKey::from(&self.0).sign(bytes);
+    /// The 32 byte scalar for secp256k1, the tagged seed for Ed25519.
+    pub fn bytes(&self) -> Vec<u8> {
+        with_suite!(SecretKeyInner(&self.0) => |k, S| tagged::<S>(&S::secret_key_bytes(k)))
+    }
 
-        Signature(sig)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let (suite, key) = untagged(bytes, 32);
+
+        Self::from_suite_bytes(suite, key)
+    }
+
+    /// A key in `suite` made from 32 bytes of key material rather than drawn
+    /// at random. Fails for the few secp256k1 values that aren't a scalar.
+    pub(crate) fn from_seed(suite: Suite, seed: [u8; 32]) -> Result<Self, ParseError> {
+        Self::from_suite_bytes(suite, &seed)
+    }
+
+    // `bytes` in the suite's own encoding, without a tag
+    fn from_suite_bytes(suite: Suite, bytes: &[u8]) -> Result<Self, ParseError> {
+        with_suite!(suite => |S| Ok(Self(S::secret_key_inner(S::secret_key_from_bytes(bytes)?))))
+    }
+
+    /// The key as a 24 word BIP39 mnemonic, easier to write down and type back
+    /// in than hex. The last word carries a checksum. Ed25519 keys have an
+    /// extra `ed25519` in front.
+    pub fn to_mnemonic(&self) -> String {
+        let (word, entropy) = with_suite!(SecretKeyInner(&self.0) => |k, S| (S::MNEMONIC_WORD, S::secret_key_bytes(k)));
+        let mnemonic = Mnemonic::from_entropy(&entropy).expect("32 bytes is valid entropy");
+
+        match word {
+            Some(word) => format!("{} {}", word, mnemonic),
+            None => mnemonic.to_string(),
+        }
+    }
+
+    /// Recovers a key from [`SecretKey::to_mnemonic`]. Case and extra
+    /// whitespace are ignored.
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Self, ParseError> {
+        let mut words: Vec<String> = mnemonic.split_whitespace().map(str::to_lowercase).collect();
+        let first = words.first().map(String::as_str);
+        let suite = match Suite::ALL.into_iter().find(|suite| first.is_some() && with_suite!(suite => |S| S::MNEMONIC_WORD) == first) {
+            Some(suite) => {
+                words.remove(0);
+                suite
+            }
+            None => unmarked_suite(),
+        };
+        if words.len() != MNEMONIC_WORDS {
+            return Err(ParseError::WordCount { expected: MNEMONIC_WORDS, actual: words.len() });
+        }
+
+        let mnemonic = Mnemonic::parse_in_normalized(Language::English, &words.join(" ")).map_err(|e| match e {
+            bip39::Error::UnknownWord(i) => ParseError::UnknownWord(words[i].clone()),
+            _ => ParseError::Checksum,
+        })?;
+
+        Self::from_suite_bytes(suite, &mnemonic.to_entropy())
+    }
+
+    pub fn sign<T: Signable>(&self, object: &T) -> Signature {
+        let message = object.signing_bytes();
+
+        with_suite!(SecretKeyInner(&self.0) => |k, S| Signature(S::signature_inner(S::sign(k, &message))))
+    }
+
+    /// Encrypts the key with a passphrase into a key file, for backing it up
+    /// or moving it to another device.
+    pub fn export_encrypted(&self, passphrase: &str) -> String {
+        self.export_encrypted_with(passphrase, KdfParams::default())
+    }
+
+    /// [`SecretKey::export_encrypted`] with a chosen KDF cost.
+    pub fn export_encrypted_with(&self, passphrase: &str, kdf: KdfParams) -> String {
+        let mut rng = rand::thread_rng();
+        let salt: [u8; 16] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+
+        let key = kdf.derive(passphrase, &salt).expect("export parameters are valid");
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: &self.bytes(), aad: &key_file_aad(KEY_FILE_VERSION) })
+            .expect("encryption doesn't fail");
+
+        let key_file = KeyFile { version: KEY_FILE_VERSION, kdf, salt, nonce, ciphertext };
+        serde_json::to_string(&key_file).expect("key files always serialize")
+    }
+
+    /// Decrypts a key file made by [`SecretKey::export_encrypted`].
+    pub fn import_encrypted(key_file: &str, passphrase: &str) -> Result<Self, KeyFileError> {
+        let key_file: KeyFile = serde_json::from_str(key_file).map_err(|_| KeyFileError::Format)?;
+        if key_file.version != KEY_FILE_VERSION {
+            return Err(KeyFileError::UnsupportedVersion(key_file.version));
+        }
+
+        let key = key_file.kdf.derive(passphrase, &key_file.salt)?;
+        let plaintext = Aes256Gcm::new(&key.into())
+            .decrypt(Nonce::from_slice(&key_file.nonce), Payload { msg: &key_file.ciphertext, aad: &key_file_aad(key_file.version) })
+            .map_err(|_| KeyFileError::Passphrase)?;
+
+        Self::from_bytes(&plaintext).map_err(KeyFileError::Key)
+    }
+
+    /// Elliptic curve Diffie-Hellman with another party's public key. The
+    /// result is raw key material and should be passed through a KDF before use.
+    /// `None` if the key is from another suite.
+    pub fn diffie_hellman(&self, public_key: &PublicKey) -> Option<[u8; 32]> {
+        with_suite!(SecretKeyInner(&self.0) => |k, S| {
+            S::as_public_key(&public_key.0).and_then(|p| S::diffie_hellman(k, p))
+        })
+    }
+
+    /// Decrypts what was [`PublicKey::encrypt`]ed to this key.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        with_suite!(SecretKeyInner(&self.0) => |k, S| S::decrypt(k, ciphertext))
     }
 }
 
 impl Signature {
-    pub fn bytes(&self) -> &[u8] {
-        use k256::ecdsa::signature::Signature;
+    /// 64 bytes for secp256k1, tagged for Ed25519.
+    pub fn bytes(&self) -> Vec<u8> {
+        with_suite!(SignatureInner(&self.0) => |s, S| tagged::<S>(&S::signature_bytes(s)))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError> {
+        let (suite, signature) = untagged(bytes, 64);
+
+        with_suite!(suite => |S| Ok(Self(S::signature_inner(S::signature_from_bytes(signature)?))))
+    }
+}
+
+/// The number of words in a secret key's mnemonic.
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// The key file format written by [`SecretKey::export_encrypted`].
+pub const KEY_FILE_VERSION: u8 = 1;
+
+/// The Argon2id cost of turning a passphrase into the key that encrypts a key
+/// file. It is stored in the file, so the defaults can be raised without
+/// breaking older files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Memory in KiB.
+    pub memory: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Key files asking for more memory than this are refused, so importing
+    /// one can't exhaust the device.
+    pub const MAX_MEMORY: u32 = 256 * 1024;
+
+    fn derive(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; 32], KeyFileError> {
+        if self.memory > Self::MAX_MEMORY {
+            return Err(KeyFileError::Parameters);
+        }
+
+        let params = argon2::Params::new(self.memory, self.iterations, self.parallelism, Some(32))
+            .map_err(|_| KeyFileError::Parameters)?;
+        let mut key = [0; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|_| KeyFileError::Parameters)?;
+
+        Ok(key)
+    }
+}
+
+impl Default for KdfParams {
+    /// OWASP's recommended minimum for Argon2id.
+    fn default() -> Self {
+        Self { memory: 19 * 1024, iterations: 2, parallelism: 1 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    version: u8,
+    kdf: KdfParams,
+    #[serde(with = "crate::wire::bytes")]
+    salt: [u8; 16],
+    #[serde(with = "crate::wire::bytes")]
+    nonce: [u8; 12],
+    #[serde(with = "crate::wire::bytes")]
+    ciphertext: Vec<u8>,
+}
+
+// the KDF parameters and salt are already bound by the derived key
+fn key_file_aad(version: u8) -> Vec<u8> {
+    [b"muruchat key file".as_slice(), &[version]].concat()
+}
+
+/// Why a key file couldn't be imported.
+#[derive(Debug)]
+pub enum KeyFileError {
+    /// The text is not a key file.
+    Format,
+    /// The key file was written by a format this version doesn't know.
+    UnsupportedVersion(u8),
+    /// The key file's KDF parameters are invalid or too expensive.
+    Parameters,
+    /// The passphrase is wrong, or the key file was tampered with.
+    Passphrase,
+    /// The key file decrypted to something that isn't a secret key.
+    Key(ParseError),
+}
 
-        self.0.as_bytes()
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Format => f.write_str("not a key file"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported key file version {}", v),
+            Self::Parameters => f.write_str("key file has invalid key derivation parameters"),
+            Self::Passphrase => f.write_str("wrong passphrase"),
+            Self::Key(e) => write!(f, "key file holds an invalid key: {}", e),
+        }
     }
+}
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SignatureParseError> {
-        ecdsa::signature::Signature::from_bytes(
-            bytes
-        ).map_err(|_| SignatureParseError {}).map(Self)
+impl std::error::Error for KeyFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Key(e) => Some(e),
+            _ => None,
+        }
     }
 }
 
@@ -141,19 +515,40 @@ impl Hash for PublicKey {
         H: Hasher,
     {
         state.write(&self.bytes());
-        state.finish();
     }
 }
 
+/// The prefix of a bech32m encoded public key.
+pub const PUBLIC_KEY_PREFIX: &str = "muru";
+
+const PUBLIC_KEY_HRP: Hrp = Hrp::parse_unchecked(PUBLIC_KEY_PREFIX);
+
+impl PublicKey {
+    /// Parses a bech32m encoded key, see [`PublicKey`]'s `Display`.
+    fn from_bech32(s: &str) -> Result<Self, ParseError> {
+        let encoded = CheckedHrpstring::new::<Bech32m>(s).map_err(|e| match e {
+            CheckedHrpstringError::Checksum(_) => ParseError::Checksum,
+            _ => ParseError::Encoding,
+        })?;
+        if encoded.hrp() != PUBLIC_KEY_HRP {
+            return Err(ParseError::Prefix(encoded.hrp().to_lowercase()));
+        }
+
+        Self::from_bytes(&encoded.byte_iter().collect::<Vec<u8>>())
+    }
+}
+
+/// Public keys are shown bech32m encoded with a `muru` prefix, so a mistyped
+/// key fails its checksum instead of parsing as someone else's.
 impl fmt::Display for PublicKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", hex::encode(self.bytes()))
+        bech32::encode_lower_to_fmt::<Bech32m, _>(f, PUBLIC_KEY_HRP, &self.bytes()).map_err(|_| fmt::Error)
     }
 }
 
 impl fmt::Display for SecretKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let encoded = hex::encode(self.0.to_be_bytes());
+        let encoded = hex::encode(self.bytes());
 
         write!(f, "{}", encoded)
     }
@@ -161,39 +556,36 @@ impl fmt::Display for SecretKey {
 
 impl fmt::Display for Signature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", hex::encode(self.bytes()))
     }
 }
 
+/// Accepts the bech32m encoding, or hex as keys were shown before.
 impl FromStr for PublicKey {
-    type Err = PublicKeyParseError;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let decoded = hex::decode(s).map_err(|_| Self::Err {})?;
-
-        Self::from_bytes(&decoded)
+        // the prefix keeps bech32 from ever being valid hex
+        match s.chars().all(|c| c.is_ascii_hexdigit()) {
+            true => Self::from_bytes(&hex::decode(s)?),
+            false => Self::from_bech32(s),
+        }
     }
 }
 
 impl FromStr for SecretKey {
-    type Err = SecretKeyParseError;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let decoded = hex::decode(s).map_err(|_| Self::Err {})?;
-
-        k256::SecretKey::from_be_bytes(&decoded)
-            .map_err(|_| Self::Err {})
-            .map(Self)
+        Self::from_bytes(&hex::decode(s)?)
     }
 }
 
 impl FromStr for Signature {
-    type Err = SignatureParseError;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        ecdsa::Signature::from_str(s)
-            .map_err(|_| Self::Err {})
-            .map(Self)
+        Self::from_bytes(&hex::decode(s)?)
     }
 }
 
@@ -202,7 +594,12 @@ impl Serialize for PublicKey {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&self.to_string())
+        // stays hex, so stored data and older clients can still read it
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(self.bytes()))
+        } else {
+            serializer.serialize_bytes(&self.bytes())
+        }
     }
 }
 
@@ -211,7 +608,11 @@ impl Serialize for SecretKey {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&self.to_string())
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_string())
+        } else {
+            serializer.serialize_bytes(&self.bytes())
+        }
     }
 }
 
@@ -219,7 +620,7 @@ impl<'de> Visitor<'de> for PublicKeyVisitor {
     type Value = PublicKey;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("secp256k1 public key")
+        formatter.write_str("a public key")
     }
 
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
@@ -227,7 +628,15 @@ impl<'de> Visitor<'de> for PublicKeyVisitor {
         E: de::Error,
     {
         PublicKey::from_str(value)
-            .map_err(|_| E::custom(format!("failed to parse public key: {}", value)))
+            .map_err(|e| E::custom(format!("failed to parse public key {}: {}", value, e)))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        PublicKey::from_bytes(value)
+            .map_err(|e| E::custom(format!("failed to parse public key: {}", e)))
     }
 }
 
@@ -236,7 +645,48 @@ impl<'de> Deserialize<'de> for PublicKey {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_str(PublicKeyVisitor)
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(PublicKeyVisitor)
+        } else {
+            deserializer.deserialize_bytes(PublicKeyVisitor)
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for SecretKeyVisitor {
+    type Value = SecretKey;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a secret key")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        SecretKey::from_str(value)
+            .map_err(|e| E::custom(format!("failed to parse secret key: {}", e)))
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        SecretKey::from_bytes(value)
+            .map_err(|e| E::custom(format!("failed to parse secret key: {}", e)))
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(SecretKeyVisitor)
+        } else {
+            deserializer.deserialize_bytes(SecretKeyVisitor)
+        }
     }
 }
 
@@ -256,6 +706,141 @@ mod tests {
         assert_eq!(public.bytes().len(), 33);
     }
 
+    #[wasm_bindgen_test]
+    fn test_public_key_encoding() {
+        let public = SecretKey::generate().public_key();
+        let encoded = public.to_string();
+        assert!(encoded.starts_with("muru1"));
+        assert_eq!(encoded.len(), 64);
+
+        assert_eq!(PublicKey::from_str(&encoded).unwrap(), public);
+        assert_eq!(PublicKey::from_str(&encoded.to_uppercase()).unwrap(), public);
+        // keys written down before still work
+        assert_eq!(PublicKey::from_str(&hex::encode(public.bytes())).unwrap(), public);
+
+        // serialized data stays readable by older versions
+        assert_eq!(serde_json::to_string(&public).unwrap(), format!("\"{}\"", hex::encode(public.bytes())));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_public_key_typos() {
+        let encoded = SecretKey::generate().public_key().to_string();
+        let charset = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+        // every single character typo is caught
+        for i in 5..encoded.len() {
+            for c in charset.chars().filter(|c| *c != encoded.as_bytes()[i] as char) {
+                let mut typo = encoded.clone();
+                typo.replace_range(i..i + 1, &c.to_string());
+                assert_eq!(PublicKey::from_str(&typo).unwrap_err(), ParseError::Checksum);
+            }
+        }
+
+        // and so is swapping neighbours
+        for i in 5..encoded.len() - 1 {
+            let mut swapped = encoded.clone().into_bytes();
+            swapped.swap(i, i + 1);
+            if swapped != encoded.as_bytes() {
+                assert_eq!(PublicKey::from_str(&String::from_utf8(swapped).unwrap()).unwrap_err(), ParseError::Checksum);
+            }
+        }
+
+        assert_eq!(PublicKey::from_str(&encoded[..encoded.len() - 1]).unwrap_err(), ParseError::Checksum);
+        assert_eq!(PublicKey::from_str(&encoded.replacen('q', "b", 1)).unwrap_err(), ParseError::Encoding);
+        assert_eq!(PublicKey::from_str(&encoded.replacen('m', "M", 1)).unwrap_err(), ParseError::Encoding);
+
+        let other = bech32::encode::<Bech32m>(Hrp::parse("nope").unwrap(), &[2; 33]).unwrap();
+        assert_eq!(PublicKey::from_str(&other).unwrap_err(), ParseError::Prefix("nope".to_string()));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_parse_errors() {
+        assert!(matches!("abc".parse::<PublicKey>(), Err(ParseError::Hex(_))));
+        assert_eq!("00".parse::<PublicKey>().unwrap_err(), ParseError::Length { expected: 33, actual: 1 });
+        assert_eq!(PublicKey::from_bytes(&[5; 33]).unwrap_err(), ParseError::InvalidPoint);
+
+        assert!(matches!("0011".parse::<SecretKey>(), Err(ParseError::Length { expected: 32, actual: 2 })));
+        assert!(matches!(SecretKey::from_bytes(&[0; 32]), Err(ParseError::InvalidScalar)));
+        assert!(matches!(SecretKey::from_bytes(&[0xff; 32]), Err(ParseError::InvalidScalar)));
+
+        assert_eq!(Signature::from_bytes(&[1; 10]).unwrap_err(), ParseError::Length { expected: 64, actual: 10 });
+        assert_eq!(Signature::from_bytes(&[0; 64]).unwrap_err(), ParseError::InvalidSignature);
+
+        let secret = SecretKey::generate();
+        assert!(SecretKey::from_str(&secret.to_string()).unwrap() == secret);
+        let signature = secret.sign(&crate::handshake::Challenge::new());
+        assert_eq!(Signature::from_str(&hex::encode(signature.bytes())).unwrap().bytes(), signature.bytes());
+    }
+
+    // cheap parameters so the tests run quickly
+    const TEST_KDF: KdfParams = KdfParams { memory: 64, iterations: 1, parallelism: 1 };
+
+    #[wasm_bindgen_test]
+    fn test_key_file() {
+        let secret = SecretKey::generate();
+
+        let key_file = secret.export_encrypted("correct horse");
+        assert!(!key_file.contains(&secret.to_string()));
+        assert!(SecretKey::import_encrypted(&key_file, "correct horse").unwrap() == secret);
+
+        // every export is salted differently
+        let other = secret.export_encrypted_with("correct horse", TEST_KDF);
+        assert_ne!(other, secret.export_encrypted_with("correct horse", TEST_KDF));
+        assert!(SecretKey::import_encrypted(&other, "correct horse").unwrap() == secret);
+        assert!(matches!(SecretKey::import_encrypted(&other, "wrong horse"), Err(KeyFileError::Passphrase)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_key_file_errors() {
+        let secret = SecretKey::generate();
+        let key_file: serde_json::Value = serde_json::from_str(&secret.export_encrypted_with("pass", TEST_KDF)).unwrap();
+
+        let import = |edit: fn(&mut serde_json::Value)| {
+            let mut key_file = key_file.clone();
+            edit(&mut key_file);
+            SecretKey::import_encrypted(&key_file.to_string(), "pass")
+        };
+
+        assert!(import(|_| ()).is_ok());
+        assert!(matches!(SecretKey::import_encrypted(&secret.to_string(), "pass"), Err(KeyFileError::Format)));
+        assert!(matches!(import(|f| f["version"] = 2.into()), Err(KeyFileError::UnsupportedVersion(2))));
+        assert!(matches!(import(|f| f["kdf"]["iterations"] = 0.into()), Err(KeyFileError::Parameters)));
+        assert!(matches!(import(|f| f["kdf"]["memory"] = (KdfParams::MAX_MEMORY + 1).into()), Err(KeyFileError::Parameters)));
+
+        // lowering the cost changes the derived key
+        assert!(matches!(import(|f| f["kdf"]["memory"] = 32.into()), Err(KeyFileError::Passphrase)));
+        assert!(matches!(import(|f| f["salt"] = hex::encode([0; 16]).into()), Err(KeyFileError::Passphrase)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_mnemonic() {
+        let secret = SecretKey::generate();
+        let mnemonic = secret.to_mnemonic();
+        assert_eq!(mnemonic.split(' ').count(), MNEMONIC_WORDS);
+        assert!(SecretKey::from_mnemonic(&mnemonic).unwrap() == secret);
+
+        // typing it back in sloppily still works
+        let sloppy = format!("  {}\n", mnemonic.to_uppercase().replace(' ', "   "));
+        assert!(SecretKey::from_mnemonic(&sloppy).unwrap() == secret);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_mnemonic_errors() {
+        let mnemonic = SecretKey::generate().to_mnemonic();
+        let mut words: Vec<&str> = mnemonic.split(' ').collect();
+
+        assert_eq!(SecretKey::from_mnemonic(&words[1..].join(" ")).err(), Some(ParseError::WordCount { expected: 24, actual: 23 }));
+
+        words[3] = "muruchat";
+        assert_eq!(SecretKey::from_mnemonic(&words.join(" ")).err(), Some(ParseError::UnknownWord("muruchat".to_string())));
+
+        // swapping two words breaks the checksum
+        let mnemonic = SecretKey::from_bytes(&[1; 32]).unwrap().to_mnemonic();
+        let mut words: Vec<&str> = mnemonic.split(' ').collect();
+        words.swap(0, 23);
+        assert_eq!(SecretKey::from_mnemonic(&words.join(" ")).err(), Some(ParseError::Checksum));
+    }
+
     #[wasm_bindgen_test]
     fn test_serialize_deserialize() {
         let secret = SecretKey::generate();
@@ -266,4 +851,110 @@ mod tests {
 
         assert_eq!(de_ser, public);
     }
+
+    #[wasm_bindgen_test]
+    fn test_binary_serialize_deserialize() {
+        let secret = SecretKey::generate();
+        let public = secret.public_key();
+        let signature = secret.sign(&crate::handshake::Challenge::new());
+
+        let bytes = postcard::to_allocvec(&(&public, &secret, &signature)).unwrap();
+        // raw keys and signature with a length byte each, rather than hex
+        assert_eq!(bytes.len(), 1 + 33 + 1 + 32 + 1 + 64);
+
+        let (de_public, de_secret, de_signature): (PublicKey, SecretKey, Signature) = postcard::from_bytes(&bytes).unwrap();
+
+        assert_eq!(de_public, public);
+        assert!(de_secret == secret);
+        assert_eq!(de_signature.bytes(), signature.bytes());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_diffie_hellman_agrees() {
+        for suite in Suite::ALL {
+            let alice = SecretKey::generate_with(suite);
+            let bob = SecretKey::generate_with(suite);
+
+            assert!(alice.diffie_hellman(&bob.public_key()).is_some());
+            assert_eq!(
+                alice.diffie_hellman(&bob.public_key()),
+                bob.diffie_hellman(&alice.public_key()),
+            );
+            assert_ne!(
+                alice.diffie_hellman(&bob.public_key()),
+                alice.diffie_hellman(&SecretKey::generate_with(suite).public_key()),
+            );
+        }
+
+        let other = SecretKey::generate_with(Suite::Ed25519).public_key();
+        assert_eq!(SecretKey::generate().diffie_hellman(&other), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_suite_encodings() {
+        let secret = SecretKey::generate_with(Suite::Ed25519);
+        let public = secret.public_key();
+        let signature = secret.sign(&crate::handshake::Challenge::new());
+        assert_eq!((secret.suite(), public.suite()), (Suite::Ed25519, Suite::Ed25519));
+
+        // the suite is in the first byte
+        assert_eq!(public.bytes()[0], ED25519_TAG);
+        assert_eq!(secret.bytes().len(), 33);
+        assert_eq!(signature.bytes().len(), 65);
+
+        assert_eq!(PublicKey::from_bytes(&public.bytes()).unwrap(), public);
+        assert_eq!(PublicKey::from_str(&public.to_string()).unwrap(), public);
+        assert!(SecretKey::from_str(&secret.to_string()).unwrap() == secret);
+        assert_eq!(Signature::from_str(&signature.to_string()).unwrap().bytes(), signature.bytes());
+
+        let bytes = postcard::to_allocvec(&(&public, &secret, &signature)).unwrap();
+        let (de_public, de_secret, de_signature): (PublicKey, SecretKey, Signature) = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(de_public, public);
+        assert!(de_secret == secret);
+        assert_eq!(de_signature.bytes(), signature.bytes());
+
+        // small order points are refused
+        let mut identity = [0; 33];
+        identity[0] = ED25519_TAG;
+        identity[1] = 1;
+        assert_eq!(PublicKey::from_bytes(&identity).unwrap_err(), ParseError::InvalidPoint);
+        assert!(matches!(SecretKey::from_bytes(&[ED25519_TAG; 20]), Err(ParseError::Length { expected: 32, actual: 20 })));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_signatures_stay_in_their_suite() {
+        let challenge = crate::handshake::Challenge::new();
+        let secp256k1 = SecretKey::generate_with(Suite::Secp256k1);
+        let ed25519 = SecretKey::generate_with(Suite::Ed25519);
+
+        assert!(ed25519.public_key().verify(&challenge, &ed25519.sign(&challenge)));
+        assert!(!ed25519.public_key().verify(&challenge, &secp256k1.sign(&challenge)));
+        assert!(!secp256k1.public_key().verify(&challenge, &ed25519.sign(&challenge)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_ed25519_mnemonic() {
+        let secret = SecretKey::generate_with(Suite::Ed25519);
+        let mnemonic = secret.to_mnemonic();
+        assert!(mnemonic.starts_with("ed25519 "));
+        assert_eq!(mnemonic.split(' ').count(), MNEMONIC_WORDS + 1);
+        assert!(SecretKey::from_mnemonic(&mnemonic.to_uppercase()).unwrap() == secret);
+
+        // the same words without the marker are a different, secp256k1 key
+        let secp256k1 = SecretKey::from_mnemonic(&mnemonic["ed25519 ".len()..]).unwrap();
+        assert_eq!(secp256k1.suite(), Suite::Secp256k1);
+        assert_eq!(SecretKey::from_mnemonic("ed25519").err(), Some(ParseError::WordCount { expected: 24, actual: 0 }));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_encrypt_decrypt() {
+        for suite in Suite::ALL {
+            let secret = SecretKey::generate_with(suite);
+            let ciphertext = secret.public_key().encrypt(b"hello");
+
+            assert_eq!(secret.decrypt(&ciphertext).unwrap(), b"hello");
+            assert_eq!(SecretKey::generate_with(suite).decrypt(&ciphertext), None);
+            assert_eq!(secret.decrypt(&ciphertext[..20]), None);
+        }
+    }
 }
@@ -0,0 +1,268 @@
+//! The elliptic curve schemes keys can use.
+//!
+//! A [`CipherSuite`] provides everything the protocol needs from a curve:
+//! signatures, Diffie-Hellman and public key encryption. [`PublicKey`],
+//! [`SecretKey`] and [`Signature`] carry which [`Suite`] they belong to, so
+//! the rest of the library works with either without knowing which.
+//!
+//! Adding a suite takes a variant of [`Suite`], one in each of the key enums
+//! in [`pki`](super), an arm in its `with_suite!` and a `variant!` line for
+//! the suite's tag. Everything else is written against [`CipherSuite`].
+//!
+//! [`PublicKey`]: super::PublicKey
+//! [`SecretKey`]: super::SecretKey
+//! [`Signature`]: super::Signature
+
+use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use hkdf::Hkdf;
+use k256::ecdsa::{self, signature::{Signer, Verifier}};
+use rand::Rng;
+use sha2::Sha256;
+
+use crate::error::ParseError;
+
+const X25519_ECIES_INFO: &[u8] = b"muruchat x25519 ecies";
+
+/// The operations the protocol needs from a curve. Encodings are the curve's
+/// own, without the suite tag [`PublicKey`](super::PublicKey) and friends add.
+pub trait CipherSuite {
+    type PublicKey: Clone;
+    type SecretKey: Clone;
+    type Signature: Clone;
+
+    const SUITE: Suite;
+
+    fn generate() -> Self::SecretKey;
+    fn public_key(secret_key: &Self::SecretKey) -> Self::PublicKey;
+
+    fn public_key_bytes(public_key: &Self::PublicKey) -> Vec<u8>;
+    fn public_key_from_bytes(bytes: &[u8]) -> Result<Self::PublicKey, ParseError>;
+    fn secret_key_bytes(secret_key: &Self::SecretKey) -> [u8; 32];
+    fn secret_key_from_bytes(bytes: &[u8]) -> Result<Self::SecretKey, ParseError>;
+    fn signature_bytes(signature: &Self::Signature) -> [u8; 64];
+    fn signature_from_bytes(bytes: &[u8]) -> Result<Self::Signature, ParseError>;
+
+    fn sign(secret_key: &Self::SecretKey, message: &[u8]) -> Self::Signature;
+    fn verify(public_key: &Self::PublicKey, message: &[u8], signature: &Self::Signature) -> bool;
+
+    /// Raw shared key material, `None` if `public_key` leads to a degenerate
+    /// result.
+    fn diffie_hellman(secret_key: &Self::SecretKey, public_key: &Self::PublicKey) -> Option<[u8; 32]>;
+    /// Encrypts to a public key with a fresh ephemeral key.
+    fn encrypt(public_key: &Self::PublicKey, plaintext: &[u8]) -> Vec<u8>;
+    fn decrypt(secret_key: &Self::SecretKey, ciphertext: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Which [`CipherSuite`] a key belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Suite {
+    Secp256k1,
+    Ed25519,
+}
+
+impl Suite {
+    pub const ALL: [Suite; 2] = [Suite::Secp256k1, Suite::Ed25519];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Secp256k1 => "secp256k1",
+            Self::Ed25519 => "ed25519",
+        }
+    }
+}
+
+/// ECDSA over secp256k1 for signatures, and ECIES on the same curve.
+pub struct Secp256k1;
+
+impl CipherSuite for Secp256k1 {
+    type PublicKey = k256::PublicKey;
+    type SecretKey = k256::SecretKey;
+    type Signature = ecdsa::Signature;
+
+    const SUITE: Suite = Suite::Secp256k1;
+
+    fn generate() -> Self::SecretKey {
+        k256::SecretKey::random(rand::thread_rng())
+    }
+
+    fn public_key(secret_key: &Self::SecretKey) -> Self::PublicKey {
+        secret_key.public_key()
+    }
+
+    fn public_key_bytes(public_key: &Self::PublicKey) -> Vec<u8> {
+        use k256::elliptic_curve::sec1::ToEncodedPoint;
+
+        public_key.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    /// Parses a SEC1 encoded key, compressed or not.
+    fn public_key_from_bytes(bytes: &[u8]) -> Result<Self::PublicKey, ParseError> {
+        if bytes.len() != 33 && bytes.len() != 65 {
+            return Err(ParseError::Length { expected: 33, actual: bytes.len() });
+        }
+
+        k256::PublicKey::from_sec1_bytes(bytes).map_err(|_| ParseError::InvalidPoint)
+    }
+
+    fn secret_key_bytes(secret_key: &Self::SecretKey) -> [u8; 32] {
+        secret_key.to_be_bytes().into()
+    }
+
+    fn secret_key_from_bytes(bytes: &[u8]) -> Result<Self::SecretKey, ParseError> {
+        ParseError::check_length(bytes, 32)?;
+
+        k256::SecretKey::from_be_bytes(bytes).map_err(|_| ParseError::InvalidScalar)
+    }
+
+    fn signature_bytes(signature: &Self::Signature) -> [u8; 64] {
+        use k256::ecdsa::signature::Signature;
+
+        signature.as_bytes().try_into().unwrap()
+    }
+
+    fn signature_from_bytes(bytes: &[u8]) -> Result<Self::Signature, ParseError> {
+        ParseError::check_length(bytes, 64)?;
+
+        ecdsa::signature::Signature::from_bytes(bytes).map_err(|_| ParseError::InvalidSignature)
+    }
+
+    fn sign(secret_key: &Self::SecretKey, message: &[u8]) -> Self::Signature {
+        ecdsa::SigningKey::from(secret_key).sign(message)
+    }
+
+    fn verify(public_key: &Self::PublicKey, message: &[u8], signature: &Self::Signature) -> bool {
+        ecdsa::VerifyingKey::from(public_key).verify(message, signature).is_ok()
+    }
+
+    fn diffie_hellman(secret_key: &Self::SecretKey, public_key: &Self::PublicKey) -> Option<[u8; 32]> {
+        let shared = k256::ecdh::diffie_hellman(secret_key.to_nonzero_scalar(), public_key.as_affine());
+
+        Some((*shared.raw_secret_bytes()).into())
+    }
+
+    fn encrypt(public_key: &Self::PublicKey, plaintext: &[u8]) -> Vec<u8> {
+        ecies::encrypt(&Self::public_key_bytes(public_key), plaintext).expect("public key is a valid secp256k1 point")
+    }
+
+    fn decrypt(secret_key: &Self::SecretKey, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        ecies::decrypt(&Self::secret_key_bytes(secret_key), ciphertext).ok()
+    }
+}
+
+/// Ed25519 for signatures, with the same keys converted to X25519 for
+/// Diffie-Hellman and encryption.
+pub struct Ed25519;
+
+impl CipherSuite for Ed25519 {
+    type PublicKey = ed25519_dalek::VerifyingKey;
+    type SecretKey = ed25519_dalek::SigningKey;
+    type Signature = ed25519_dalek::Signature;
+
+    const SUITE: Suite = Suite::Ed25519;
+
+    fn generate() -> Self::SecretKey {
+        ed25519_dalek::SigningKey::generate(&mut rand::thread_rng())
+    }
+
+    fn public_key(secret_key: &Self::SecretKey) -> Self::PublicKey {
+        secret_key.verifying_key()
+    }
+
+    fn public_key_bytes(public_key: &Self::PublicKey) -> Vec<u8> {
+        public_key.as_bytes().to_vec()
+    }
+
+    /// Small order points are refused, they would let a key sign for anything
+    /// and give away every Diffie-Hellman result.
+    fn public_key_from_bytes(bytes: &[u8]) -> Result<Self::PublicKey, ParseError> {
+        ParseError::check_length(bytes, 32)?;
+
+        match ed25519_dalek::VerifyingKey::from_bytes(bytes.try_into().unwrap()) {
+            Ok(key) if !key.is_weak() => Ok(key),
+            _ => Err(ParseError::InvalidPoint),
+        }
+    }
+
+    fn secret_key_bytes(secret_key: &Self::SecretKey) -> [u8; 32] {
+        secret_key.to_bytes()
+    }
+
+    fn secret_key_from_bytes(bytes: &[u8]) -> Result<Self::SecretKey, ParseError> {
+        ParseError::check_length(bytes, 32)?;
+
+        Ok(ed25519_dalek::SigningKey::from_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn signature_bytes(signature: &Self::Signature) -> [u8; 64] {
+        signature.to_bytes()
+    }
+
+    fn signature_from_bytes(bytes: &[u8]) -> Result<Self::Signature, ParseError> {
+        ParseError::check_length(bytes, 64)?;
+
+        ed25519_dalek::Signature::from_slice(bytes).map_err(|_| ParseError::InvalidSignature)
+    }
+
+    fn sign(secret_key: &Self::SecretKey, message: &[u8]) -> Self::Signature {
+        use ed25519_dalek::Signer;
+
+        secret_key.sign(message)
+    }
+
+    fn verify(public_key: &Self::PublicKey, message: &[u8], signature: &Self::Signature) -> bool {
+        public_key.verify_strict(message, signature).is_ok()
+    }
+
+    fn diffie_hellman(secret_key: &Self::SecretKey, public_key: &Self::PublicKey) -> Option<[u8; 32]> {
+        x25519(public_key.to_montgomery(), secret_key.to_scalar_bytes())
+    }
+
+    /// An ephemeral X25519 key, HKDF-SHA256 and AES-256-GCM. The output is the
+    /// ephemeral public key, the nonce and the ciphertext.
+    fn encrypt(public_key: &Self::PublicKey, plaintext: &[u8]) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let ephemeral: [u8; 32] = rng.gen();
+        let nonce: [u8; 12] = rng.gen();
+
+        let ephemeral_key = MontgomeryPoint::mul_base_clamped(ephemeral);
+        let shared = x25519(public_key.to_montgomery(), ephemeral).expect("public key isn't a small order point");
+        let ciphertext = Aes256Gcm::new(&ecies_key(&shared, &ephemeral_key, public_key).into())
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("encryption doesn't fail");
+
+        [ephemeral_key.as_bytes().as_slice(), &nonce, &ciphertext].concat()
+    }
+
+    fn decrypt(secret_key: &Self::SecretKey, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.len() < 32 + 12 {
+            return None;
+        }
+        let (ephemeral_key, rest) = ciphertext.split_at(32);
+        let (nonce, ciphertext) = rest.split_at(12);
+
+        let ephemeral_key = MontgomeryPoint(ephemeral_key.try_into().unwrap());
+        let shared = x25519(ephemeral_key, secret_key.to_scalar_bytes())?;
+        Aes256Gcm::new(&ecies_key(&shared, &ephemeral_key, &secret_key.verifying_key()).into())
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()
+    }
+}
+
+// an all zero result means the point was of small order, which only a
+// malicious party would send
+fn x25519(point: MontgomeryPoint, scalar: [u8; 32]) -> Option<[u8; 32]> {
+    match point.mul_clamped(scalar).to_bytes() {
+        shared if shared == [0; 32] => None,
+        shared => Some(shared),
+    }
+}
+
+// binds both public keys, so the ciphertext can't be replayed to another key
+fn ecies_key(shared: &[u8; 32], ephemeral_key: &MontgomeryPoint, public_key: &ed25519_dalek::VerifyingKey) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha256>::new(None, shared)
+        .expand(&[X25519_ECIES_INFO, ephemeral_key.as_bytes(), public_key.as_bytes()].concat(), &mut key)
+        .expect("32 bytes is a valid hkdf output length");
+    key
+}
@@ -0,0 +1,543 @@
+//! X3DH prekey bundles, so a session can be started with a contact who is
+//! offline.
+//!
+//! A user uploads a [`PrekeyUpload`] to the worker ahead of time. Anyone who
+//! wants to talk to them fetches a [`PrekeyBundle`], which hands out at most one
+//! of the one-time prekeys, and runs [`initiate`] to derive a [`Session`]. The
+//! resulting [`InitialKeys`] travel with the first message so the recipient can
+//! derive the same session with [`Prekeys::accept`], which only counts once the
+//! first message decrypts with [`Prekeys::decrypt_first`].
+//!
+//! See <https://signal.org/docs/specifications/x3dh/>.
+
+use std::{error::Error, fmt};
+
+use hkdf::Hkdf;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+
+use crate::{
+    pki::{PublicKey, SecretKey, Signature, Suite},
+    session::{RatchetMessage, Session, SessionError},
+    signing::{Encoder, Signable},
+};
+
+const X3DH_INFO: &[u8] = b"muruchat x3dh";
+
+#[derive(Debug)]
+pub enum PrekeyError {
+    /// A prekey was not signed by the identity key of the bundle.
+    InvalidSignature,
+    /// The signed prekey used by the initiator is not the current one.
+    UnknownSignedPrekey,
+    /// The one-time prekey used by the initiator doesn't exist or was already used.
+    UnknownOneTimePrekey,
+    /// The keys are from different cipher suites.
+    SuiteMismatch,
+    /// The first message doesn't decrypt with the accepted session.
+    FirstMessage(SessionError),
+}
+
+impl fmt::Display for PrekeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => f.write_str("prekey signature is invalid"),
+            Self::UnknownSignedPrekey => f.write_str("unknown signed prekey"),
+            Self::UnknownOneTimePrekey => f.write_str("unknown or already used one-time prekey"),
+            Self::SuiteMismatch => f.write_str("keys are from different cipher suites"),
+            Self::FirstMessage(e) => write!(f, "first message doesn't decrypt: {}", e),
+        }
+    }
+}
+
+impl Error for PrekeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::FirstMessage(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A prekey signed by an identity key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPrekey {
+    pub public_key: PublicKey,
+    signature: Signature,
+}
+
+impl SignedPrekey {
+    fn new(identity: &SecretKey, prekey: &PublicKey) -> Self {
+        Self {
+            public_key: prekey.clone(),
+            signature: identity.sign(&Prekey(prekey)),
+        }
+    }
+
+    pub fn verify(&self, identity_key: &PublicKey) -> bool {
+        identity_key.verify(&Prekey(&self.public_key), &self.signature)
+    }
+}
+
+/// What an identity key signs to vouch for one of its prekeys.
+struct Prekey<'a>(&'a PublicKey);
+
+impl Signable for Prekey<'_> {
+    const CONTEXT: &'static str = "prekey";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bytes(&self.0.bytes());
+    }
+}
+
+/// Everything a user publishes to the worker so others can start sessions with them.
+///
+/// The whole upload is signed along with when it was made, so the worker can
+/// refuse one that isn't newer than what it has. Otherwise anyone who saw an
+/// old upload could replay it and bring back one-time prekeys already used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrekeyUpload {
+    pub identity_key: PublicKey,
+    pub signed_prekey: SignedPrekey,
+    pub one_time_prekeys: Vec<SignedPrekey>,
+    /// Milliseconds since the unix epoch.
+    pub created_at: u64,
+    signature: Signature,
+}
+
+impl PrekeyUpload {
+    /// Only holds as uploaded, taking bundles changes what was signed.
+    pub fn verify(&self) -> bool {
+        self.signed_prekey.verify(&self.identity_key)
+            && self.one_time_prekeys.iter().all(|k| k.verify(&self.identity_key))
+            && self.identity_key.verify(&self.signed_fields(), &self.signature)
+    }
+
+    /// Whether this upload may replace `stored`.
+    pub fn is_newer_than(&self, stored: &PrekeyUpload) -> bool {
+        self.created_at > stored.created_at
+    }
+
+    fn signed_fields(&self) -> UploadFields<'_> {
+        UploadFields {
+            identity_key: &self.identity_key,
+            signed_prekey: &self.signed_prekey.public_key,
+            one_time_prekeys: self.one_time_prekeys.iter().map(|k| &k.public_key).collect(),
+            created_at: self.created_at,
+        }
+    }
+
+    /// Hands out a bundle, consuming one of the one-time prekeys if any are left.
+    pub fn take_bundle(&mut self) -> PrekeyBundle {
+        PrekeyBundle {
+            identity_key: self.identity_key.clone(),
+            signed_prekey: self.signed_prekey.clone(),
+            one_time_prekey: self.one_time_prekeys.pop(),
+        }
+    }
+}
+
+/// Everything in an upload except its signature.
+struct UploadFields<'a> {
+    identity_key: &'a PublicKey,
+    signed_prekey: &'a PublicKey,
+    one_time_prekeys: Vec<&'a PublicKey>,
+    created_at: u64,
+}
+
+impl Signable for UploadFields<'_> {
+    const CONTEXT: &'static str = "prekey upload";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.identity_key.bytes())
+            .bytes(&self.signed_prekey.bytes())
+            .u32(self.one_time_prekeys.len() as u32);
+
+        for prekey in &self.one_time_prekeys {
+            encoder.bytes(&prekey.bytes());
+        }
+
+        encoder.u64(self.created_at);
+    }
+}
+
+/// What an initiator receives from the worker for a given public key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrekeyBundle {
+    pub identity_key: PublicKey,
+    pub signed_prekey: SignedPrekey,
+    pub one_time_prekey: Option<SignedPrekey>,
+}
+
+impl PrekeyBundle {
+    pub fn verify(&self) -> bool {
+        self.signed_prekey.verify(&self.identity_key)
+            && self.one_time_prekey.iter().all(|k| k.verify(&self.identity_key))
+    }
+}
+
+/// The public keys an initiator used, sent alongside their first message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitialKeys {
+    pub identity_key: PublicKey,
+    pub ephemeral_key: PublicKey,
+    pub signed_prekey: PublicKey,
+    pub one_time_prekey: Option<PublicKey>,
+}
+
+/// A session derived by [`Prekeys::accept`], whose one-time prekey is still
+/// kept until [`Prekeys::decrypt_first`] shows the initiator holds it.
+pub struct Accepted {
+    pub session: Session,
+    one_time_prekey: Option<PublicKey>,
+}
+
+/// The secret halves of a user's prekeys, kept on their device.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Prekeys {
+    signed_prekey: SecretKey,
+    one_time_prekeys: Vec<SecretKey>,
+}
+
+impl Prekeys {
+    /// secp256k1 prekeys.
+    pub fn generate(one_time_prekeys: usize) -> Self {
+        Self::generate_with(Suite::Secp256k1, one_time_prekeys)
+    }
+
+    /// Prekeys in the same suite as the identity they are uploaded for.
+    pub fn generate_with(suite: Suite, one_time_prekeys: usize) -> Self {
+        Self {
+            signed_prekey: SecretKey::generate_with(suite),
+            one_time_prekeys: (0..one_time_prekeys).map(|_| SecretKey::generate_with(suite)).collect(),
+        }
+    }
+
+    /// `created_at` has to increase with every upload, the worker keeps the
+    /// newest.
+    pub fn upload(&self, identity: &SecretKey, created_at: u64) -> PrekeyUpload {
+        let identity_key = identity.public_key();
+        let signed_prekey = SignedPrekey::new(identity, &self.signed_prekey.public_key());
+        let one_time_prekeys: Vec<SignedPrekey> = self
+            .one_time_prekeys
+            .iter()
+            .map(|k| SignedPrekey::new(identity, &k.public_key()))
+            .collect();
+
+        let signature = identity.sign(&UploadFields {
+            identity_key: &identity_key,
+            signed_prekey: &signed_prekey.public_key,
+            one_time_prekeys: one_time_prekeys.iter().map(|k| &k.public_key).collect(),
+            created_at,
+        });
+
+        PrekeyUpload {
+            identity_key,
+            signed_prekey,
+            one_time_prekeys,
+            created_at,
+            signature,
+        }
+    }
+
+    /// Derives the responder side of a session started with [`initiate`].
+    /// Anyone can send well formed initial keys, so nothing is used up until
+    /// the first message decrypts.
+    pub fn accept(&self, identity: &SecretKey, initial: &InitialKeys) -> Result<Accepted, PrekeyError> {
+        if initial.signed_prekey != self.signed_prekey.public_key() {
+            return Err(PrekeyError::UnknownSignedPrekey);
+        }
+
+        let one_time_prekey = match &initial.one_time_prekey {
+            Some(public_key) => Some(
+                self.one_time_prekeys
+                    .iter()
+                    .position(|k| &k.public_key() == public_key)
+                    .ok_or(PrekeyError::UnknownOneTimePrekey)?,
+            ),
+            None => None,
+        };
+
+        let mut dh = [
+            self.signed_prekey.diffie_hellman(&initial.identity_key),
+            identity.diffie_hellman(&initial.ephemeral_key),
+            self.signed_prekey.diffie_hellman(&initial.ephemeral_key),
+        ].to_vec();
+        if let Some(i) = one_time_prekey {
+            dh.push(self.one_time_prekeys[i].diffie_hellman(&initial.ephemeral_key));
+        }
+
+        let associated_data = [initial.identity_key.bytes(), identity.public_key().bytes()].concat();
+        let session = Session::responder(kdf(dh)?, associated_data, self.signed_prekey.suite());
+
+        Ok(Accepted { session, one_time_prekey: initial.one_time_prekey.clone() })
+    }
+
+    /// Decrypts the first message of an accepted session, and only then uses
+    /// up its one-time prekey so the same initial keys can't be accepted
+    /// twice.
+    pub fn decrypt_first(&mut self, accepted: Accepted, first: &RatchetMessage) -> Result<(Session, Vec<u8>), PrekeyError> {
+        let Accepted { mut session, one_time_prekey } = accepted;
+
+        // another session may have used it up since it was accepted
+        let one_time_prekey = match one_time_prekey {
+            Some(public_key) => Some(
+                self.one_time_prekeys
+                    .iter()
+                    .position(|k| k.public_key() == public_key)
+                    .ok_or(PrekeyError::UnknownOneTimePrekey)?,
+            ),
+            None => None,
+        };
+
+        let plaintext = session.decrypt(first).map_err(PrekeyError::FirstMessage)?;
+        if let Some(i) = one_time_prekey {
+            self.one_time_prekeys.remove(i);
+        }
+
+        Ok((session, plaintext))
+    }
+}
+
+/// Starts a session with the owner of `bundle`, who may be offline.
+pub fn initiate(identity: &SecretKey, bundle: &PrekeyBundle) -> Result<(Session, InitialKeys), PrekeyError> {
+    if !bundle.verify() {
+        return Err(PrekeyError::InvalidSignature);
+    }
+
+    let ephemeral = SecretKey::generate_with(identity.suite());
+    let signed_prekey = &bundle.signed_prekey.public_key;
+
+    let mut dh = [
+        identity.diffie_hellman(signed_prekey),
+        ephemeral.diffie_hellman(&bundle.identity_key),
+        ephemeral.diffie_hellman(signed_prekey),
+    ].to_vec();
+    if let Some(k) = &bundle.one_time_prekey {
+        dh.push(ephemeral.diffie_hellman(&k.public_key));
+    }
+
+    let associated_data = [identity.public_key().bytes(), bundle.identity_key.bytes()].concat();
+    let session = Session::initiator(kdf(dh)?, associated_data, signed_prekey.suite());
+
+    let initial = InitialKeys {
+        identity_key: identity.public_key(),
+        ephemeral_key: ephemeral.public_key(),
+        signed_prekey: signed_prekey.clone(),
+        one_time_prekey: bundle.one_time_prekey.as_ref().map(|k| k.public_key.clone()),
+    };
+
+    Ok((session, initial))
+}
+
+// every exchange is `None` unless both sides' keys are from the same suite
+fn kdf(dh: Vec<Option<[u8; 32]>>) -> Result<[u8; 32], PrekeyError> {
+    let dh: Vec<[u8; 32]> = dh.into_iter().collect::<Option<_>>().ok_or(PrekeyError::SuiteMismatch)?;
+    // 32 0xFF bytes are prepended for domain separation from other uses of the curve
+    let ikm = [[0xFF; 32].as_slice(), &dh.concat()].concat();
+
+    let mut out = [0; 32];
+    Hkdf::<Sha256>::new(Some(&[0; 32]), &ikm)
+        .expand(X3DH_INFO, &mut out)
+        .expect("32 bytes is a valid hkdf output length");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_upload_verify() {
+        let identity = SecretKey::generate();
+        let upload = Prekeys::generate(3).upload(&identity, 1000);
+
+        assert!(upload.verify());
+
+        let mut forged = upload.clone();
+        forged.one_time_prekeys[1].public_key = SecretKey::generate().public_key();
+        assert!(!forged.verify());
+
+        let mut forged = upload.clone();
+        forged.identity_key = SecretKey::generate().public_key();
+        assert!(!forged.verify());
+
+        // an old upload can't be passed off as a newer one
+        let mut replayed = upload.clone();
+        replayed.created_at += 1;
+        assert!(!replayed.verify());
+
+        // nor can used one-time prekeys be added back to a newer one
+        let mut newer = Prekeys::generate(1).upload(&identity, 2000);
+        assert!(newer.is_newer_than(&upload) && !upload.is_newer_than(&newer) && !newer.is_newer_than(&newer));
+        newer.one_time_prekeys.extend(upload.one_time_prekeys);
+        assert!(!newer.verify());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_take_bundle_consumes_one_time_prekeys() {
+        let identity = SecretKey::generate();
+        let mut upload = Prekeys::generate(2).upload(&identity, 1000);
+
+        let first = upload.take_bundle();
+        let second = upload.take_bundle();
+        let third = upload.take_bundle();
+
+        assert!(first.verify() && second.verify() && third.verify());
+        assert_ne!(first.one_time_prekey.unwrap().public_key, second.one_time_prekey.unwrap().public_key);
+        assert!(third.one_time_prekey.is_none());
+    }
+
+    fn converse(alice: &SecretKey, bob: &SecretKey, prekeys: &mut Prekeys, bundle: &PrekeyBundle) {
+        let (mut alice_session, initial) = initiate(alice, bundle).unwrap();
+        let first = alice_session.encrypt(b"hi bob").unwrap();
+
+        let accepted = prekeys.accept(bob, &initial).unwrap();
+        let (mut bob_session, plaintext) = prekeys.decrypt_first(accepted, &first).unwrap();
+        assert_eq!(plaintext, b"hi bob");
+
+        let reply = bob_session.encrypt(b"hi alice").unwrap();
+        assert_eq!(alice_session.decrypt(&reply).unwrap(), b"hi alice");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_initiate_accept() {
+        for suite in Suite::ALL {
+            let alice = SecretKey::generate_with(suite);
+            let bob = SecretKey::generate_with(suite);
+
+            let mut prekeys = Prekeys::generate_with(suite, 1);
+            let mut upload = prekeys.upload(&bob, 1000);
+            assert!(upload.verify());
+
+            // with a one-time prekey, then once they have run out
+            converse(&alice, &bob, &mut prekeys, &upload.take_bundle());
+            converse(&alice, &bob, &mut prekeys, &upload.take_bundle());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_initiate_suite_mismatch() {
+        let alice = SecretKey::generate_with(Suite::Ed25519);
+        let bob = SecretKey::generate_with(Suite::Secp256k1);
+
+        let bundle = Prekeys::generate(1).upload(&bob, 1000).take_bundle();
+        assert!(matches!(initiate(&alice, &bundle), Err(PrekeyError::SuiteMismatch)));
+
+        // prekeys from another suite than the identity are no use either
+        let prekeys = Prekeys::generate_with(Suite::Ed25519, 0);
+        let bundle = prekeys.upload(&bob, 1000).take_bundle();
+        assert!(bundle.verify());
+        assert!(matches!(initiate(&alice, &bundle), Err(PrekeyError::SuiteMismatch)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_one_time_prekey_single_use() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+
+        let mut prekeys = Prekeys::generate(1);
+        let bundle = prekeys.upload(&bob, 1000).take_bundle();
+
+        let (mut session, initial) = initiate(&alice, &bundle).unwrap();
+        let first = session.encrypt(b"hi bob").unwrap();
+
+        // accepting twice before either decrypts still only lets one through
+        let accepted = prekeys.accept(&bob, &initial).unwrap();
+        let again = prekeys.accept(&bob, &initial).unwrap();
+        assert!(prekeys.decrypt_first(accepted, &first).is_ok());
+        assert!(matches!(prekeys.decrypt_first(again, &first), Err(PrekeyError::UnknownOneTimePrekey)));
+        assert!(matches!(prekeys.accept(&bob, &initial), Err(PrekeyError::UnknownOneTimePrekey)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_failed_accept_keeps_one_time_prekey() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+
+        let mut prekeys = Prekeys::generate(1);
+        let bundle = prekeys.upload(&bob, 1000).take_bundle();
+        let (mut session, initial) = initiate(&alice, &bundle).unwrap();
+        let first = session.encrypt(b"hi bob").unwrap();
+
+        let mut mismatched = initial.clone();
+        mismatched.identity_key = SecretKey::generate_with(Suite::Ed25519).public_key();
+        assert!(matches!(prekeys.accept(&bob, &mismatched), Err(PrekeyError::SuiteMismatch)));
+        assert_eq!(prekeys.one_time_prekeys.len(), 1);
+
+        // the real initial message still goes through
+        let accepted = prekeys.accept(&bob, &initial).unwrap();
+        assert!(prekeys.decrypt_first(accepted, &first).is_ok());
+        assert!(prekeys.one_time_prekeys.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_forged_initial_keys_keep_one_time_prekey() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let mallory = SecretKey::generate();
+
+        let mut prekeys = Prekeys::generate(1);
+        let bundle = prekeys.upload(&bob, 1000).take_bundle();
+        let (mut session, initial) = initiate(&alice, &bundle).unwrap();
+        let first = session.encrypt(b"hi bob").unwrap();
+
+        // well formed initial keys from someone without the shared secret are
+        // accepted, but their first message doesn't decrypt
+        let forged = InitialKeys {
+            identity_key: mallory.public_key(),
+            ephemeral_key: SecretKey::generate().public_key(),
+            ..initial.clone()
+        };
+        let accepted = prekeys.accept(&bob, &forged).unwrap();
+        let (mut mallory_session, _) = initiate(&mallory, &bundle).unwrap();
+        let forged_first = mallory_session.encrypt(b"hi bob").unwrap();
+        assert!(matches!(prekeys.decrypt_first(accepted, &forged_first), Err(PrekeyError::FirstMessage(_))));
+        assert_eq!(prekeys.one_time_prekeys.len(), 1);
+
+        // nor can a real first message be paired with forged initial keys
+        let accepted = prekeys.accept(&bob, &forged).unwrap();
+        assert!(matches!(prekeys.decrypt_first(accepted, &first), Err(PrekeyError::FirstMessage(_))));
+        assert_eq!(prekeys.one_time_prekeys.len(), 1);
+
+        let accepted = prekeys.accept(&bob, &initial).unwrap();
+        assert_eq!(prekeys.decrypt_first(accepted, &first).unwrap().1, b"hi bob");
+        assert!(prekeys.one_time_prekeys.is_empty());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_initiate_rejects_forged_bundle() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let mallory = SecretKey::generate();
+
+        let mut bundle = Prekeys::generate(1).upload(&bob, 1000).take_bundle();
+        bundle.signed_prekey = SignedPrekey::new(&mallory, &SecretKey::generate().public_key());
+
+        assert!(matches!(initiate(&alice, &bundle), Err(PrekeyError::InvalidSignature)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_accept_wrong_identity() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let eve = SecretKey::generate();
+
+        let mut prekeys = Prekeys::generate(1);
+        let bundle = prekeys.upload(&bob, 1000).take_bundle();
+
+        let (mut alice_session, initial) = initiate(&alice, &bundle).unwrap();
+        let first = alice_session.encrypt(b"hi bob").unwrap();
+
+        // eve has somehow got bob's prekeys but not his identity key
+        let accepted = prekeys.accept(&eve, &initial).unwrap();
+        assert!(matches!(prekeys.decrypt_first(accepted, &first), Err(PrekeyError::FirstMessage(_))));
+    }
+}
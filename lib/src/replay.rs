@@ -0,0 +1,218 @@
+//! Receiver side replay protection, using the signed sequence numbers on messages.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{message::Message, pki::PublicKey};
+
+/// How far behind the newest message an out of order message can arrive.
+/// Anything older is rejected, as there is no record left to tell if it is a
+/// duplicate.
+pub const WINDOW: u64 = 1024;
+
+/// How far past the newest message the next one can jump. A message further
+/// ahead is refused, otherwise a single one could move the window on for good
+/// and leave every message after it too old.
+pub const MAX_AHEAD: u64 = 4 * WINDOW;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The message has been seen before.
+    Duplicate,
+    /// The message is too far behind the newest one to tell.
+    TooOld,
+    /// The message is further ahead of the newest one than [`MAX_AHEAD`].
+    TooFarAhead,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Duplicate => f.write_str("message has already been received"),
+            Self::TooOld => f.write_str("message is too old to check for replay"),
+            Self::TooFarAhead => f.write_str("message is too far ahead of the conversation"),
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+/// Where an accepted message falls in its conversation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sequence {
+    /// Directly follows the newest message.
+    Next,
+    /// Skips ahead, this many messages before it have not arrived.
+    Gap(u64),
+    /// Arrived after newer messages, filling an earlier gap.
+    Late,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+struct Window {
+    newest: u64,
+    // sequence numbers within the window that have been seen
+    seen: BTreeSet<u64>,
+}
+
+/// Tracks the sequence numbers received from each sender.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ReplayGuard {
+    conversations: HashMap<PublicKey, Window>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a message, rejecting it if it has been seen before. The
+    /// signature must already have been verified, otherwise the sequence
+    /// number can't be trusted.
+    pub fn check(&mut self, message: &Message) -> Result<Sequence, ReplayError> {
+        self.check_sequence(&message.from, message.seq)
+    }
+
+    /// Records a sequence number from a sender, for envelopes other than
+    /// [`Message`] that carry one.
+    pub fn check_sequence(&mut self, from: &PublicKey, seq: u64) -> Result<Sequence, ReplayError> {
+        let window = self.conversations.entry(from.clone()).or_default();
+
+        // by distance, adding to `seq` could overflow
+        if window.newest.saturating_sub(seq) >= WINDOW {
+            return Err(ReplayError::TooOld);
+        }
+        if seq > window.newest.saturating_add(MAX_AHEAD) {
+            return Err(ReplayError::TooFarAhead);
+        }
+
+        if !window.seen.insert(seq) {
+            return Err(ReplayError::Duplicate);
+        }
+
+        if seq <= window.newest {
+            return Ok(Sequence::Late);
+        }
+
+        let missing = seq - window.newest - 1;
+        window.newest = seq;
+
+        let floor = seq.saturating_sub(WINDOW);
+        window.seen = window.seen.split_off(&floor);
+
+        match missing {
+            0 => Ok(Sequence::Next),
+            n => Ok(Sequence::Gap(n)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::{content::Content, pki::SecretKey};
+    use super::*;
+
+    fn message(from: &SecretKey, seq: u64) -> Message {
+        Message::new(&SecretKey::generate().public_key(), from, &Content::text("hello"), seq, 0)
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_in_order() {
+        let alice = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        for seq in 1..=10 {
+            assert_eq!(guard.check(&message(&alice, seq)), Ok(Sequence::Next));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_duplicate() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        let first = message(&alice, 1);
+        assert_eq!(guard.check(&first), Ok(Sequence::Next));
+        assert_eq!(guard.check(&first), Err(ReplayError::Duplicate));
+
+        // sequences are per conversation
+        assert_eq!(guard.check(&message(&bob, 1)), Ok(Sequence::Next));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_gap() {
+        let alice = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        assert_eq!(guard.check(&message(&alice, 1)), Ok(Sequence::Next));
+        assert_eq!(guard.check(&message(&alice, 4)), Ok(Sequence::Gap(2)));
+        assert_eq!(guard.check(&message(&alice, 3)), Ok(Sequence::Late));
+        assert_eq!(guard.check(&message(&alice, 3)), Err(ReplayError::Duplicate));
+        assert_eq!(guard.check(&message(&alice, 2)), Ok(Sequence::Late));
+        assert_eq!(guard.check(&message(&alice, 5)), Ok(Sequence::Next));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_too_old() {
+        let alice = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        let first = message(&alice, 1);
+        assert_eq!(guard.check(&first), Ok(Sequence::Next));
+        assert_eq!(guard.check(&message(&alice, 1 + WINDOW)), Ok(Sequence::Gap(WINDOW - 1)));
+        assert_eq!(guard.check(&first), Err(ReplayError::TooOld));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_too_far_ahead() {
+        let alice = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        assert_eq!(guard.check(&message(&alice, 1)), Ok(Sequence::Next));
+        assert_eq!(guard.check(&message(&alice, u64::MAX)), Err(ReplayError::TooFarAhead));
+        assert_eq!(guard.check(&message(&alice, 2 + MAX_AHEAD)), Err(ReplayError::TooFarAhead));
+
+        // the window hasn't moved, later messages still get through
+        assert_eq!(guard.check(&message(&alice, 1 + MAX_AHEAD)), Ok(Sequence::Gap(MAX_AHEAD - 1)));
+        assert_eq!(guard.check(&message(&alice, 2 + MAX_AHEAD)), Ok(Sequence::Next));
+        assert_eq!(guard.check(&message(&alice, 2)), Err(ReplayError::TooOld));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_near_max() {
+        let alice = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        // a conversation that has run up to the end of the range
+        let newest = u64::MAX - 1;
+        let window = Window { newest, seen: [newest].into() };
+        guard.conversations.insert(alice.public_key(), window);
+
+        assert_eq!(guard.check(&message(&alice, u64::MAX)), Ok(Sequence::Next));
+        assert_eq!(guard.check(&message(&alice, u64::MAX)), Err(ReplayError::Duplicate));
+        assert_eq!(guard.check(&message(&alice, u64::MAX - 2)), Ok(Sequence::Late));
+        assert_eq!(guard.check(&message(&alice, 1)), Err(ReplayError::TooOld));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_replay_serialize() {
+        let alice = SecretKey::generate();
+        let mut guard = ReplayGuard::new();
+
+        let first = message(&alice, 1);
+        guard.check(&first).unwrap();
+
+        let mut guard: ReplayGuard = serde_json::from_str(&serde_json::to_string(&guard).unwrap()).unwrap();
+        assert_eq!(guard.check(&first), Err(ReplayError::Duplicate));
+    }
+}
@@ -0,0 +1,139 @@
+//! Revoking a key that has been lost or stolen.
+//!
+//! A [`Revocation`] is made while the key is still at hand and kept somewhere
+//! safe, away from the browser holding the key. If the key is lost, publishing
+//! the revocation makes the worker refuse it and tells contacts to stop
+//! trusting it. Only the key itself can sign one, so nobody can revoke
+//! someone else's key.
+
+use std::{fmt, str::FromStr};
+
+use bech32::{primitives::decode::{CheckedHrpstring, CheckedHrpstringError}, Bech32m, Hrp};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    error::ParseError,
+    pki::{PublicKey, SecretKey, Signature},
+    signing::{Encoder, Signable},
+    wire::{self, WireError},
+};
+
+/// The prefix of an encoded [`Revocation`].
+pub const REVOCATION_PREFIX: &str = "mururevoke";
+
+const REVOCATION_HRP: Hrp = Hrp::parse_unchecked(REVOCATION_PREFIX);
+
+/// A key declaring itself no longer to be trusted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revocation {
+    pub key: PublicKey,
+    /// Milliseconds since the unix epoch when the revocation was made, which
+    /// can be long before it is published.
+    pub created_at: u64,
+    signature: Signature,
+}
+
+impl Revocation {
+    pub fn new(secret_key: &SecretKey, created_at: u64) -> Self {
+        let key = secret_key.public_key();
+        let signature = secret_key.sign(&SignedFields { key: &key, created_at });
+
+        Self { key, created_at, signature }
+    }
+
+    pub fn verify(&self) -> bool {
+        self.key.verify(&SignedFields { key: &self.key, created_at: self.created_at }, &self.signature)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        wire::decode(bytes)
+    }
+}
+
+/// Everything in a revocation except the signature.
+struct SignedFields<'a> {
+    key: &'a PublicKey,
+    created_at: u64,
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "revocation";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bytes(&self.key.bytes()).u64(self.created_at);
+    }
+}
+
+/// Revocations are bech32m encoded with a `mururevoke` prefix, to be written
+/// down or printed and typed back in.
+impl fmt::Display for Revocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        bech32::encode_lower_to_fmt::<Bech32m, _>(f, REVOCATION_HRP, &self.to_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl FromStr for Revocation {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = CheckedHrpstring::new::<Bech32m>(s.trim()).map_err(|e| match e {
+            CheckedHrpstringError::Checksum(_) => ParseError::Checksum,
+            _ => ParseError::Encoding,
+        })?;
+        if encoded.hrp() != REVOCATION_HRP {
+            return Err(ParseError::Prefix(encoded.hrp().to_lowercase()));
+        }
+
+        Self::from_bytes(&encoded.byte_iter().collect::<Vec<u8>>()).map_err(|_| ParseError::Encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_revocation() {
+        let secret_key = SecretKey::generate();
+
+        let revocation = Revocation::new(&secret_key, 1000);
+        assert!(revocation.verify());
+        assert_eq!(revocation.key, secret_key.public_key());
+
+        let encoded = revocation.to_string();
+        assert!(encoded.starts_with("mururevoke1"));
+        let parsed: Revocation = encoded.to_uppercase().parse().unwrap();
+        assert!(parsed.verify() && parsed.key == revocation.key && parsed.created_at == 1000);
+
+        let json = serde_json::to_string(&revocation).unwrap();
+        assert!(serde_json::from_str::<Revocation>(&json).unwrap().verify());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_revocation_checks() {
+        let revocation = Revocation::new(&SecretKey::generate(), 1000);
+
+        // only a key can revoke itself
+        let mut forged = revocation.clone();
+        forged.key = SecretKey::generate().public_key();
+        assert!(!forged.verify());
+
+        let mut backdated = revocation.clone();
+        backdated.created_at = 0;
+        assert!(!backdated.verify());
+
+        let mut typo = revocation.to_string();
+        typo.remove(20);
+        assert_eq!(typo.parse::<Revocation>().unwrap_err(), ParseError::Checksum);
+        assert_eq!(SecretKey::generate().public_key().to_string().parse::<Revocation>().unwrap_err(), ParseError::Prefix("muru".to_string()));
+    }
+}
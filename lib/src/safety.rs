@@ -0,0 +1,140 @@
+//! Safety numbers for verifying contacts out of band.
+//!
+//! A public key pasted into the app could belong to anyone. Two people can
+//! compare the [`SafetyNumber`] of their keys, in person or over a call, and
+//! if both see the same number nobody has swapped a key in between. Both sides
+//! compute the same number no matter whose key comes first.
+
+use std::fmt;
+
+use bip39::Language;
+use sha2::{Digest, Sha512};
+
+use crate::pki::PublicKey;
+
+const VERSION: u16 = 0;
+
+// hashing this many times makes finding a key with a matching number slow
+const ITERATIONS: usize = 5200;
+
+const BLOCKS_PER_KEY: usize = 6;
+const BLOCK_DIGITS: usize = 5;
+const WORDS: usize = 6;
+
+/// The safety number of a pair of public keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyNumber {
+    digits: String,
+    words: Vec<&'static str>,
+}
+
+impl SafetyNumber {
+    pub fn new(a: &PublicKey, b: &PublicKey) -> Self {
+        let mut halves = [fingerprint(a), fingerprint(b)];
+        halves.sort();
+        let digits = halves.concat();
+
+        let hash = Sha512::digest(digits.as_bytes());
+        let word_list = Language::English.word_list();
+        let words = (0..WORDS)
+            .map(|i| {
+                // 11 bits a word, from the first 66 bits of the hash
+                let bit = i * 11;
+                let window = u32::from_be_bytes(hash[bit / 8..bit / 8 + 4].try_into().expect("4 bytes"));
+                word_list[(window >> (32 - 11 - bit % 8)) as usize & 0x7ff]
+            })
+            .collect();
+
+        Self { digits, words }
+    }
+
+    /// The number as blocks of five digits, to be read out or compared.
+    pub fn blocks(&self) -> Vec<&str> {
+        (0..self.digits.len())
+            .step_by(BLOCK_DIGITS)
+            .map(|i| &self.digits[i..i + BLOCK_DIGITS])
+            .collect()
+    }
+
+    /// A few words derived from the number, quicker to compare over a call.
+    pub fn words(&self) -> &[&'static str] {
+        &self.words
+    }
+}
+
+impl fmt::Display for SafetyNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.blocks().join(" "))
+    }
+}
+
+/// One key's half of a safety number, 30 digits.
+fn fingerprint(public_key: &PublicKey) -> String {
+    let key = public_key.bytes();
+
+    let mut hash = Sha512::new()
+        .chain_update(VERSION.to_be_bytes())
+        .chain_update(key)
+        .finalize();
+    for _ in 1..ITERATIONS {
+        hash = Sha512::new().chain_update(hash).chain_update(key).finalize();
+    }
+
+    hash.chunks(5)
+        .take(BLOCKS_PER_KEY)
+        .map(|chunk| {
+            let n = chunk.iter().fold(0u64, |n, byte| n << 8 | *byte as u64);
+            format!("{:05}", n % 100_000)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::pki::SecretKey;
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_safety_number_is_symmetric() {
+        let alice = SecretKey::generate().public_key();
+        let bob = SecretKey::generate().public_key();
+
+        let number = SafetyNumber::new(&alice, &bob);
+        assert_eq!(number, SafetyNumber::new(&bob, &alice));
+
+        assert_eq!(number.blocks().len(), 12);
+        assert!(number.blocks().iter().all(|b| b.len() == 5 && b.chars().all(|c| c.is_ascii_digit())));
+        assert_eq!(number.words().len(), 6);
+        assert_eq!(number.to_string().len(), 12 * 6 - 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_safety_number_changes_with_keys() {
+        let alice = SecretKey::generate().public_key();
+        let bob = SecretKey::generate().public_key();
+        let mallory = SecretKey::generate().public_key();
+
+        let number = SafetyNumber::new(&alice, &bob);
+        let swapped = SafetyNumber::new(&alice, &mallory);
+        assert_ne!(number.blocks(), swapped.blocks());
+        assert_ne!(number.words(), swapped.words());
+
+        // alice's half stays the same
+        assert!(swapped.digits.contains(&fingerprint(&alice)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_safety_number_is_stable() {
+        let a = SecretKey::from_bytes(&[1; 32]).unwrap().public_key();
+        let b = SecretKey::from_bytes(&[2; 32]).unwrap().public_key();
+
+        // changing how numbers are computed would undo every verification
+        let number = SafetyNumber::new(&a, &b);
+        assert_eq!(number.to_string(), "03445 79912 43969 79014 32317 38009 73228 02325 00374 10226 88619 39984");
+        assert_eq!(number.words(), ["source", "width", "recycle", "prosper", "shiver", "forward"]);
+    }
+}
@@ -0,0 +1,250 @@
+//! Sealed sender, so the worker doesn't learn who is messaging whom.
+//!
+//! A [`SealedMessage`] wraps an ordinary signed [`Message`] in a second layer of
+//! encryption to the recipient, leaving only the recipient's key in the clear.
+//! Sealed messages are sent without authenticating to the worker, so instead of
+//! knowing the sender the worker checks a [`DeliveryToken`]. Tokens are made
+//! with the recipient's [`AccessKey`], which they hand to their contacts and the
+//! worker. Every contact shares the same key and every token has a fresh nonce,
+//! so the worker can tell a token came from one of the recipient's contacts and
+//! rate limit them, but not which one.
+
+use std::{error::Error, fmt};
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+
+use crate::{
+    message::Message,
+    pki::{PublicKey, SecretKey},
+    wire::{self, WireError},
+};
+
+const ACCESS_KEY_INFO: &[u8] = b"muruchat access key";
+const TOKEN_INFO: &[u8] = b"muruchat delivery token";
+
+#[derive(Debug)]
+pub enum SealedError {
+    /// The secret key does not belong to the recipient of the message.
+    WrongRecipient,
+    /// The outer ciphertext failed authentication.
+    Ciphertext,
+    /// The decrypted envelope is not a message.
+    Message(WireError),
+    /// The inner message has an invalid signature, or is for someone else.
+    InvalidMessage,
+}
+
+impl fmt::Display for SealedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongRecipient => f.write_str("sealed message is not addressed to this key"),
+            Self::Ciphertext => f.write_str("failed to decrypt sealed message"),
+            Self::Message(e) => write!(f, "sealed message does not contain a message: {}", e),
+            Self::InvalidMessage => f.write_str("sealed message contains an invalid message"),
+        }
+    }
+}
+
+impl Error for SealedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Message(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Shared by a recipient with their contacts and the worker, to make and check
+/// delivery tokens.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessKey(#[serde(with = "crate::wire::bytes")] [u8; 32]);
+
+impl AccessKey {
+    /// The access key for an identity, the same on every device holding it.
+    pub fn derive(secret_key: &SecretKey) -> Self {
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(None, &secret_key.bytes())
+            .expand(ACCESS_KEY_INFO, &mut key)
+            .expect("32 bytes is a valid hkdf output length");
+
+        Self(key)
+    }
+
+    /// Makes a fresh token for a message to `to`, sent at `timestamp`.
+    pub fn token(&self, to: &PublicKey, timestamp: u64) -> DeliveryToken {
+        let mut nonce = [0; 16];
+        rand::thread_rng().fill(&mut nonce[..]);
+
+        let mac = self.mac(to, timestamp, &nonce).finalize().into_bytes().into();
+
+        DeliveryToken { timestamp, nonce, mac }
+    }
+
+    pub fn verify(&self, to: &PublicKey, token: &DeliveryToken) -> bool {
+        self.mac(to, token.timestamp, &token.nonce).verify_slice(&token.mac).is_ok()
+    }
+
+    fn mac(&self, to: &PublicKey, timestamp: u64, nonce: &[u8; 16]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("hmac takes any key length");
+        mac.update(TOKEN_INFO);
+        mac.update(&to.bytes());
+        mac.update(&timestamp.to_be_bytes());
+        mac.update(nonce);
+        mac
+    }
+}
+
+// the key is kept out of logs, anyone holding it can send to the recipient
+impl fmt::Debug for AccessKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessKey(..)")
+    }
+}
+
+/// Proves a sealed message was sent by someone holding the recipient's access key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryToken {
+    /// Milliseconds since the unix epoch, so the worker can expire tokens.
+    pub timestamp: u64,
+    /// Makes every token unique, the worker refuses a nonce it has seen before.
+    #[serde(with = "crate::wire::bytes")]
+    pub nonce: [u8; 16],
+    #[serde(with = "crate::wire::bytes")]
+    mac: [u8; 32],
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedMessage {
+    pub to: PublicKey,
+    pub token: DeliveryToken,
+    #[serde(with = "crate::wire::bytes")]
+    ciphertext: Vec<u8>,
+}
+
+impl SealedMessage {
+    /// Seals a message for its recipient, with a token made from their access key.
+    pub fn seal(message: &Message, access_key: &AccessKey) -> Self {
+        let ciphertext = message.to.encrypt(&message.to_bytes());
+
+        Self {
+            to: message.to.clone(),
+            token: access_key.token(&message.to, message.timestamp),
+            ciphertext,
+        }
+    }
+
+    /// Opens a sealed message, returning the inner message once its signature
+    /// has been verified.
+    pub fn open(&self, secret_key: &SecretKey) -> Result<Message, SealedError> {
+        if secret_key.public_key() != self.to {
+            return Err(SealedError::WrongRecipient);
+        }
+
+        let plaintext = secret_key.decrypt(&self.ciphertext).ok_or(SealedError::Ciphertext)?;
+
+        let message = Message::from_bytes(&plaintext).map_err(SealedError::Message)?;
+        if message.to != self.to || !message.verify() {
+            return Err(SealedError::InvalidMessage);
+        }
+
+        Ok(message)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        wire::encode(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, WireError> {
+        wire::decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::{content::Content, pki::Suite};
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_sealed_round_trip() {
+        for suite in Suite::ALL {
+            let alice = SecretKey::generate_with(suite);
+            let bob = SecretKey::generate_with(suite);
+
+            let message = Message::new(&bob.public_key(), &alice, &Content::text("hello"), 1, 0);
+            let sealed = SealedMessage::from_bytes(&SealedMessage::seal(&message, &AccessKey::derive(&bob)).to_bytes()).unwrap();
+
+            let opened = sealed.open(&bob).unwrap();
+            assert_eq!(opened.from, alice.public_key());
+            assert!(matches!(opened.decrypt(&bob), Ok(Content::Text { text, .. }) if text == "hello"));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sealed_hides_sender() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+
+        let message = Message::new(&bob.public_key(), &alice, &Content::text("hello"), 1, 0);
+        let sealed = SealedMessage::seal(&message, &AccessKey::derive(&bob));
+
+        let sender = alice.public_key().bytes();
+        assert!(!sealed.to_bytes().windows(sender.len()).any(|w| w == sender));
+        assert!(!serde_json::to_string(&sealed).unwrap().contains(&hex::encode(alice.public_key().bytes())));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sealed_wrong_recipient() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+
+        let message = Message::new(&bob.public_key(), &alice, &Content::text("hello"), 1, 0);
+        let sealed = SealedMessage::seal(&message, &AccessKey::derive(&bob));
+
+        assert!(matches!(sealed.open(&alice), Err(SealedError::WrongRecipient)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_sealed_inner_message_for_someone_else() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let carol = SecretKey::generate();
+
+        // a message alice sent carol, resealed by carol to bob
+        let message = Message::new(&carol.public_key(), &alice, &Content::text("hello"), 1, 0);
+        let mut sealed = SealedMessage::seal(&message, &AccessKey::derive(&carol));
+        sealed.to = bob.public_key();
+        sealed.ciphertext = bob.public_key().encrypt(&message.to_bytes());
+
+        assert!(matches!(sealed.open(&bob), Err(SealedError::InvalidMessage)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_delivery_token() {
+        let bob = SecretKey::generate();
+        let access_key = AccessKey::derive(&bob);
+        assert!(access_key == AccessKey::derive(&bob));
+
+        let first = access_key.token(&bob.public_key(), 1000);
+        let second = access_key.token(&bob.public_key(), 1000);
+
+        assert!(access_key.verify(&bob.public_key(), &first));
+        assert!(access_key.verify(&bob.public_key(), &second));
+        // unlinkable, two tokens from the same key share nothing
+        assert_ne!(first.nonce, second.nonce);
+        assert_ne!(first.mac, second.mac);
+
+        let mut expired = first.clone();
+        expired.timestamp += 1;
+        assert!(!access_key.verify(&bob.public_key(), &expired));
+        assert!(!access_key.verify(&SecretKey::generate().public_key(), &first));
+        assert!(!AccessKey::derive(&SecretKey::generate()).verify(&bob.public_key(), &first));
+    }
+}
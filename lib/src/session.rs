@@ -0,0 +1,566 @@
+//! Double Ratchet sessions between two identities.
+//!
+//! Every message is encrypted with a fresh message key derived from a
+//! symmetric chain, and the chains are reseeded with a new Diffie-Hellman
+//! exchange each time the conversation changes direction. Compromising a
+//! `SecretKey` therefore does not expose messages sent before the compromise.
+//!
+//! See <https://signal.org/docs/specifications/doubleratchet/> for the
+//! algorithm this implements.
+
+use std::{collections::VecDeque, error::Error, fmt};
+
+use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+
+use crate::pki::{PublicKey, SecretKey, Suite};
+
+/// Maximum number of message keys that will be skipped within a single chain.
+pub const MAX_SKIP: u32 = 1000;
+
+/// Maximum number of skipped message keys kept around for late messages.
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+const INITIAL_INFO: &[u8] = b"muruchat session initial";
+const RATCHET_INFO: &[u8] = b"muruchat session ratchet";
+const ROOT_INFO: &[u8] = b"muruchat session root";
+const MESSAGE_INFO: &[u8] = b"muruchat session message";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub ratchet_key: PublicKey,
+    pub previous_chain_length: u32,
+    pub number: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatchetMessage {
+    pub header: Header,
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    /// No message has been received yet so there is no sending chain. The
+    /// responding side of a session can't send first.
+    NoSendingChain,
+    /// The message has already been decrypted.
+    Duplicate,
+    /// The message is further ahead in its chain than [`MAX_SKIP`].
+    TooManySkipped,
+    /// The ciphertext failed authentication.
+    Decrypt,
+    /// The keys are from different cipher suites.
+    SuiteMismatch,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSendingChain => f.write_str("session can't send until a message is received"),
+            Self::Duplicate => f.write_str("message has already been received"),
+            Self::TooManySkipped => f.write_str("too many messages skipped"),
+            Self::Decrypt => f.write_str("failed to decrypt message"),
+            Self::SuiteMismatch => f.write_str("keys are from different cipher suites"),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SkippedKey {
+    ratchet_key: PublicKey,
+    number: u32,
+    message_key: [u8; 32],
+}
+
+/// One side of a Double Ratchet session. The whole state is serializable so it
+/// can be persisted between page loads, it must be saved after every call to
+/// [`Session::encrypt`] or [`Session::decrypt`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    associated_data: Vec<u8>,
+    ratchet_key: SecretKey,
+    remote_ratchet_key: Option<PublicKey>,
+    root_key: [u8; 32],
+    sending_chain: Option<[u8; 32]>,
+    receiving_chain: Option<[u8; 32]>,
+    sending_number: u32,
+    receiving_number: u32,
+    previous_sending_number: u32,
+    skipped: VecDeque<SkippedKey>,
+}
+
+impl Session {
+    /// Starts a session with `remote`. The initiator must send the first message.
+    pub fn initiate(secret_key: &SecretKey, remote: &PublicKey) -> Result<Self, SessionError> {
+        let associated_data = [secret_key.public_key().bytes(), remote.bytes()].concat();
+        let shared_secret = initial_secret(secret_key, remote, &associated_data)?;
+
+        Ok(Self::initiator(shared_secret, associated_data, remote.suite()))
+    }
+
+    /// Accepts a session started by `remote` with [`Session::initiate`].
+    pub fn respond(secret_key: &SecretKey, remote: &PublicKey) -> Result<Self, SessionError> {
+        let associated_data = [remote.bytes(), secret_key.public_key().bytes()].concat();
+        let shared_secret = initial_secret(secret_key, remote, &associated_data)?;
+
+        Ok(Self::responder(shared_secret, associated_data, secret_key.suite()))
+    }
+
+    pub(crate) fn initiator(shared_secret: [u8; 32], associated_data: Vec<u8>, suite: Suite) -> Self {
+        let remote_ratchet_key = initial_ratchet_key(&shared_secret, suite).public_key();
+        let ratchet_key = SecretKey::generate_with(suite);
+        let (root_key, sending_chain) = kdf_root(&shared_secret, &ratchet(&ratchet_key, &remote_ratchet_key));
+
+        Self {
+            associated_data,
+            ratchet_key,
+            remote_ratchet_key: Some(remote_ratchet_key),
+            root_key,
+            sending_chain: Some(sending_chain),
+            receiving_chain: None,
+            sending_number: 0,
+            receiving_number: 0,
+            previous_sending_number: 0,
+            skipped: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn responder(shared_secret: [u8; 32], associated_data: Vec<u8>, suite: Suite) -> Self {
+        Self {
+            associated_data,
+            ratchet_key: initial_ratchet_key(&shared_secret, suite),
+            remote_ratchet_key: None,
+            root_key: shared_secret,
+            sending_chain: None,
+            receiving_chain: None,
+            sending_number: 0,
+            receiving_number: 0,
+            previous_sending_number: 0,
+            skipped: VecDeque::new(),
+        }
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<RatchetMessage, SessionError> {
+        let chain = self.sending_chain.ok_or(SessionError::NoSendingChain)?;
+        let (chain, message_key) = kdf_chain(&chain);
+        self.sending_chain = Some(chain);
+
+        let header = Header {
+            ratchet_key: self.ratchet_key.public_key(),
+            previous_chain_length: self.previous_sending_number,
+            number: self.sending_number,
+        };
+        self.sending_number += 1;
+
+        let ciphertext = seal(&message_key, &self.aad(&header), plaintext);
+
+        Ok(RatchetMessage { header, ciphertext })
+    }
+
+    /// Decrypts a message from the other side. The session is left untouched
+    /// if decryption fails, so a forged or corrupt message can't desync it.
+    pub fn decrypt(&mut self, message: &RatchetMessage) -> Result<Vec<u8>, SessionError> {
+        let mut next = self.clone();
+        let plaintext = next.try_decrypt(message)?;
+        *self = next;
+
+        Ok(plaintext)
+    }
+
+    fn try_decrypt(&mut self, message: &RatchetMessage) -> Result<Vec<u8>, SessionError> {
+        let header = &message.header;
+        let aad = self.aad(header);
+
+        if let Some(i) = self
+            .skipped
+            .iter()
+            .position(|s| s.ratchet_key == header.ratchet_key && s.number == header.number)
+        {
+            let skipped = self.skipped.remove(i).unwrap();
+            return open(&skipped.message_key, &aad, &message.ciphertext).ok_or(SessionError::Decrypt);
+        }
+
+        if self.remote_ratchet_key.as_ref() == Some(&header.ratchet_key) {
+            if header.number < self.receiving_number {
+                return Err(SessionError::Duplicate);
+            }
+        } else {
+            self.skip_message_keys(header.previous_chain_length)?;
+            self.dh_ratchet(&header.ratchet_key)?;
+        }
+
+        self.skip_message_keys(header.number)?;
+
+        let chain = self.receiving_chain.ok_or(SessionError::Decrypt)?;
+        let (chain, message_key) = kdf_chain(&chain);
+        self.receiving_chain = Some(chain);
+        self.receiving_number += 1;
+
+        open(&message_key, &aad, &message.ciphertext).ok_or(SessionError::Decrypt)
+    }
+
+    fn skip_message_keys(&mut self, until: u32) -> Result<(), SessionError> {
+        if until > self.receiving_number.saturating_add(MAX_SKIP) {
+            return Err(SessionError::TooManySkipped);
+        }
+
+        if let (Some(mut chain), Some(ratchet_key)) = (self.receiving_chain, self.remote_ratchet_key.clone()) {
+            while self.receiving_number < until {
+                let (next, message_key) = kdf_chain(&chain);
+                chain = next;
+
+                self.skipped.push_back(SkippedKey {
+                    ratchet_key: ratchet_key.clone(),
+                    number: self.receiving_number,
+                    message_key,
+                });
+                if self.skipped.len() > MAX_SKIPPED_KEYS {
+                    self.skipped.pop_front();
+                }
+
+                self.receiving_number += 1;
+            }
+
+            self.receiving_chain = Some(chain);
+        }
+
+        Ok(())
+    }
+
+    fn dh_ratchet(&mut self, remote_ratchet_key: &PublicKey) -> Result<(), SessionError> {
+        self.previous_sending_number = self.sending_number;
+        self.sending_number = 0;
+        self.receiving_number = 0;
+
+        let dh_out = self.ratchet_key.diffie_hellman(remote_ratchet_key).ok_or(SessionError::Decrypt)?;
+        let (root_key, receiving_chain) = kdf_root(&self.root_key, &dh_out);
+        self.receiving_chain = Some(receiving_chain);
+
+        self.ratchet_key = SecretKey::generate_with(remote_ratchet_key.suite());
+        let (root_key, sending_chain) = kdf_root(&root_key, &ratchet(&self.ratchet_key, remote_ratchet_key));
+        self.sending_chain = Some(sending_chain);
+
+        self.root_key = root_key;
+        self.remote_ratchet_key = Some(remote_ratchet_key.clone());
+
+        Ok(())
+    }
+
+    fn aad(&self, header: &Header) -> Vec<u8> {
+        [
+            self.associated_data.as_slice(),
+            header.ratchet_key.bytes().as_slice(),
+            &header.previous_chain_length.to_be_bytes(),
+            &header.number.to_be_bytes(),
+        ].concat()
+    }
+}
+
+fn initial_secret(secret_key: &SecretKey, remote: &PublicKey, associated_data: &[u8]) -> Result<[u8; 32], SessionError> {
+    let dh_out = secret_key.diffie_hellman(remote).ok_or(SessionError::SuiteMismatch)?;
+
+    let mut out = [0; 32];
+    Hkdf::<Sha256>::new(None, &dh_out)
+        .expand(&[INITIAL_INFO, associated_data].concat(), &mut out)
+        .expect("32 bytes is a valid hkdf output length");
+    Ok(out)
+}
+
+/// The responder's first ratchet key, which both sides derive from the shared
+/// secret. Starting from the responder's identity key or prekey instead would
+/// put a long-term secret in the session state that gets persisted.
+fn initial_ratchet_key(shared_secret: &[u8; 32], suite: Suite) -> SecretKey {
+    let hkdf = Hkdf::<Sha256>::new(None, shared_secret);
+
+    // the odd seed that isn't a valid key is skipped
+    (0..=u8::MAX)
+        .find_map(|counter| {
+            let mut seed = [0; 32];
+            hkdf.expand(&[RATCHET_INFO, &[counter]].concat(), &mut seed)
+                .expect("32 bytes is a valid hkdf output length");
+            SecretKey::from_seed(suite, seed).ok()
+        })
+        .expect("a valid key is found long before running out of seeds")
+}
+
+// a fresh ratchet key is always made in the remote key's suite
+fn ratchet(ratchet_key: &SecretKey, remote_ratchet_key: &PublicKey) -> [u8; 32] {
+    ratchet_key.diffie_hellman(remote_ratchet_key).expect("ratchet keys are from the same suite")
+}
+
+fn kdf_root(root_key: &[u8; 32], dh_out: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut out = [0; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh_out)
+        .expand(ROOT_INFO, &mut out)
+        .expect("64 bytes is a valid hkdf output length");
+
+    (out[..32].try_into().unwrap(), out[32..].try_into().unwrap())
+}
+
+pub(crate) fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let hmac = |input: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key).expect("hmac accepts any key length");
+        mac.update(&[input]);
+        mac.finalize().into_bytes().into()
+    };
+
+    (hmac(0x02), hmac(0x01))
+}
+
+fn message_cipher(message_key: &[u8; 32]) -> (Aes256Gcm, [u8; 12]) {
+    let mut out = [0; 44];
+    Hkdf::<Sha256>::new(None, message_key)
+        .expand(MESSAGE_INFO, &mut out)
+        .expect("44 bytes is a valid hkdf output length");
+
+    let cipher = Aes256Gcm::new_from_slice(&out[..32]).expect("key is 32 bytes");
+    (cipher, out[32..].try_into().unwrap())
+}
+
+pub(crate) fn seal(message_key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let (cipher, nonce) = message_cipher(message_key);
+
+    cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .expect("aes-gcm encryption is infallible for in-memory buffers")
+}
+
+pub(crate) fn open(message_key: &[u8; 32], aad: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+    let (cipher, nonce) = message_cipher(message_key);
+
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: ciphertext, aad })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use wasm_bindgen_test::*;
+
+    use super::*;
+    use crate::pki::Suite;
+
+    fn pair() -> (Session, Session) {
+        pair_with(Suite::Secp256k1)
+    }
+
+    fn pair_with(suite: Suite) -> (Session, Session) {
+        let alice = SecretKey::generate_with(suite);
+        let bob = SecretKey::generate_with(suite);
+
+        (
+            Session::initiate(&alice, &bob.public_key()).unwrap(),
+            Session::respond(&bob, &alice.public_key()).unwrap(),
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_ping_pong() {
+        for suite in Suite::ALL {
+            let (mut alice, mut bob) = pair_with(suite);
+
+            for i in 0..10 {
+                let text = format!("alice {}", i);
+                let message = alice.encrypt(text.as_bytes()).unwrap();
+                assert_eq!(message.header.ratchet_key.suite(), suite);
+                assert_eq!(bob.decrypt(&message).unwrap(), text.as_bytes());
+
+                let text = format!("bob {}", i);
+                let message = bob.encrypt(text.as_bytes()).unwrap();
+                assert_eq!(alice.decrypt(&message).unwrap(), text.as_bytes());
+            }
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_suite_mismatch() {
+        let alice = SecretKey::generate_with(Suite::Secp256k1);
+        let bob = SecretKey::generate_with(Suite::Ed25519);
+
+        assert!(matches!(Session::initiate(&alice, &bob.public_key()), Err(SessionError::SuiteMismatch)));
+        assert!(matches!(Session::respond(&bob, &alice.public_key()), Err(SessionError::SuiteMismatch)));
+
+        // a ratchet key from the other suite is refused, and changes nothing
+        let (mut alice, mut bob) = pair();
+        let mut message = alice.encrypt(b"hello").unwrap();
+        let original = message.clone();
+        message.header.ratchet_key = SecretKey::generate_with(Suite::Ed25519).public_key();
+        assert!(matches!(bob.decrypt(&message), Err(SessionError::Decrypt)));
+        assert_eq!(bob.decrypt(&original).unwrap(), b"hello");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_state_holds_no_identity_key() {
+        for suite in Suite::ALL {
+            let alice = SecretKey::generate_with(suite);
+            let bob = SecretKey::generate_with(suite);
+            let mut sender = Session::initiate(&alice, &bob.public_key()).unwrap();
+            let receiver = Session::respond(&bob, &alice.public_key()).unwrap();
+
+            assert!(receiver.ratchet_key != bob && sender.ratchet_key != alice);
+            assert!(sender.remote_ratchet_key != Some(bob.public_key()));
+
+            // the initiator starts from the ratchet key the responder holds
+            assert_eq!(sender.remote_ratchet_key, Some(receiver.ratchet_key.public_key()));
+            let mut receiver = receiver;
+            let message = sender.encrypt(b"hello").unwrap();
+            assert_eq!(receiver.decrypt(&message).unwrap(), b"hello");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_responder_cant_send_first() {
+        let (_, mut bob) = pair();
+
+        assert!(matches!(bob.encrypt(b"hi"), Err(SessionError::NoSendingChain)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_wrong_peer() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let eve = SecretKey::generate();
+
+        let mut sender = Session::initiate(&alice, &bob.public_key()).unwrap();
+        let mut receiver = Session::respond(&eve, &alice.public_key()).unwrap();
+
+        let message = sender.encrypt(b"for bob only").unwrap();
+        assert!(matches!(receiver.decrypt(&message), Err(SessionError::Decrypt)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_out_of_order() {
+        let (mut alice, mut bob) = pair();
+
+        let messages: Vec<RatchetMessage> = (0..5)
+            .map(|i| alice.encrypt(&[i]).unwrap())
+            .collect();
+
+        for i in [3, 0, 4, 2, 1] {
+            assert_eq!(bob.decrypt(&messages[i]).unwrap(), [i as u8]);
+        }
+
+        // old chain messages still decrypt after the ratchet has turned
+        let late = alice.encrypt(b"late").unwrap();
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        let next = alice.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+        assert_eq!(bob.decrypt(&late).unwrap(), b"late");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_duplicate_rejected() {
+        let (mut alice, mut bob) = pair();
+
+        let first = alice.encrypt(b"first").unwrap();
+        let second = alice.encrypt(b"second").unwrap();
+
+        bob.decrypt(&second).unwrap();
+        bob.decrypt(&first).unwrap();
+
+        assert!(matches!(bob.decrypt(&first), Err(SessionError::Duplicate)));
+        assert!(matches!(bob.decrypt(&second), Err(SessionError::Duplicate)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_tampered_message_does_not_advance() {
+        let (mut alice, mut bob) = pair();
+
+        let message = alice.encrypt(b"hello").unwrap();
+
+        let mut tampered = message.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(matches!(bob.decrypt(&tampered), Err(SessionError::Decrypt)));
+
+        let mut tampered = message.clone();
+        tampered.header.number += 1;
+        assert!(matches!(bob.decrypt(&tampered), Err(SessionError::Decrypt)));
+
+        assert_eq!(bob.decrypt(&message).unwrap(), b"hello");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_too_many_skipped() {
+        let (mut alice, mut bob) = pair();
+
+        let mut message = alice.encrypt(b"hello").unwrap();
+        message.header.number = MAX_SKIP + 1;
+
+        assert!(matches!(bob.decrypt(&message), Err(SessionError::TooManySkipped)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_serialize_resume() {
+        let (mut alice, mut bob) = pair();
+
+        let message = alice.encrypt(b"one").unwrap();
+        bob.decrypt(&message).unwrap();
+        let skipped = bob.encrypt(b"skipped").unwrap();
+        let message = bob.encrypt(b"two").unwrap();
+        alice.decrypt(&message).unwrap();
+
+        let mut alice: Session = serde_json::from_str(&serde_json::to_string(&alice).unwrap()).unwrap();
+        let mut bob: Session = serde_json::from_str(&serde_json::to_string(&bob).unwrap()).unwrap();
+
+        assert_eq!(alice.decrypt(&skipped).unwrap(), b"skipped");
+        let message = alice.encrypt(b"three").unwrap();
+        assert_eq!(bob.decrypt(&message).unwrap(), b"three");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_long_conversation_with_lost_messages() {
+        let (mut alice, mut bob) = pair();
+        let mut rng = StdRng::seed_from_u64(7);
+
+        // alice has to speak first, after that either side may send
+        let first = alice.encrypt(b"start").unwrap();
+        bob.decrypt(&first).unwrap();
+
+        let mut to_alice: Vec<(RatchetMessage, Vec<u8>)> = Vec::new();
+        let mut to_bob: Vec<(RatchetMessage, Vec<u8>)> = Vec::new();
+        let mut delivered = 0;
+
+        for i in 0..1000u32 {
+            let plaintext = i.to_be_bytes().to_vec();
+
+            if rng.gen_bool(0.5) {
+                to_bob.push((alice.encrypt(&plaintext).unwrap(), plaintext));
+            } else {
+                to_alice.push((bob.encrypt(&plaintext).unwrap(), plaintext));
+            }
+
+            for (queue, receiver) in [(&mut to_alice, &mut alice), (&mut to_bob, &mut bob)] {
+                // deliver a random message from the in flight queue, or lose it
+                if !queue.is_empty() && rng.gen_bool(0.6) {
+                    let (message, plaintext) = queue.remove(rng.gen_range(0..queue.len()));
+
+                    if rng.gen_bool(0.15) {
+                        continue;
+                    }
+
+                    assert_eq!(receiver.decrypt(&message).unwrap(), plaintext);
+                    delivered += 1;
+                }
+            }
+        }
+
+        for (queue, receiver) in [(to_alice, &mut alice), (to_bob, &mut bob)] {
+            for (message, plaintext) in queue {
+                assert_eq!(receiver.decrypt(&message).unwrap(), plaintext);
+                delivered += 1;
+            }
+        }
+
+        assert!(delivered > 500);
+    }
+}
@@ -0,0 +1,105 @@
+//! Domain separated signing. Every signature made by the library covers a
+//! canonical encoding of the object prefixed with what kind of object it is,
+//! so a signature over one kind of object is never valid for another.
+
+const DOMAIN: &[u8] = b"muruchat";
+
+/// An object that can be signed and verified with the keys in [`crate::pki`].
+pub trait Signable {
+    /// Names the kind of object, no two types may share a context.
+    const CONTEXT: &'static str;
+    /// Bumped whenever the signed fields change.
+    const VERSION: u8;
+
+    /// Writes every field covered by the signature.
+    fn encode(&self, encoder: &mut Encoder);
+
+    /// The bytes that are actually signed.
+    fn signing_bytes(&self) -> Vec<u8> {
+        let mut encoder = Encoder::default();
+        encoder
+            .bytes(DOMAIN)
+            .bytes(Self::CONTEXT.as_bytes())
+            .u8(Self::VERSION);
+
+        self.encode(&mut encoder);
+
+        encoder.0
+    }
+}
+
+/// Writes fields unambiguously, variable length fields are length prefixed so
+/// no two sequences of fields encode to the same bytes.
+#[derive(Default)]
+pub struct Encoder(Vec<u8>);
+
+impl Encoder {
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        let len = u32::try_from(bytes.len()).expect("signed fields are under 4GiB");
+        self.0.extend_from_slice(&len.to_be_bytes());
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn u8(&mut self, n: u8) -> &mut Self {
+        self.0.push(n);
+        self
+    }
+
+    pub fn u32(&mut self, n: u32) -> &mut Self {
+        self.0.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, n: u64) -> &mut Self {
+        self.0.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use crate::pki::SecretKey;
+    use super::*;
+
+    struct Note<'a>(&'a str, &'a str);
+
+    impl Signable for Note<'_> {
+        const CONTEXT: &'static str = "note";
+        const VERSION: u8 = 1;
+
+        fn encode(&self, encoder: &mut Encoder) {
+            encoder.bytes(self.0.as_bytes()).bytes(self.1.as_bytes());
+        }
+    }
+
+    struct Memo<'a>(&'a str, &'a str);
+
+    impl Signable for Memo<'_> {
+        const CONTEXT: &'static str = "memo";
+        const VERSION: u8 = 1;
+
+        fn encode(&self, encoder: &mut Encoder) {
+            encoder.bytes(self.0.as_bytes()).bytes(self.1.as_bytes());
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_signing_fields_are_unambiguous() {
+        assert_ne!(Note("ab", "c").signing_bytes(), Note("a", "bc").signing_bytes());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_signing_context_separates() {
+        let secret = SecretKey::generate();
+
+        let signature = secret.sign(&Note("hello", "world"));
+
+        assert!(secret.public_key().verify(&Note("hello", "world"), &signature));
+        assert!(!secret.public_key().verify(&Memo("hello", "world"), &signature));
+    }
+}
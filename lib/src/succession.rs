@@ -0,0 +1,257 @@
+//! Replacing a key without contacts having to add it again.
+//!
+//! The old key signs a [`KeySuccession`] naming the key that replaces it, and
+//! the new key may countersign it to show it agreed to take over. Contacts who
+//! trust the old key follow the statements from it to the latest key with
+//! [`follow`]. A key that names two different successors has been used by
+//! someone other than its owner, so such a chain isn't followed at all.
+
+use std::{collections::HashSet, error::Error, fmt};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    pki::{PublicKey, SecretKey, Signature},
+    signing::{Encoder, Signable},
+};
+
+/// The most statements followed from one key, so a chain can't be made to go
+/// on forever.
+pub const MAX_CHAIN_LENGTH: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SuccessionError {
+    /// The statement isn't signed by the old key.
+    InvalidSignature,
+    /// The statement's countersignature isn't from the new key.
+    InvalidCountersignature,
+    /// A key can't countersign a statement naming another key.
+    WrongKey,
+    /// A key names more than one successor.
+    Conflict,
+    /// The chain leads back to a key in it, or is longer than [`MAX_CHAIN_LENGTH`].
+    Loop,
+}
+
+impl fmt::Display for SuccessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSignature => f.write_str("key succession isn't signed by the old key"),
+            Self::InvalidCountersignature => f.write_str("key succession countersignature is invalid"),
+            Self::WrongKey => f.write_str("key succession names another key"),
+            Self::Conflict => f.write_str("key has more than one successor"),
+            Self::Loop => f.write_str("key succession chain loops"),
+        }
+    }
+}
+
+impl Error for SuccessionError {}
+
+/// An old key handing over to a new one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySuccession {
+    pub old: PublicKey,
+    pub new: PublicKey,
+    /// Milliseconds since the unix epoch.
+    pub created_at: u64,
+    signature: Signature,
+    countersignature: Option<Signature>,
+}
+
+impl KeySuccession {
+    pub fn new(old: &SecretKey, new: &PublicKey, created_at: u64) -> Self {
+        let old_key = old.public_key();
+        let signature = old.sign(&SignedFields { old: &old_key, new, created_at });
+
+        Self {
+            old: old_key,
+            new: new.clone(),
+            created_at,
+            signature,
+            countersignature: None,
+        }
+    }
+
+    /// A statement signed by the old key and countersigned by the new one.
+    pub fn countersigned(old: &SecretKey, new: &SecretKey, created_at: u64) -> Self {
+        let mut succession = Self::new(old, &new.public_key(), created_at);
+        succession.countersign(new).expect("statement names the new key");
+
+        succession
+    }
+
+    /// Countersigns the statement with the key it names.
+    pub fn countersign(&mut self, new: &SecretKey) -> Result<(), SuccessionError> {
+        if new.public_key() != self.new {
+            return Err(SuccessionError::WrongKey);
+        }
+        self.countersignature = Some(new.sign(&Countersignature(self.signed_fields())));
+
+        Ok(())
+    }
+
+    pub fn is_countersigned(&self) -> bool {
+        self.countersignature.is_some()
+    }
+
+    /// Checks the signature, and the countersignature if there is one.
+    pub fn verify(&self) -> Result<(), SuccessionError> {
+        if !self.old.verify(&self.signed_fields(), &self.signature) {
+            return Err(SuccessionError::InvalidSignature);
+        }
+        match &self.countersignature {
+            Some(countersignature) if !self.new.verify(&Countersignature(self.signed_fields()), countersignature) => {
+                Err(SuccessionError::InvalidCountersignature)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn signed_fields(&self) -> SignedFields<'_> {
+        SignedFields {
+            old: &self.old,
+            new: &self.new,
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// The latest key `from` has been replaced by, following the statements in
+/// any order. Every statement on the way must verify, and `from` itself is
+/// returned if it hasn't been replaced.
+pub fn follow(from: &PublicKey, statements: &[KeySuccession]) -> Result<PublicKey, SuccessionError> {
+    let mut current = from.clone();
+    let mut seen = HashSet::from([from.clone()]);
+
+    loop {
+        let mut next: Option<&PublicKey> = None;
+        for statement in statements.iter().filter(|s| s.old == current) {
+            statement.verify()?;
+
+            match next {
+                Some(key) if *key != statement.new => return Err(SuccessionError::Conflict),
+                _ => next = Some(&statement.new),
+            }
+        }
+
+        match next {
+            Some(key) if seen.contains(key) || seen.len() > MAX_CHAIN_LENGTH => return Err(SuccessionError::Loop),
+            Some(key) => {
+                seen.insert(key.clone());
+                current = key.clone();
+            }
+            None => return Ok(current),
+        }
+    }
+}
+
+/// Everything in a key succession except the signatures.
+struct SignedFields<'a> {
+    old: &'a PublicKey,
+    new: &'a PublicKey,
+    created_at: u64,
+}
+
+impl Signable for SignedFields<'_> {
+    const CONTEXT: &'static str = "key succession";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder
+            .bytes(&self.old.bytes())
+            .bytes(&self.new.bytes())
+            .u64(self.created_at);
+    }
+}
+
+// the new key signs the same fields under its own context, so neither
+// signature can stand in for the other
+struct Countersignature<'a>(SignedFields<'a>);
+
+impl Signable for Countersignature<'_> {
+    const CONTEXT: &'static str = "key succession countersignature";
+    const VERSION: u8 = 1;
+
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_key_succession() {
+        let old = SecretKey::generate();
+        let new = SecretKey::generate();
+
+        let mut succession = KeySuccession::new(&old, &new.public_key(), 1000);
+        assert_eq!(succession.verify(), Ok(()));
+        assert!(!succession.is_countersigned());
+
+        assert_eq!(succession.countersign(&SecretKey::generate()), Err(SuccessionError::WrongKey));
+        succession.countersign(&new).unwrap();
+        assert!(succession.is_countersigned());
+
+        let json = serde_json::to_string(&succession).unwrap();
+        assert_eq!(serde_json::from_str::<KeySuccession>(&json).unwrap().verify(), Ok(()));
+
+        let mut tampered = succession.clone();
+        tampered.new = SecretKey::generate().public_key();
+        assert_eq!(tampered.verify(), Err(SuccessionError::InvalidSignature));
+
+        // the countersignature can't be passed off as the old key's signature
+        let mut swapped = succession.clone();
+        swapped.signature = swapped.countersignature.clone().unwrap();
+        assert_eq!(swapped.verify(), Err(SuccessionError::InvalidSignature));
+
+        let mut forged = succession;
+        forged.countersignature = Some(SecretKey::generate().sign(&Countersignature(forged.signed_fields())));
+        assert_eq!(forged.verify(), Err(SuccessionError::InvalidCountersignature));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_follow_chain() {
+        let keys: Vec<SecretKey> = (0..4).map(|_| SecretKey::generate()).collect();
+        let public = |i: usize| keys[i].public_key();
+
+        // statements can arrive in any order
+        let statements = vec![
+            KeySuccession::countersigned(&keys[2], &keys[3], 3),
+            KeySuccession::new(&keys[0], &public(1), 1),
+            KeySuccession::countersigned(&keys[1], &keys[2], 2),
+        ];
+        assert_eq!(follow(&public(0), &statements), Ok(public(3)));
+        assert_eq!(follow(&public(2), &statements), Ok(public(3)));
+        assert_eq!(follow(&public(3), &statements), Ok(public(3)));
+        assert_eq!(follow(&public(0), &[]), Ok(public(0)));
+
+        // the same statement twice is fine
+        let mut repeated = statements.clone();
+        repeated.push(statements[1].clone());
+        assert_eq!(follow(&public(0), &repeated), Ok(public(3)));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_follow_refuses_bad_chains() {
+        let a = SecretKey::generate();
+        let b = SecretKey::generate();
+        let c = SecretKey::generate();
+
+        let mut forged = KeySuccession::new(&a, &b.public_key(), 0);
+        forged.old = c.public_key();
+        assert_eq!(follow(&c.public_key(), &[forged]), Err(SuccessionError::InvalidSignature));
+
+        // whoever else holds the old key could name a successor of their own
+        let conflict = [KeySuccession::new(&a, &b.public_key(), 0), KeySuccession::new(&a, &c.public_key(), 1)];
+        assert_eq!(follow(&a.public_key(), &conflict), Err(SuccessionError::Conflict));
+
+        let cycle = [KeySuccession::new(&a, &b.public_key(), 0), KeySuccession::new(&b, &a.public_key(), 1)];
+        assert_eq!(follow(&a.public_key(), &cycle), Err(SuccessionError::Loop));
+    }
+}
@@ -0,0 +1,128 @@
+//! The binary encoding used on the wire.
+//!
+//! Encoded values are a version byte followed by the value in
+//! [postcard](https://docs.rs/postcard), where keys, signatures and ciphertexts
+//! are raw bytes. Human readable formats like JSON keep using hex strings for
+//! the same fields.
+
+use std::{error::Error, fmt};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The wire format written by this version of the library. It goes up with
+/// every change to the layout of a value:
+///
+/// 1. The first layout.
+/// 2. Messages carry when they expire.
+/// 3. Messages name the message sent before them.
+/// 4. Messages record how their content was padded.
+pub const WIRE_VERSION: u8 = 4;
+
+/// The oldest wire format still read. [`Message`](crate::message::Message)
+/// and [`Frame`](crate::frame::Frame) keep a decoder for it, so older clients
+/// can still be understood.
+pub const LEGACY_WIRE_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum WireError {
+    /// There were no bytes to decode.
+    Empty,
+    /// The bytes were written with a wire format this version doesn't know.
+    UnsupportedVersion(u8),
+    /// The bytes after the version are not a valid encoding.
+    Decode,
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("nothing to decode"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported wire format version {}", v),
+            Self::Decode => f.write_str("failed to decode wire format"),
+        }
+    }
+}
+
+impl Error for WireError {}
+
+pub(crate) fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![WIRE_VERSION];
+    bytes.extend(postcard::to_allocvec(value).expect("values always encode"));
+    bytes
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, WireError> {
+    decode_version(bytes, WIRE_VERSION)
+}
+
+/// Decodes bytes written with wire format `version`, as `T` laid out the way
+/// it was in that version.
+pub(crate) fn decode_version<T: DeserializeOwned>(bytes: &[u8], version: u8) -> Result<T, WireError> {
+    match bytes.split_first() {
+        Some((&v, rest)) if v == version => postcard::from_bytes(rest).map_err(|_| WireError::Decode),
+        Some((&v, _)) => Err(WireError::UnsupportedVersion(v)),
+        None => Err(WireError::Empty),
+    }
+}
+
+/// Serde helpers for byte strings, hex in human readable formats and raw
+/// bytes otherwise. Use with `#[serde(with = "crate::wire::bytes")]` on a
+/// `Vec<u8>` or byte array.
+pub(crate) mod bytes {
+    use std::fmt;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    struct BytesVisitor;
+
+    impl<'de> de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a byte string")
+        }
+
+        fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(value.to_vec())
+        }
+
+        fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(value)
+        }
+    }
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: AsRef<[u8]>,
+        S: Serializer,
+    {
+        let bytes = bytes.as_ref();
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: TryFrom<Vec<u8>>,
+        D: Deserializer<'de>,
+    {
+        let bytes = if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            hex::decode(s).map_err(de::Error::custom)?
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)?
+        };
+
+        let len = bytes.len();
+        T::try_from(bytes).map_err(|_| de::Error::invalid_length(len, &"a byte string of the expected length"))
+    }
+}
@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use muruchat::blob::{BlobError, BlobStore};
+
+const BLOBS_URL: &str = "http://127.0.0.1:8787/blobs";
+
+/// The worker's blob storage, for attachment chunks.
+#[derive(Default)]
+pub struct HttpBlobStore;
+
+impl HttpBlobStore {
+    async fn fetch(method: &str, key: &str, body: Option<&[u8]>) -> Result<web_sys::Response, BlobError> {
+        let mut init = web_sys::RequestInit::new();
+        init.method(method);
+        if let Some(body) = body {
+            init.body(Some(&js_sys::Uint8Array::from(body)));
+        }
+
+        let req = web_sys::Request::new_with_str_and_init(&format!("{}/{}", BLOBS_URL, key), &init)
+            .map_err(|e| BlobError::new(format!("{:?}", e)))?;
+
+        let res = JsFuture::from(web_sys::window().unwrap().fetch_with_request(&req))
+            .await
+            .map_err(|e| BlobError::new(format!("{:?}", e)))?;
+
+        res.dyn_into().map_err(|e| BlobError::new(format!("{:?}", e)))
+    }
+}
+
+#[async_trait(?Send)]
+impl BlobStore for HttpBlobStore {
+    async fn put(&mut self, key: &str, blob: Vec<u8>) -> Result<(), BlobError> {
+        let res = Self::fetch("PUT", key, Some(&blob)).await?;
+
+        match res.ok() {
+            true => Ok(()),
+            false => Err(BlobError::new(format!("upload failed with status {}", res.status()))),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, BlobError> {
+        let res = Self::fetch("GET", key, None).await?;
+
+        match res.status() {
+            404 => return Ok(None),
+            s if !res.ok() => return Err(BlobError::new(format!("download failed with status {}", s))),
+            _ => {}
+        }
+
+        let buffer = res.array_buffer().map_err(|e| BlobError::new(format!("{:?}", e)))?;
+        let buffer = JsFuture::from(buffer).await.map_err(|e| BlobError::new(format!("{:?}", e)))?;
+
+        Ok(Some(js_sys::Uint8Array::new(&buffer).to_vec()))
+    }
+}
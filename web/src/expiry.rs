@@ -0,0 +1,41 @@
+use dioxus::prelude::*;
+use wasm_bindgen::{prelude::Closure, JsCast};
+
+use crate::state::*;
+
+/// How often expired messages are looked for, in milliseconds.
+const INTERVAL: i32 = 1000;
+
+/// The timers a chat can be set to, in milliseconds.
+pub const TIMERS: [(&str, Option<u64>); 5] = [
+    ("Off", None),
+    ("5 minutes", Some(5 * 60 * 1000)),
+    ("1 hour", Some(60 * 60 * 1000)),
+    ("1 day", Some(24 * 60 * 60 * 1000)),
+    ("1 week", Some(7 * 24 * 60 * 60 * 1000)),
+];
+
+/// Deletes disappearing messages from the history once they expire, for as
+/// long as the app is open.
+pub fn use_expiry(cx: &ScopeState) {
+    let set_history = use_set(cx, HISTORY).clone();
+
+    cx.use_hook(move |_| {
+        let callback = Closure::wrap(Box::new(move || {
+            let mut history = History::from_context();
+            if history.expire(js_sys::Date::now() as u64) {
+                history.save();
+                set_history(history);
+            }
+        }) as Box<dyn FnMut()>);
+
+        if let Err(e) = web_sys::window()
+            .unwrap()
+            .set_interval_with_callback_and_timeout_and_arguments_0(callback.as_ref().unchecked_ref(), INTERVAL)
+        {
+            web_sys::console::error_1(&e);
+        }
+        // the interval runs for the life of the app
+        callback.forget();
+    });
+}
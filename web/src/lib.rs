@@ -19,20 +19,39 @@ mod pages {
 }
 
 mod state {
+    mod access_keys;
     mod address_book;
     mod chats;
+    mod devices;
+    mod history;
+    mod integrity;
+    mod sequences;
+    mod undelivered;
     mod user;
 
+    pub use access_keys::*;
     pub use address_book::*;
     pub use chats::*;
+    pub use devices::*;
+    pub use history::*;
+    pub use integrity::*;
+    pub use sequences::*;
+    pub use undelivered::*;
     pub use user::*;
 }
 
+mod blobs;
+mod expiry;
+mod relay;
+
 use dioxus::prelude::*;
 use dioxus_router::{Route, Router};
 use pages::*;
 
 pub fn app(cx: Scope) -> Element {
+    relay::use_relay(&cx);
+    expiry::use_expiry(&cx);
+
     cx.render(rsx! {
         Router {
             Route { to: "/", Home {} },
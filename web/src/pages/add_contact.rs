@@ -1,10 +1,12 @@
 use dioxus::prelude::*;
 use dioxus_router::{use_router, Link};
 use std::str::FromStr;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
 
-use muruchat::pki::PublicKey;
+use muruchat::{attestation, card::ContactCard, pki::PublicKey};
 
-use crate::{components::*, state::*};
+use crate::{components::*, relay::Relay, state::*};
 
 pub fn AddContact(cx: Scope) -> Element {
     let router = use_router(&cx);
@@ -16,6 +18,10 @@ pub fn AddContact(cx: Scope) -> Element {
 
     let nickname = use_state(&cx, || "".to_string());
     let public_key = use_state(&cx, || "".to_string());
+    let card = use_state(&cx, || "".to_string());
+    let card_note = use_state(&cx, || "".to_string());
+    let has_card_note = card_note.get() != "";
+    let vouchers = use_state(&cx, || None::<String>);
 
     let error = use_state(&cx, || "".to_string());
     let has_error = error.get() != "";
@@ -42,6 +48,79 @@ pub fn AddContact(cx: Scope) -> Element {
                         "Enter your contact's public key, and assign them a nickname to start chatting"
                     }
                 }
+                div {
+                    label {
+                        class: "block text-gray-700 text-sm font-bold mb-2",
+                        r#for: "card",
+                        "Contact Card"
+                    }
+                    textarea {
+                        class: "shadow border rounded w-full py-2 px-3 text-gray-700 font-mono",
+                        id: "card",
+                        placeholder: "Paste a murucard1... contact card, or choose a saved one",
+                        value: "{card}",
+                        oninput: move |evt| card.set(evt.value.clone())
+                    }
+                    div {
+                        class: "flex space-x-2 pt-2 text-sm",
+                        input {
+                            id: "card_file",
+                            r#type: "file",
+                            accept: ".murucard,text/plain",
+                        }
+                        button {
+                            class: "text-blue-600 hover:text-blue-700 font-bold",
+                            onclick: move |_| {
+                                let pasted = card.get().clone();
+                                let card = card.clone();
+                                let card_note = card_note.clone();
+                                let nickname = nickname.clone();
+                                let public_key = public_key.clone();
+                                let error = error.clone();
+                                let vouchers = vouchers.clone();
+
+                                wasm_bindgen_futures::spawn_local(async move {
+                                    let text = match selected_card_file() {
+                                        Some(file) => match JsFuture::from(file.text()).await {
+                                            Ok(text) => text.as_string().unwrap_or_default(),
+                                            Err(e) => return error.set(format!("Couldn't read the file: {:?}", e)),
+                                        },
+                                        None => pasted,
+                                    };
+
+                                    match read_card(&text) {
+                                        Ok(c) => {
+                                            card.set(text.trim().to_string());
+                                            nickname.set(c.name.clone());
+                                            public_key.set(c.key.to_string());
+                                            card_note.set(match c.devices.len() {
+                                                0 => format!("Card signed by {}.", c.name),
+                                                n => format!("Card signed by {}, who uses {} other device(s).", c.name, n),
+                                            });
+                                            error.set("".to_string());
+                                            vouchers.set(Some(describe_vouchers(&c.key).await));
+                                        }
+                                        Err(e) => {
+                                            card_note.set("".to_string());
+                                            error.set(e);
+                                        }
+                                    }
+                                });
+                            },
+                            "Read Card"
+                        }
+                    }
+                    has_card_note.then(|| {
+                        let note = card_note.get();
+
+                        rsx!(
+                            p {
+                                class: "text-green-600 text-sm pt-2",
+                                "{note}"
+                            }
+                        )
+                    })
+                }
                 div {
                     label {
                         class: "block text-gray-700 text-sm font-bold mb-2",
@@ -67,9 +146,35 @@ pub fn AddContact(cx: Scope) -> Element {
                         class: "shadow border rounded w-full py-2 px-3 text-gray-700",
                         id: "public_key",
                         r#type: "text",
-                        placeholder: "Enter a public key",
+                        placeholder: "muru1...",
                         value: "{public_key}",
-                        oninput: move |evt| public_key.set(evt.value.clone())
+                        oninput: move |evt| {
+                            vouchers.set(None);
+                            public_key.set(evt.value.clone());
+                        }
+                    }
+                    div {
+                        class: "flex space-x-2 pt-2 text-sm",
+                        button {
+                            class: "text-blue-600 hover:text-blue-700 font-bold",
+                            onclick: move |_| {
+                                let key = match PublicKey::from_str(public_key.trim()) {
+                                    Ok(k) => k,
+                                    Err(e) => return error.set(format!("Invalid public key: {}", e)),
+                                };
+                                let vouchers = vouchers.clone();
+
+                                wasm_bindgen_futures::spawn_local(async move {
+                                    vouchers.set(Some(describe_vouchers(&key).await));
+                                });
+                            },
+                            "Who vouches for this key?"
+                        }
+                        vouchers.as_ref().map(|v| rsx!(
+                            span {
+                                "{v}"
+                            }
+                        ))
                     }
                 }
                 has_error.then(|| {
@@ -97,7 +202,7 @@ pub fn AddContact(cx: Scope) -> Element {
 
                             let public_key = match PublicKey::from_str(public_key) {
                                 Ok(k) => k,
-                                Err(_) => return error.set("Failed to parse public key".to_string()),
+                                Err(e) => return error.set(format!("Invalid public key: {}", e)),
                             };
 
                             if let Some(u) = user {
@@ -126,3 +231,41 @@ pub fn AddContact(cx: Scope) -> Element {
         }
     ))
 }
+
+/// Parses and checks a contact card before anything from it is used.
+fn read_card(text: &str) -> Result<ContactCard, String> {
+    let card = ContactCard::from_str(text).map_err(|e| format!("Invalid contact card: {}", e))?;
+    card.verify().map_err(|e| format!("Invalid contact card: {}", e))?;
+
+    Ok(card)
+}
+
+fn selected_card_file() -> Option<web_sys::File> {
+    web_sys::window()?
+        .document()?
+        .get_element_by_id("card_file")?
+        .dyn_into::<web_sys::HtmlInputElement>()
+        .ok()?
+        .files()?
+        .get(0)
+}
+
+/// Which of the user's verified contacts vouch for a key.
+async fn describe_vouchers(public_key: &PublicKey) -> String {
+    let attestations = match Relay::fetch_attestations(public_key).await {
+        Ok(attestations) => attestations,
+        Err(e) => return format!("Couldn't look up who vouches for this key: {:?}", e),
+    };
+
+    let address_book = AddressBook::from_context();
+    let trusted = address_book.trusted();
+    let names: Vec<String> = attestation::vouchers(public_key, &attestations, &trusted)
+        .iter()
+        .filter_map(|k| address_book.who_is(k))
+        .collect();
+
+    match names.len() {
+        0 => format!("Not verified by any of your {} verified contacts.", trusted.len()),
+        n => format!("Verified by {} of your {} verified contacts: {}.", n, trusted.len(), names.join(", ")),
+    }
+}
@@ -1,11 +1,18 @@
 use dioxus::prelude::*;
-use dioxus_router::{use_route, Link};
-
-use muruchat::{handshake::Challenge, pki::{PublicKey, SecretKey}};
+use dioxus_router::{use_route, use_router, Link};
+use std::str::FromStr;
 
 use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use muruchat::{
+    attachment::Attachment,
+    content::{Content, MessageId, Receipt},
+    conversation::ChainIssue,
+    pki::PublicKey,
+};
 
-use crate::{components::*, state::*};
+use crate::{blobs::HttpBlobStore, components::*, expiry::TIMERS, relay::*, state::*};
 
 pub fn Chat(cx: Scope) -> Element {
     let chats = use_read(&cx, CHATS);
@@ -68,141 +75,722 @@ pub fn Chat(cx: Scope) -> Element {
                 }
             }
         }
-        Tester {
-            chat_id: chat_id
+        div {
+            class: "space-y-8 m-2 md:m-8",
+            IntegrityWarnings {
+                chat_id: chat_id
+            }
+            UndeliveredMessages {
+                chat_id: chat_id
+            }
+            Container {
+                Conversation {
+                    chat_id: chat_id
+                }
+            }
+            DisappearingMessages {
+                chat_id: chat_id
+            }
+            Members {
+                chat_id: chat_id
+            }
         }
-        Container {
+    ))
+}
+
+/// Warns when the hash chain from a peer shows messages that were dropped,
+/// or a history that has split.
+#[inline_props]
+fn IntegrityWarnings<'a>(cx: Scope, chat_id: &'a str) -> Element {
+    let chats = use_read(&cx, CHATS);
+    let address_book = use_read(&cx, ADDRESS_BOOK);
+    // every received message updates the history, which is when the chains change
+    let _history = use_read(&cx, HISTORY);
+
+    let chat = chats.get(chat_id)?;
+    let integrity = Integrity::from_context();
+
+    let warnings: Vec<String> = chat
+        .iter()
+        .flat_map(|public_key| {
+            let nickname = address_book.who_is(public_key).unwrap_or_else(|| "Unknown".to_string());
+            let issues = integrity.issues(public_key);
+
+            let revoked = address_book.is_revoked(public_key).then(|| format!(
+                "{} has revoked their key. Nothing more is sent to it, and anything it sends is dropped.",
+                nickname,
+            ));
+
+            let missing = issues.iter().filter(|i| matches!(i, ChainIssue::Missing(_))).count();
+            let forked = issues.iter().any(|i| matches!(i, ChainIssue::Fork(_)));
+
+            let missing = (missing > 0).then(|| match missing {
+                1 => format!("A message from {} never arrived, or hasn't yet.", nickname),
+                n => format!("{} messages from {} never arrived, or haven't yet.", n, nickname),
+            });
+            let forked = forked.then(|| format!(
+                "Messages from {} don't form a single history, some may have been replayed or sent from a copy of their key.",
+                nickname,
+            ));
+
+            revoked.into_iter().chain(missing).chain(forked)
+        })
+        .collect();
+
+    if warnings.is_empty() {
+        return None;
+    }
+
+    cx.render(rsx!(
+        div {
+            class: "bg-yellow-100 border-l-4 border-yellow-500 text-yellow-700 p-4 space-y-2",
+            warnings.iter().map(|warning| rsx!(
+                p {
+                    "{warning}"
+                }
+            ))
+        }
+    ))
+}
+
+/// Messages to the chat's members the worker wouldn't take sealed. Each waits
+/// for the user to retry, send it unsealed, which shows the worker who it's
+/// from, or discard it.
+#[inline_props]
+fn UndeliveredMessages<'a>(cx: Scope, chat_id: &'a str) -> Element {
+    let chats = use_read(&cx, CHATS);
+    let relay = use_read(&cx, RELAY);
+    let address_book = use_read(&cx, ADDRESS_BOOK);
+    let history = use_read(&cx, HISTORY);
+    let undelivered = use_read(&cx, UNDELIVERED);
+    let set_undelivered = use_set(&cx, UNDELIVERED);
+
+    let chat = chats.get(chat_id)?;
+    let r = relay.as_ref()?;
+
+    let failed: Vec<_> = chat.iter().flat_map(|public_key| undelivered.to(public_key)).collect();
+    if failed.is_empty() {
+        return None;
+    }
+
+    cx.render(rsx!(
+        div {
+            class: "bg-red-100 border-l-4 border-red-500 text-red-700 p-4 space-y-4",
+            failed.into_iter().map(|failed| {
+                let id = failed.message.id;
+                let nickname = address_book.who_is(&failed.message.to).unwrap_or_else(|| "Unknown".to_string());
+                let what = match failed.content.and_then(|content| history.find(chat_id, &content)) {
+                    Some(entry) => format!("Your message \"{}\"", entry.text),
+                    None => "A receipt or key update".to_string(),
+                };
+                let error = &failed.error;
+
+                rsx!(
+                    div {
+                        key: "{id}",
+                        class: "space-y-2",
+                        p {
+                            "{what} to {nickname} couldn't be sent sealed: {error}. Sending it unsealed shows the relay that it's from you."
+                        }
+                        div {
+                            class: "flex space-x-2 text-sm",
+                            button {
+                                class: "hover:text-blue-600",
+                                onclick: move |_| r.retry_sealed(&id),
+                                "Retry"
+                            }
+                            button {
+                                class: "hover:text-blue-600",
+                                onclick: move |_| r.send_unsealed(&id),
+                                "Send unsealed"
+                            }
+                            button {
+                                class: "hover:text-red-600",
+                                onclick: move |_| {
+                                    let mut undelivered = Undelivered::from_context();
+                                    undelivered.take(&id);
+                                    undelivered.save();
+                                    set_undelivered(undelivered);
+                                },
+                                "Discard"
+                            }
+                        }
+                    }
+                )
+            })
+        }
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Draft {
+    New,
+    Reply(MessageId),
+    Edit(MessageId),
+}
+
+#[inline_props]
+fn Conversation<'a>(cx: Scope, chat_id: &'a str) -> Element {
+    let user = use_read(&cx, USER);
+    let relay = use_read(&cx, RELAY);
+    let address_book = use_read(&cx, ADDRESS_BOOK);
+    let history = use_read(&cx, HISTORY);
+
+    let set_chats = use_set(&cx, CHATS);
+    let set_history = use_set(&cx, HISTORY);
+
+    let text = use_state(&cx, || "".to_string());
+    let draft = use_state(&cx, || Draft::New);
+
+    let (u, r) = match (user, relay) {
+        (Some(u), Some(r)) => (u, r),
+        _ => return None,
+    };
+
+    let who = |public_key: &PublicKey| {
+        if *public_key == u.public_key() {
+            "You".to_string()
+        } else {
+            address_book.who_is(public_key).unwrap_or_else(|| "Unknown".to_string())
+        }
+    };
+
+    let entries = history.get(chat_id);
+
+    // everything rendered here has been seen, the receipts go out after the
+    // render so the history isn't changed during it
+    if history.has_unread(chat_id, &u.public_key()) {
+        let (relay, user, set_history, chat_id) = (r.clone(), u.clone(), set_history.clone(), chat_id.to_string());
+        wasm_bindgen_futures::spawn_local(async move {
+            relay.mark_read(&user, &chat_id, &set_history);
+        });
+    }
+
+    let draft_label = match draft.get() {
+        Draft::New => None,
+        Draft::Reply(parent) => Some(format!(
+            "Replying to {}",
+            history.find(chat_id, parent).map(|e| who(&e.from)).unwrap_or_default()
+        )),
+        Draft::Edit(_) => Some("Editing your message".to_string()),
+    };
+
+    cx.render(rsx!(
+        ul {
+            class: "space-y-4",
+            entries.is_empty().then(|| rsx!(
+                li {
+                    class: "text-gray-500",
+                    "No messages yet!"
+                }
+            ))
+            entries.iter().map(|entry| {
+                let id = entry.id;
+                let sender = who(&entry.from);
+                let mine = entry.from == u.public_key();
+                let editable = mine && entry.attachment.is_none();
+
+                let quote = entry.parent.map(|parent| match history.find(chat_id, &parent) {
+                    Some(p) if p.deleted => format!("{}: message deleted", who(&p.from)),
+                    Some(p) => format!("{}: {}", who(&p.from), p.text),
+                    None => "Original message not found".to_string(),
+                });
+
+                let reactions = entry.reactions.iter().map(|(_, emoji)| emoji.as_str()).collect::<Vec<_>>().join(" ");
+
+                let has_reactions = !reactions.is_empty();
+                let actionable = !entry.deleted && !entry.unsupported;
+
+                let status = match (mine, entry.receipt) {
+                    (false, _) => "",
+                    (true, None) => "Sent",
+                    (true, Some(Receipt::Delivered)) => "Delivered",
+                    (true, Some(Receipt::Read)) => "Read",
+                };
+                let disappearing = if entry.expires_at.is_some() { "⏱" } else { "" };
+
+                rsx!(
+                    li {
+                        quote.map(|quote| rsx!(
+                            div {
+                                class: "pl-2 border-l-4 text-sm text-gray-500",
+                                "{quote}"
+                            }
+                        ))
+                        div {
+                            span {
+                                class: "font-bold pr-2",
+                                "{sender}:"
+                            }
+                            if entry.unsupported {
+                                rsx!(span {
+                                    class: "italic text-gray-500",
+                                    "This message needs a newer version of MuruChat"
+                                })
+                            } else if entry.deleted {
+                                rsx!(span {
+                                    class: "italic text-gray-500",
+                                    "Message deleted"
+                                })
+                            } else if let Some(attachment) = &entry.attachment {
+                                rsx!(AttachmentView {
+                                    attachment: attachment
+                                })
+                            } else {
+                                let message = &entry.text;
+                                let edited = if entry.edited { " (edited)" } else { "" };
+                                rsx!(span { "{message}{edited}" })
+                            }
+                        }
+                        has_reactions.then(|| rsx!(
+                            div {
+                                "{reactions}"
+                            }
+                        ))
+                        actionable.then(|| rsx!(
+                            div {
+                                class: "flex space-x-2 text-sm text-gray-500",
+                                span {
+                                    "{status}"
+                                }
+                                span {
+                                    "{disappearing}"
+                                }
+                                button {
+                                    class: "hover:text-blue-600",
+                                    onclick: move |_| draft.set(Draft::Reply(id)),
+                                    "Reply"
+                                }
+                                button {
+                                    class: "hover:text-blue-600",
+                                    onclick: move |_| {
+                                        let content = Content::Reaction { parent: id, emoji: "👍".to_string() };
+                                        r.publish(u, chat_id, content, set_chats, set_history);
+                                    },
+                                    "👍"
+                                }
+                                editable.then(|| rsx!(
+                                    button {
+                                        class: "hover:text-blue-600",
+                                        onclick: move |_| {
+                                            if let Some(e) = history.find(chat_id, &id) {
+                                                text.set(e.text.clone());
+                                            }
+                                            draft.set(Draft::Edit(id));
+                                        },
+                                        "Edit"
+                                    }
+                                ))
+                                mine.then(|| rsx!(
+                                    button {
+                                        class: "hover:text-red-600",
+                                        onclick: move |_| r.publish(u, chat_id, Content::Delete { parent: id }, set_chats, set_history),
+                                        "Delete"
+                                    }
+                                ))
+                            }
+                        ))
+                    }
+                )
+            })
+        }
+        draft_label.map(|label| rsx!(
             div {
-                "..."
+                class: "flex space-x-2 pt-4 md:pt-8 text-sm text-gray-500",
+                span {
+                    "{label}"
+                }
+                button {
+                    class: "hover:text-blue-600",
+                    onclick: move |_| {
+                        draft.set(Draft::New);
+                        text.set("".to_string());
+                    },
+                    "Cancel"
+                }
             }
-            div {
-                "..."
+        ))
+        div {
+            class: "flex space-x-2 pt-4 md:pt-8",
+            input {
+                class: "shadow border rounded w-full py-2 px-3 text-gray-700",
+                r#type: "text",
+                placeholder: "Write a message",
+                value: "{text}",
+                oninput: move |evt| text.set(evt.value.clone())
             }
-            div {
-                "..."
+            button {
+                class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                onclick: move |_| {
+                    if text.get().is_empty() {
+                        return;
+                    }
+
+                    let content = match draft.get() {
+                        Draft::New => Content::text(text.get()),
+                        Draft::Reply(parent) => Content::reply(*parent, text.get()),
+                        Draft::Edit(parent) => Content::Edit { parent: *parent, text: text.to_string() },
+                    };
+                    r.publish(u, chat_id, content, set_chats, set_history);
+
+                    draft.set(Draft::New);
+                    text.set("".to_string());
+                },
+                "Send"
             }
         }
+        Attach {
+            chat_id: chat_id
+        }
     ))
 }
 
 #[inline_props]
-fn Tester<'a>(cx: Scope, chat_id: &'a str) -> Element {
+fn Attach<'a>(cx: Scope, chat_id: &'a str) -> Element {
     let user = use_read(&cx, USER);
+    let relay = use_read(&cx, RELAY);
+
+    let set_chats = use_set(&cx, CHATS);
+    let set_history = use_set(&cx, HISTORY);
+
+    let status = use_state(&cx, || None::<String>);
+    let busy = status.is_some();
 
     cx.render(rsx!(
         div {
-            class: "flex justify-center",
+            class: "flex space-x-2 pt-2 md:pt-4 text-sm text-gray-500",
+            input {
+                id: "attachment",
+                r#type: "file",
+            }
             button {
-                class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                class: "hover:text-blue-600",
+                disabled: "{busy}",
                 onclick: move |_| {
-                    if let Some(u) = user {
-                        if let Err(e) = do_ws(chat_id, &u.public_key(), &u.secret_key()) {
-                            web_sys::console::log_1(&e);
+                    let (u, r) = match (user, relay) {
+                        (Some(u), Some(r)) => (u.clone(), r.clone()),
+                        _ => return,
+                    };
+                    let file = match selected_file() {
+                        Some(f) => f,
+                        None => return,
+                    };
+
+                    let chat_id = chat_id.to_string();
+                    let status = status.clone();
+                    let set_chats = set_chats.clone();
+                    let set_history = set_history.clone();
+
+                    wasm_bindgen_futures::spawn_local(async move {
+                        status.set(Some("Encrypting...".to_string()));
+
+                        match upload(file, |n, total| status.set(Some(format!("Uploading {}/{}", n, total)))).await {
+                            Ok(attachment) => {
+                                r.publish(&u, &chat_id, Content::Attachment(attachment), &set_chats, &set_history);
+                                status.set(None);
+                            }
+                            Err(e) => status.set(Some(format!("Upload failed: {}", e))),
                         }
-                    }
+                    });
                 },
-                div {
-                    "test websocket"
-                }
+                "Attach"
             }
+            status.as_ref().map(|s| rsx!(
+                span {
+                    "{s}"
+                }
+            ))
+        }
+    ))
+}
+
+fn selected_file() -> Option<web_sys::File> {
+    web_sys::window()?
+        .document()?
+        .get_element_by_id("attachment")?
+        .dyn_into::<web_sys::HtmlInputElement>()
+        .ok()?
+        .files()?
+        .get(0)
+}
+
+/// Encrypts a file and uploads its chunks to the worker.
+async fn upload(file: web_sys::File, mut progress: impl FnMut(u32, u32)) -> Result<Attachment, String> {
+    let buffer = JsFuture::from(file.array_buffer()).await.map_err(|e| format!("{:?}", e))?;
+    let data = js_sys::Uint8Array::new(&buffer).to_vec();
+
+    let (attachment, chunks) = Attachment::encrypt(&file.name(), &file.type_(), &data).map_err(|e| e.to_string())?;
+
+    let total = attachment.chunks;
+    attachment
+        .upload(&mut HttpBlobStore, chunks, |n| progress(n, total))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(attachment)
+}
+
+#[derive(Clone)]
+enum Download {
+    Progress(u32, u32),
+    Ready(String),
+    Failed(String),
+}
+
+#[inline_props]
+fn AttachmentView<'a>(cx: Scope, attachment: &'a Attachment) -> Element {
+    let download = use_state(&cx, || None::<Download>);
+
+    let name = &attachment.name;
+    let size = format_size(attachment.size);
+    let action = if attachment.is_image() { "Preview" } else { "Download" };
+
+    cx.render(rsx!(
+        span {
+            "📎 {name} ({size}) "
+        }
+        match download.get() {
+            None => rsx!(
+                button {
+                    class: "text-blue-600 hover:text-blue-700",
+                    onclick: move |_| {
+                        let attachment = (*attachment).clone();
+                        let download = download.clone();
+
+                        wasm_bindgen_futures::spawn_local(async move {
+                            let total = attachment.chunks;
+                            let result = attachment
+                                .download(&HttpBlobStore, |n| download.set(Some(Download::Progress(n, total))))
+                                .await
+                                .map_err(|e| e.to_string())
+                                .and_then(|data| object_url(&data, &attachment.mime_type));
+
+                            download.set(Some(match result {
+                                Ok(url) => Download::Ready(url),
+                                Err(e) => Download::Failed(e),
+                            }));
+                        });
+                    },
+                    "{action}"
+                }
+            ),
+            Some(Download::Progress(n, total)) => rsx!(
+                span {
+                    class: "text-gray-500",
+                    "Downloading {n}/{total}"
+                }
+            ),
+            Some(Download::Ready(url)) => rsx!(
+                attachment.is_image().then(|| rsx!(
+                    img {
+                        class: "max-w-xs pt-2",
+                        src: "{url}",
+                    }
+                ))
+                a {
+                    class: "text-blue-600 hover:text-blue-700",
+                    href: "{url}",
+                    download: "{name}",
+                    "Save"
+                }
+            ),
+            Some(Download::Failed(e)) => rsx!(
+                span {
+                    class: "text-red-600",
+                    "{e}"
+                }
+            ),
         }
     ))
 }
 
-#[derive(Debug)]
-enum FSM {
-    WaitingForChallenge,
-    Authed,
+fn object_url(data: &[u8], mime_type: &str) -> Result<String, String> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(data));
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, web_sys::BlobPropertyBag::new().type_(mime_type))
+        .map_err(|e| format!("{:?}", e))?;
+
+    web_sys::Url::create_object_url_with_blob(&blob).map_err(|e| format!("{:?}", e))
 }
 
-fn do_ws<'a>(_chat_id: &'a str, public_key: &PublicKey, secret_key: &SecretKey) -> Result<(), wasm_bindgen::JsValue> {
-    // open connection
-    let ws = web_sys::WebSocket::new("ws://127.0.0.1:8787/chat")?;
-    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KiB", b as f64 / 1024.0),
+        b => format!("{:.1} MiB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
 
-    let cloned_pk = public_key.clone();
-    let cloned_sk = secret_key.clone();
+#[inline_props]
+fn Members<'a>(cx: Scope, chat_id: &'a str) -> Element {
+    let router = use_router(&cx);
 
-    // create callback
-    let cloned_ws = ws.clone();
+    let user = use_read(&cx, USER);
+    let relay = use_read(&cx, RELAY);
+    let address_book = use_read(&cx, ADDRESS_BOOK);
 
-    let mut fsm = FSM::WaitingForChallenge;
+    let chats = use_read(&cx, CHATS);
+    let set_chats = use_set(&cx, CHATS);
+
+    let selected = use_state(&cx, || "".to_string());
+
+    let chat = chats.get(chat_id)?;
+    let is_group = chat.group().is_some();
+
+    cx.render(rsx!(
+        Container {
+            h2 {
+                class: "font-bold text-xl md:text-3xl",
+                "Members"
+            }
+            ul {
+                class: "pt-4 md:pt-8 space-y-4",
+                chat.iter().map(|public_key| {
+                    let nickname = address_book.who_is(public_key).unwrap_or_else(|| "Unknown".to_string());
+                    let public_key = public_key.clone();
 
-    let onmessage_callback = wasm_bindgen::prelude::Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
-        // Only care about array buffers
-        if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
-            web_sys::console::log_1(&format!("message event, received arraybuffer: {:?}", abuf).into());
+                    rsx!(
+                        li {
+                            div {
+                                class: "flex space-x-2",
+                                div {
+                                    "{nickname}"
+                                }
+                                is_group.then(move || rsx!(
+                                    button {
+                                        class: "text-red-600 hover:text-red-700 font-bold",
+                                        onclick: move |_| {
+                                            if let (Some(u), Some(r), Some(mut chat)) = (user, relay, chats.get(chat_id)) {
+                                                let mut group = match chat.group().cloned() {
+                                                    Some(g) => g,
+                                                    None => return,
+                                                };
 
-            let array = js_sys::Uint8Array::new(&abuf);
-            
-            web_sys::console::log_1(&format!("processing fsm: {:?}", fsm).into());
+                                                // removing rekeys the group, everyone left needs our new sender key
+                                                group.remove_member(&public_key);
+                                                r.distribute(u, &group, group.members());
+                                                chat.set_group(group);
 
-            let next = match fsm {
-                FSM::WaitingForChallenge => {
-                    let sig = match Challenge::from_bytes(&array.to_vec()) {
-                        Ok(c) => c.sign(&cloned_sk),
-                        Err(e) => {
-                            web_sys::console::error_1(&format!("{:?}", e).into());
-                            return;
+                                                let mut new_chats = chats.clone();
+                                                new_chats.add_chat(chat.id(), chat).unwrap();
+                                                new_chats.save();
+                                                set_chats(new_chats);
+                                            }
+                                        },
+                                        "Remove"
+                                    }
+                                ))
+                            }
                         }
-                    };
+                    )
+                })
+            }
+            div {
+                class: "flex space-x-2 pt-4 md:pt-8",
+                select {
+                    class: "shadow border rounded py-2 px-3 text-gray-700",
+                    onchange: move |evt| selected.set(evt.value.clone()),
+                    option {
+                        value: "",
+                        "Add a contact to this chat"
+                    }
+                    address_book
+                        .iter()
+                        .filter(|(public_key, _)| !chat.iter().any(|p| p == *public_key))
+                        .map(|(public_key, nickname)| rsx!(
+                            option {
+                                value: "{public_key}",
+                                "{nickname}"
+                            }
+                        ))
+                }
+                button {
+                    class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                    onclick: move |_| {
+                        let public_key = match PublicKey::from_str(selected.get()) {
+                            Ok(k) => k,
+                            Err(_) => return,
+                        };
 
-                    if let Err(e) = cloned_ws.send_with_u8_array(sig.bytes()) {
-                        web_sys::console::error_1(&e);
-                        return;
-                    };
+                        if let (Some(u), Some(r), Some(chat)) = (user, relay, chats.get(chat_id)) {
+                            // a direct chat turns into a new group chat, an existing group grows
+                            let chat = match chat.group().cloned() {
+                                Some(mut group) => {
+                                    let mut chat = chat.clone();
+                                    group.add_member(public_key);
+                                    chat.set_group(group);
+                                    chat
+                                }
+                                None => chat.group_chat_with(&u.public_key(), public_key),
+                            };
 
-                    if let Err(e) = cloned_ws.send_with_u8_array("World".as_bytes()) {
-                        web_sys::console::error_1(&e);
-                        return;
-                    };
+                            if let Some(group) = chat.group() {
+                                r.distribute(u, group, group.members());
+                            }
 
-                    FSM::Authed
-                },
-                FSM::Authed => {
-                    if let Ok(s) = String::from_utf8(array.to_vec()) {
-                        web_sys::console::log_1(&s.into());
+                            let chat_id = chat.id();
+                            let mut new_chats = chats.clone();
+                            new_chats.add_chat(chat_id.clone(), chat).unwrap();
+                            new_chats.save();
+                            set_chats(new_chats);
+
+                            selected.set("".to_string());
+                            router.push_route(&format!("/chats/{}", chat_id), None, None);
+                        }
+                    },
+                    "Add"
+                }
+            }
+        }
+    ))
+}
+
+#[inline_props]
+fn DisappearingMessages<'a>(cx: Scope, chat_id: &'a str) -> Element {
+    let chats = use_read(&cx, CHATS);
+    let set_chats = use_set(&cx, CHATS);
+
+    let chat = chats.get(chat_id)?;
+    let current = chat.expires_in();
+
+    cx.render(rsx!(
+        Container {
+            h2 {
+                class: "font-bold text-xl md:text-3xl",
+                "Disappearing messages"
+            }
+            p {
+                class: "pt-2 text-gray-500",
+                "Messages sent to this chat are deleted for everyone once the timer runs out."
+            }
+            select {
+                class: "mt-4 shadow border rounded py-2 px-3 text-gray-700",
+                onchange: move |evt| {
+                    let expires_in = TIMERS
+                        .iter()
+                        .find(|(label, _)| *label == evt.value)
+                        .and_then(|(_, expires_in)| *expires_in);
+
+                    if let Some(mut chat) = chats.get(chat_id) {
+                        chat.set_expires_in(expires_in);
+
+                        let mut new_chats = chats.clone();
+                        new_chats.add_chat(chat.id(), chat).unwrap();
+                        new_chats.save();
+                        set_chats(new_chats);
                     }
-                    FSM::Authed
                 },
-            };
-
-            fsm = next;
-
-            // let array = js_sys::Uint8Array::new(&abuf);
-            // let len = array.byte_length() as usize;
-            // web_sys::console::log_1(&format!("Arraybuffer received {}bytes: {:?}", len, array.to_vec()).into());
-            // // here you can for example use Serde Deserialize decode the message
-            // // for demo purposes we switch back to Blob-type and send off another binary message
-            // cloned_ws.set_binary_type(web_sys::BinaryType::Blob);
-            // match cloned_ws.send_with_u8_array(&vec![5, 6, 7, 8]) {
-            //     Ok(_) => web_sys::console::log_1(&format!("binary message successfully sent").into()),
-            //     Err(err) => web_sys::console::log_1(&format!("error sending message: {:?}", err).into()),
-            // }
+                TIMERS.iter().map(|(label, expires_in)| {
+                    let selected = *expires_in == current;
+
+                    rsx!(
+                        option {
+                            value: "{label}",
+                            selected: "{selected}",
+                            "{label}"
+                        }
+                    )
+                })
+            }
         }
-    }) as Box<dyn FnMut(web_sys::MessageEvent)>);
-
-    // set message event handler on WebSocket
-    ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-    // forget the callback to keep it alive
-    onmessage_callback.forget();
-
-    // on error callback just prints the error to the console
-    let onerror_callback = wasm_bindgen::prelude::Closure::wrap(Box::new(move |e: web_sys::ErrorEvent| {
-        web_sys::console::error_1(&e);
-    }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
-    ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
-    onerror_callback.forget();
-
-    // on open call back to send public key to start handshake
-    let cloned_ws = ws.clone();
-    let onopen_callback = wasm_bindgen::prelude::Closure::wrap(Box::new(move |_| {
-        if let Err(e) = cloned_ws.send_with_u8_array(&cloned_pk.bytes()) {
-            web_sys::console::error_1(&e);
-        };
-    }) as Box<dyn FnMut(wasm_bindgen::JsValue)>);
-    ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-    onopen_callback.forget();
-
-    Ok(())
+    ))
 }
@@ -1,7 +1,18 @@
 use dioxus::prelude::*;
 use dioxus_router::{use_router, Link};
+use std::str::FromStr;
 
-use crate::{components::*, state::*};
+use muruchat::{
+    attestation::Attestation,
+    card::{ContactCard, MAX_DEVICES},
+    device::{DeviceCertificate, LinkCode},
+    pki::{PublicKey, SecretKey},
+    revocation::Revocation,
+    safety::SafetyNumber,
+    succession::KeySuccession,
+};
+
+use crate::{components::*, relay::{Relay, RELAY}, state::*};
 
 pub fn Home(cx: Scope) -> Element {
     let user = use_read(&cx, USER);
@@ -15,15 +26,20 @@ pub fn Home(cx: Scope) -> Element {
             }
         }
         match user {
+            Some(u) if u.link_code().is_some() => rsx!(
+                LinkPending { link_code: u.link_code().unwrap_or_default().to_string() }
+            ),
             Some(u) => rsx!(
                 div {
                     class: "pt-8 md:pt-16 space-y-8 md:space-y-16 m-2 md:m-8",
                     KeyViewer {
                         secret_key: u.secret_key().to_string(),
+                        mnemonic: u.secret_key().to_mnemonic(),
                         public_key: u.public_key().to_string(),
                     }
                     Contacts { }
                     Chats { }
+                    u.certificate().is_none().then(|| rsx!(LinkedDevices {}))
                 }
             ),
             _ => rsx!(
@@ -36,6 +52,10 @@ pub fn Home(cx: Scope) -> Element {
 fn Welcome(cx: Scope) -> Element {
     let set_user = use_set(&cx, USER);
 
+    let show_restore = use_state(&cx, || false);
+    let show_link = use_state(&cx, || false);
+    let show_revoke = use_state(&cx, || false);
+
     cx.render(rsx!(
         div {
             class: "text-center pt-8 md:pt-16",
@@ -57,15 +77,364 @@ fn Welcome(cx: Scope) -> Element {
                 }
             }
             button {
-                class: "bg-blue-400 text-white font-bold py-2 px-4 rounded-full",
+                class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                onclick: move |_| {
+                    show_link.set(false);
+                    show_revoke.set(false);
+                    show_restore.set(!show_restore.get());
+                },
                 "upload key"
             }
+            button {
+                class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                onclick: move |_| {
+                    show_restore.set(false);
+                    show_revoke.set(false);
+                    show_link.set(!show_link.get());
+                },
+                "link device"
+            }
+            button {
+                class: "bg-red-600 hover:bg-red-700 text-white font-bold py-2 px-4 rounded-full",
+                onclick: move |_| {
+                    show_restore.set(false);
+                    show_link.set(false);
+                    show_revoke.set(!show_revoke.get());
+                },
+                "revoke key"
+            }
+        }
+        show_restore.get().then(||
+            rsx!(RestoreKey {})
+        )
+        show_link.get().then(||
+            rsx!(LinkDevice {})
+        )
+        show_revoke.get().then(||
+            rsx!(RevokeKey {})
+        )
+    ))
+}
+
+/// Publishes a revocation certificate saved from a key that has since been
+/// lost or stolen.
+fn RevokeKey(cx: Scope) -> Element {
+    let revocation = use_state(&cx, || "".to_string());
+
+    let status = use_state(&cx, || "".to_string());
+    let has_status = status.get() != "";
+
+    cx.render(rsx!(
+        div {
+            class: "flex justify-center pt-4 md:pt-8 m-2",
+            div {
+                class: "w-full md:w-1/2 space-y-2",
+                textarea {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700 font-mono",
+                    rows: "4",
+                    placeholder: "Enter the revocation certificate you saved for your key",
+                    value: "{revocation}",
+                    oninput: move |evt| revocation.set(evt.value.clone())
+                }
+                has_status.then(|| {
+                    let s = status.get();
+
+                    rsx!(
+                        p {
+                            "{s}"
+                        }
+                    )
+                })
+                div {
+                    class: "flex justify-end",
+                    button {
+                        class: "bg-red-600 hover:bg-red-700 text-white font-bold py-2 px-4 rounded-full",
+                        onclick: move |_| {
+                            let parsed = match revocation.parse::<Revocation>() {
+                                Ok(r) if r.verify() => r,
+                                Ok(_) => return status.set("The revocation certificate isn't signed by its key.".to_string()),
+                                Err(e) => return status.set(format!("Invalid revocation certificate: {}", e)),
+                            };
+
+                            let ok = web_sys::window().unwrap().confirm_with_message(
+                                "Revoke this key for good? Nobody will be able to use it again, including you."
+                            ).unwrap();
+                            if !ok {
+                                return;
+                            }
+
+                            let status = status.clone();
+                            wasm_bindgen_futures::spawn_local(async move {
+                                match Relay::revoke(&parsed).await {
+                                    Ok(_) => status.set(format!("{} has been revoked. Your contacts will stop trusting it.", parsed.key)),
+                                    Err(e) => status.set(format!("Couldn't publish the revocation: {:?}", e)),
+                                }
+                            });
+                        },
+                        "revoke"
+                    }
+                }
+            }
+        }
+    ))
+}
+
+/// Restores a key from its recovery phrase, hex, or an encrypted key file.
+fn RestoreKey(cx: Scope) -> Element {
+    let set_user = use_set(&cx, USER);
+
+    let key = use_state(&cx, || "".to_string());
+    let passphrase = use_state(&cx, || "".to_string());
+
+    let error = use_state(&cx, || "".to_string());
+    let has_error = error.get() != "";
+    let is_key_file = key.trim_start().starts_with('{');
+
+    cx.render(rsx!(
+        div {
+            class: "flex justify-center pt-4 md:pt-8 m-2",
+            div {
+                class: "w-full md:w-1/2 space-y-2",
+                textarea {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700 font-mono",
+                    rows: "4",
+                    placeholder: "Enter your 24 word recovery phrase, secret key, or encrypted key file",
+                    value: "{key}",
+                    oninput: move |evt| key.set(evt.value.clone())
+                }
+                is_key_file.then(|| rsx!(
+                    input {
+                        class: "shadow border rounded w-full py-2 px-3 text-gray-700",
+                        r#type: "password",
+                        placeholder: "Passphrase",
+                        value: "{passphrase}",
+                        oninput: move |evt| passphrase.set(evt.value.clone())
+                    }
+                ))
+                has_error.then(|| {
+                    let e = error.get();
+
+                    rsx!(
+                        p {
+                            class: "text-red-600",
+                            "{e}"
+                        }
+                    )
+                })
+                div {
+                    class: "flex justify-end",
+                    button {
+                        class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                        onclick: move |_| {
+                            match restore(key.get(), passphrase.get()) {
+                                Ok(secret_key) => {
+                                    let u = User::from_secret_key(secret_key);
+                                    u.save();
+                                    set_user(Some(u));
+                                }
+                                Err(e) => error.set(e),
+                            }
+                        },
+                        "restore"
+                    }
+                }
+            }
+        }
+    ))
+}
+
+/// Makes a key for this browser and a link code for it, to be read in on the
+/// device holding the identity.
+fn LinkDevice(cx: Scope) -> Element {
+    let set_user = use_set(&cx, USER);
+
+    let name = use_state(&cx, || "".to_string());
+
+    let error = use_state(&cx, || "".to_string());
+    let has_error = error.get() != "";
+
+    cx.render(rsx!(
+        div {
+            class: "flex justify-center pt-4 md:pt-8 m-2",
+            div {
+                class: "w-full md:w-1/2 space-y-2",
+                input {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700",
+                    r#type: "text",
+                    placeholder: "Name this device",
+                    value: "{name}",
+                    oninput: move |evt| name.set(evt.value.clone())
+                }
+                has_error.then(|| {
+                    let e = error.get();
+
+                    rsx!(
+                        p {
+                            class: "text-red-600",
+                            "{e}"
+                        }
+                    )
+                })
+                div {
+                    class: "flex justify-end",
+                    button {
+                        class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                        onclick: move |_| {
+                            if name.trim().is_empty() {
+                                return error.set("Please enter a name for this device.".to_string());
+                            }
+
+                            let secret_key = SecretKey::generate();
+                            let code = LinkCode::new(&secret_key, name.trim(), js_sys::Date::now() as u64);
+
+                            let mut u = User::from_secret_key(secret_key);
+                            u.set_link_code(code.to_string());
+                            u.save();
+                            set_user(Some(u));
+                        },
+                        "create link code"
+                    }
+                }
+            }
+        }
+    ))
+}
+
+/// Shows the link code until the identity device has certified this one.
+#[inline_props]
+fn LinkPending(cx: Scope, link_code: String) -> Element {
+    let user = use_read(&cx, USER);
+    let set_user = use_set(&cx, USER);
+
+    cx.render(rsx!(
+        div {
+            class: "text-center pt-8 md:pt-16 m-2",
+            p {
+                "On the device you use MuruChat on, paste this code under \"Devices\" to link this one. It works for ten minutes."
+            }
+        }
+        KeyInspector { pem: link_code.to_string() }
+        div {
+            class: "flex justify-center pt-4",
+            button {
+                class: "bg-red-600 hover:bg-red-700 text-white font-bold py-2 px-4 rounded-full",
+                onclick: move |_| {
+                    if let Some(u) = user {
+                        u.delete();
+                    }
+                    set_user(None);
+                },
+                "cancel"
+            }
+        }
+    ))
+}
+
+/// The devices linked to the user's identity, and linking a new one from its
+/// link code.
+fn LinkedDevices(cx: Scope) -> Element {
+    let user = use_read(&cx, USER);
+    let relay = use_read(&cx, RELAY);
+
+    let code = use_state(&cx, || "".to_string());
+
+    let error = use_state(&cx, || "".to_string());
+    let has_error = error.get() != "";
+
+    let devices = match user {
+        Some(u) => Devices::from_context().get(&u.public_key()).to_vec(),
+        None => Vec::new(),
+    };
+    let no_devices = devices.is_empty();
+
+    cx.render(rsx!(
+        Container {
+            h2 {
+                class: "font-bold text-xl md:text-3xl",
+                "Devices"
+            }
+            ul {
+                class: "pt-4 md:pt-8 space-y-4",
+                no_devices.then(|| rsx!(
+                    li {
+                        "No linked devices yet!"
+                    }
+                ))
+                devices.iter().map(|certificate| {
+                    let name = &certificate.name;
+
+                    rsx!(
+                        li {
+                            "{name}"
+                        }
+                    )
+                })
+            }
+            div {
+                class: "pt-4 space-y-2",
+                textarea {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700 font-mono",
+                    rows: "3",
+                    placeholder: "Paste the link code shown on the new device",
+                    value: "{code}",
+                    oninput: move |evt| code.set(evt.value.clone())
+                }
+                has_error.then(|| {
+                    let e = error.get();
+
+                    rsx!(
+                        p {
+                            class: "text-red-600",
+                            "{e}"
+                        }
+                    )
+                })
+                button {
+                    class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                    onclick: move |_| {
+                        let link_code = match code.parse::<LinkCode>() {
+                            Ok(c) => c,
+                            Err(e) => return error.set(format!("Invalid link code: {}", e)),
+                        };
+
+                        if let (Some(u), Some(r)) = (user, relay) {
+                            match DeviceCertificate::link(&u.secret_key(), &link_code, js_sys::Date::now() as u64) {
+                                Ok(certificate) => {
+                                    let mut devices = Devices::from_context();
+                                    devices.insert(certificate.clone());
+                                    devices.save();
+
+                                    r.certify(certificate);
+                                    error.set("".to_string());
+                                    code.set("".to_string());
+                                }
+                                Err(e) => error.set(format!("Can't link device: {}", e)),
+                            }
+                        }
+                    },
+                    "link device"
+                }
+            }
         }
     ))
 }
 
+fn restore(key: &str, passphrase: &str) -> Result<SecretKey, String> {
+    let key = key.trim();
+
+    if key.is_empty() {
+        Err("Please enter a recovery phrase or secret key.".to_string())
+    } else if key.starts_with('{') {
+        SecretKey::import_encrypted(key, passphrase).map_err(|e| format!("Invalid key file: {}", e))
+    } else if key.split_whitespace().count() > 1 {
+        SecretKey::from_mnemonic(key).map_err(|e| format!("Invalid recovery phrase: {}", e))
+    } else {
+        SecretKey::from_str(key).map_err(|e| format!("Invalid secret key: {}", e))
+    }
+}
+
 #[inline_props]
-fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
+fn KeyViewer(cx: Scope, secret_key: String, mnemonic: String, public_key: String) -> Element {
     let user = use_read(&cx, USER);
     let set_user = use_set(&cx, USER);
 
@@ -75,8 +444,26 @@ fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
     let chats = use_read(&cx, CHATS);
     let set_chats = use_set(&cx, CHATS);
 
+    let history = use_read(&cx, HISTORY);
+    let set_history = use_set(&cx, HISTORY);
+
+    let set_undelivered = use_set(&cx, UNDELIVERED);
+
+    let relay = use_read(&cx, RELAY);
+    let set_relay = use_set(&cx, RELAY);
+
+    // a linked device's key isn't the identity, so it can't replace or revoke it
+    let is_identity = user.as_ref().map_or(false, |u| u.certificate().is_none());
+
     let show_public_key = use_state(&cx, || false);
     let show_secret_key = use_state(&cx, || false);
+    let show_export = use_state(&cx, || false);
+    let show_revocation = use_state(&cx, || false);
+    let show_card = use_state(&cx, || false);
+
+    // six words a line is easier to copy down
+    let words: Vec<&str> = mnemonic.split(' ').collect();
+    let mnemonic = words.chunks(6).map(|line| line.join(" ")).collect::<Vec<String>>().join("\n");
 
     cx.render(rsx! (
         div {
@@ -93,6 +480,9 @@ fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
                     onclick: |_| {
                         if !*show_public_key.get() {
                             show_secret_key.set(false);
+                            show_export.set(false);
+                            show_revocation.set(false);
+                            show_card.set(false);
                         }
                         show_public_key.set(!show_public_key.get());
                     },
@@ -122,6 +512,9 @@ fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
                     onclick: |_| {
                         if !*show_secret_key.get() {
                             show_public_key.set(false);
+                            show_export.set(false);
+                            show_revocation.set(false);
+                            show_card.set(false);
                         }
                         show_secret_key.set(!show_secret_key.get());
                     },
@@ -146,6 +539,76 @@ fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
                         },
                     }
                 }
+                button {
+                    class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                    onclick: |_| {
+                        if !*show_export.get() {
+                            show_public_key.set(false);
+                            show_secret_key.set(false);
+                            show_revocation.set(false);
+                            show_card.set(false);
+                        }
+                        show_export.set(!show_export.get());
+                    },
+                    "export key"
+                }
+                is_identity.then(|| rsx!(
+                    button {
+                        class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                        onclick: |_| {
+                            if !*show_revocation.get() {
+                                show_public_key.set(false);
+                                show_secret_key.set(false);
+                                show_export.set(false);
+                                show_card.set(false);
+                            }
+                            show_revocation.set(!show_revocation.get());
+                        },
+                        "revocation certificate"
+                    }
+                    button {
+                        class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                        onclick: |_| {
+                            if !*show_card.get() {
+                                show_public_key.set(false);
+                                show_secret_key.set(false);
+                                show_export.set(false);
+                                show_revocation.set(false);
+                            }
+                            show_card.set(!show_card.get());
+                        },
+                        "contact card"
+                    }
+                ))
+                is_identity.then(|| rsx!(
+                    button {
+                        class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                        onclick: move |_| {
+                            let ok = web_sys::window().unwrap().confirm_with_message(
+                                "Replace your encryption key with a new one? Your contacts will be moved over to the new key automatically, but devices linked to the old key have to be linked again. Back up the new key once it is made."
+                            ).unwrap();
+                            if !ok {
+                                return;
+                            }
+
+                            if let (Some(u), Some(r)) = (user, relay) {
+                                let secret_key = SecretKey::generate_with(u.secret_key().suite());
+                                r.succeed(KeySuccession::countersigned(&u.secret_key(), &secret_key, js_sys::Date::now() as u64));
+
+                                // reconnect with the new key, and introduce it to every contact again
+                                r.close();
+                                set_relay(None);
+                                Sequences::default().delete();
+                                Devices::default().delete();
+
+                                let new_user = User::from_secret_key(secret_key);
+                                new_user.save();
+                                set_user(Some(new_user));
+                            }
+                        },
+                        "rotate key"
+                    }
+                ))
                 button {
                     class: "bg-red-600 hover:bg-red-700 text-white font-bold py-2 px-4 rounded-full",
                     onclick: move |_| {
@@ -163,6 +626,17 @@ fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
 
                             chats.delete();
                             set_chats(Chats::default());
+
+                            history.delete();
+                            set_history(History::default());
+
+                            Sequences::default().delete();
+                            AccessKeys::default().delete();
+                            Integrity::default().delete();
+                            Devices::default().delete();
+
+                            Undelivered::default().delete();
+                            set_undelivered(Undelivered::default());
                         }
                     },
                     "clear session"
@@ -172,7 +646,32 @@ fn KeyViewer(cx: Scope, secret_key: String, public_key: String) -> Element {
                 rsx!(KeyInspector { pem: public_key.to_string() })
             )
             show_secret_key.get().then(||
-                rsx!(KeyInspector { pem: secret_key.to_string() })
+                rsx!(
+                    KeyInspector { pem: mnemonic.to_string() }
+                    KeyInspector { pem: secret_key.to_string() }
+                )
+            )
+            show_export.get().then(||
+                rsx!(KeyExport {})
+            )
+            show_revocation.get().then(|| {
+                let revocation = user
+                    .as_ref()
+                    .map(|u| Revocation::new(&u.secret_key(), js_sys::Date::now() as u64).to_string())
+                    .unwrap_or_default();
+
+                rsx!(
+                    div {
+                        class: "text-center pt-4",
+                        p {
+                            "Keep this somewhere safe, away from this browser. If your key is ever lost or stolen, publish it under \"revoke key\" so nobody can use the key again."
+                        }
+                    }
+                    KeyInspector { pem: revocation }
+                )
+            })
+            show_card.get().then(||
+                rsx!(ContactCardExport {})
             )
         }
     ))
@@ -185,6 +684,8 @@ fn Contacts(cx: Scope) -> Element {
     let chats = use_read(&cx, CHATS);
     let set_chats = use_set(&cx, CHATS);
 
+    let verifying = use_state(&cx, || None::<PublicKey>);
+
     let visible_count = 5;
 
     let show_more = address_book.len() > visible_count;
@@ -206,6 +707,9 @@ fn Contacts(cx: Scope) -> Element {
             ul {
                 class: "pt-4 md:pt-8 space-y-4",
                 address_book.iter().take(5).map(|(public_key, nickname)| {
+                    let is_verified = address_book.is_verified(public_key);
+                    let is_revoked = address_book.is_revoked(public_key);
+
                     rsx!(
                         li {
                             div {
@@ -213,6 +717,29 @@ fn Contacts(cx: Scope) -> Element {
                                 div {
                                     "{nickname}"
                                 }
+                                is_verified.then(|| rsx!(
+                                    span {
+                                        class: "text-green-600",
+                                        title: "verified",
+                                        "✓"
+                                    }
+                                ))
+                                is_revoked.then(|| rsx!(
+                                    span {
+                                        class: "text-red-600",
+                                        "revoked"
+                                    }
+                                ))
+                                button {
+                                    class: "text-blue-600 hover:text-blue-700",
+                                    onclick: move |_| {
+                                        match verifying.get() {
+                                            Some(k) if k == public_key => verifying.set(None),
+                                            _ => verifying.set(Some(public_key.clone())),
+                                        }
+                                    },
+                                    "safety number"
+                                }
                                 button {
                                     class: "text-blue-600 hover:text-blue-700 font-bold flex",
                                     onclick: move |_| {
@@ -244,10 +771,90 @@ fn Contacts(cx: Scope) -> Element {
                     )
                 )
             }
+            verifying.get().as_ref().map(|public_key| rsx!(
+                Verification { public_key: public_key.clone() }
+            ))
         }
     })
 }
 
+/// A contact's safety number, to compare with theirs before marking them
+/// verified.
+#[inline_props]
+fn Verification(cx: Scope, public_key: PublicKey) -> Element {
+    let user = use_read(&cx, USER);
+
+    let address_book = use_read(&cx, ADDRESS_BOOK);
+    let set_address_book = use_set(&cx, ADDRESS_BOOK);
+
+    let vouch_status = use_state(&cx, || None::<String>);
+
+    let u = user.as_ref()?;
+    let number = SafetyNumber::new(&u.public_key(), public_key);
+    let words = number.words().join(" ");
+    let nickname = address_book.who_is(public_key).unwrap_or_default();
+    let is_verified = address_book.is_verified(public_key);
+    // contacts know the identity, not this device's key
+    let can_vouch = is_verified && u.certificate().is_none();
+
+    cx.render(rsx!(
+        div {
+            class: "pt-4 md:pt-8 space-y-2",
+            p {
+                "Compare these numbers with {nickname}, in person or over a call. If they match, nobody has swapped their key."
+            }
+            div {
+                class: "grid grid-cols-4 gap-2 w-fit p-2 bg-gray-300 rounded font-mono",
+                number.blocks().into_iter().map(|block| rsx!(
+                    div { "{block}" }
+                ))
+            }
+            p {
+                class: "font-mono",
+                "{words}"
+            }
+            button {
+                class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                onclick: move |_| {
+                    let mut addrs = address_book.clone();
+                    addrs.set_verified(public_key, !is_verified);
+                    addrs.save();
+                    set_address_book(addrs);
+                },
+                match is_verified {
+                    true => rsx!("unmark verified"),
+                    false => rsx!("mark verified"),
+                }
+            }
+            can_vouch.then(|| rsx!(
+                p {
+                    "Vouching for {nickname} publishes that you verified their key, so people who verified you can trust it too. Anyone can see who you vouch for."
+                }
+                button {
+                    class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                    onclick: move |_| {
+                        let attestation = Attestation::new(&u.secret_key(), public_key, js_sys::Date::now() as u64);
+                        let vouch_status = vouch_status.clone();
+
+                        wasm_bindgen_futures::spawn_local(async move {
+                            vouch_status.set(Some(match Relay::attest(&attestation).await {
+                                Ok(()) => "Published.".to_string(),
+                                Err(e) => format!("Couldn't publish: {:?}", e),
+                            }));
+                        });
+                    },
+                    "vouch publicly"
+                }
+            ))
+            vouch_status.as_ref().map(|s| rsx!(
+                p {
+                    "{s}"
+                }
+            ))
+        }
+    ))
+}
+
 fn Chats(cx: Scope) -> Element {
     let chats = use_read(&cx, CHATS);
     let address_book = use_read(&cx, ADDRESS_BOOK);
@@ -295,6 +902,149 @@ fn Chats(cx: Scope) -> Element {
     ))
 }
 
+/// Makes a signed contact card to give to people who want to add this identity.
+fn ContactCardExport(cx: Scope) -> Element {
+    let user = use_read(&cx, USER);
+
+    let name = use_state(&cx, || "".to_string());
+    let card = use_state(&cx, || "".to_string());
+
+    let error = use_state(&cx, || "".to_string());
+    let has_error = error.get() != "";
+    let has_card = card.get() != "";
+
+    cx.render(rsx!(
+        div {
+            class: "flex justify-center mt-4 md:mt-8",
+            div {
+                class: "space-y-2",
+                input {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700",
+                    r#type: "text",
+                    placeholder: "The name to suggest to contacts",
+                    value: "{name}",
+                    oninput: move |evt| name.set(evt.value.clone())
+                }
+                has_error.then(|| {
+                    let e = error.get();
+
+                    rsx!(
+                        p {
+                            class: "text-red-600",
+                            "{e}"
+                        }
+                    )
+                })
+                button {
+                    class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                    onclick: move |_| {
+                        if name.trim().is_empty() {
+                            return error.set("Please enter a name.".to_string());
+                        }
+
+                        if let Some(u) = user {
+                            let devices: Vec<PublicKey> = Devices::from_context()
+                                .get(&u.public_key())
+                                .iter()
+                                .map(|c| c.device.clone())
+                                .take(MAX_DEVICES)
+                                .collect();
+
+                            match ContactCard::new(&u.secret_key(), name.trim(), None, &devices, js_sys::Date::now() as u64) {
+                                Ok(c) => {
+                                    error.set("".to_string());
+                                    card.set(c.to_string());
+                                }
+                                Err(e) => error.set(e.to_string()),
+                            }
+                        }
+                    },
+                    "create card"
+                }
+            }
+        }
+        has_card.then(|| rsx!(
+            KeyInspector { pem: card.to_string() }
+            div {
+                class: "flex justify-center",
+                a {
+                    class: "text-blue-600 hover:text-blue-700",
+                    href: "data:text/plain;charset=utf-8,{card}",
+                    download: "contact.murucard",
+                    "Save as file"
+                }
+            }
+        ))
+    ))
+}
+
+/// Encrypts the secret key with a passphrase, so it can be backed up or moved
+/// to another device.
+fn KeyExport(cx: Scope) -> Element {
+    let user = use_read(&cx, USER);
+
+    let passphrase = use_state(&cx, || "".to_string());
+    let confirmation = use_state(&cx, || "".to_string());
+    let key_file = use_state(&cx, || "".to_string());
+
+    let error = use_state(&cx, || "".to_string());
+    let has_error = error.get() != "";
+    let has_key_file = key_file.get() != "";
+
+    cx.render(rsx!(
+        div {
+            class: "flex justify-center mt-4 md:mt-8",
+            div {
+                class: "space-y-2",
+                input {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700",
+                    r#type: "password",
+                    placeholder: "Passphrase",
+                    value: "{passphrase}",
+                    oninput: move |evt| passphrase.set(evt.value.clone())
+                }
+                input {
+                    class: "shadow border rounded w-full py-2 px-3 text-gray-700",
+                    r#type: "password",
+                    placeholder: "Repeat passphrase",
+                    value: "{confirmation}",
+                    oninput: move |evt| confirmation.set(evt.value.clone())
+                }
+                has_error.then(|| {
+                    let e = error.get();
+
+                    rsx!(
+                        p {
+                            class: "text-red-600",
+                            "{e}"
+                        }
+                    )
+                })
+                button {
+                    class: "bg-blue-600 hover:bg-blue-700 text-white font-bold py-2 px-4 rounded-full",
+                    onclick: move |_| {
+                        if passphrase.get() == "" {
+                            return error.set("Please enter a passphrase.".to_string());
+                        }
+                        if passphrase.get() != confirmation.get() {
+                            return error.set("The passphrases don't match.".to_string());
+                        }
+
+                        if let Some(u) = user {
+                            error.set("".to_string());
+                            key_file.set(u.secret_key().export_encrypted(passphrase));
+                        }
+                    },
+                    "encrypt"
+                }
+            }
+        }
+        has_key_file.then(||
+            rsx!(KeyInspector { pem: key_file.to_string() })
+        )
+    ))
+}
+
 #[inline_props]
 fn KeyInspector(cx: Scope, pem: String) -> Element {
     cx.render(rsx!(
@@ -315,7 +1065,7 @@ fn KeyInspector(cx: Scope, pem: String) -> Element {
             div {
                 class: "flex bg-gray-300 rounded",
                 div {
-                    class: "p-2 font-mono",
+                    class: "p-2 font-mono break-all",
                     pem.split('\n').map(|l| rsx!(
                         div { "{l}" }
                     ))
@@ -0,0 +1,907 @@
+use dioxus::prelude::*;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use muruchat::{
+    attestation::Attestation,
+    content::{Content, MessageId, Receipt},
+    conversation::MessageHash,
+    device::DeviceCertificate,
+    frame::Frame,
+    group::Group,
+    handshake::Challenge,
+    message::{Message, MessageOptions},
+    multi::MultiMessage,
+    pki::PublicKey,
+    replay::Sequence,
+    revocation::Revocation,
+    sealed::{AccessKey, SealedMessage},
+    succession::{self, KeySuccession, MAX_CHAIN_LENGTH},
+};
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::state::*;
+
+const RELAY_URL: &str = "ws://127.0.0.1:8787/chat";
+const SEALED_URL: &str = "http://127.0.0.1:8787/sealed";
+const DEVICES_URL: &str = "http://127.0.0.1:8787/devices";
+const SUCCESSION_URL: &str = "http://127.0.0.1:8787/succession";
+const REVOCATIONS_URL: &str = "http://127.0.0.1:8787/revocations";
+const ATTESTATIONS_URL: &str = "http://127.0.0.1:8787/attestations";
+
+pub static RELAY: Atom<Option<Relay>> = |_| None;
+
+/// Keeps a relay connection open while there is a user, and closes it when
+/// the session is cleared.
+pub fn use_relay(cx: &ScopeState) {
+    let user = use_read(cx, USER);
+    let set_user = use_set(cx, USER);
+    let set_address_book = use_set(cx, ADDRESS_BOOK);
+    let relay = use_read(cx, RELAY);
+    let set_relay = use_set(cx, RELAY);
+    let set_chats = use_set(cx, CHATS);
+    let set_history = use_set(cx, HISTORY);
+    let set_undelivered = use_set(cx, UNDELIVERED);
+
+    match (user, relay) {
+        (Some(u), None) => match Relay::connect(
+            u.clone(),
+            set_user.clone(),
+            set_address_book.clone(),
+            set_chats.clone(),
+            set_history.clone(),
+            set_undelivered.clone(),
+        ) {
+            Ok(r) => set_relay(Some(r)),
+            Err(e) => web_sys::console::error_1(&e),
+        },
+        (None, Some(r)) => {
+            r.close();
+            set_relay(None);
+        }
+        _ => {}
+    }
+}
+
+#[derive(Debug)]
+enum Fsm {
+    WaitingForChallenge,
+    Authed,
+}
+
+/// A websocket to the worker, authenticated as the user.
+#[derive(Clone)]
+pub struct Relay {
+    ws: web_sys::WebSocket,
+    // frames sent before the handshake completes are held back
+    outbox: Rc<RefCell<Option<Vec<Vec<u8>>>>>,
+    set_undelivered: Rc<dyn Fn(Undelivered)>,
+}
+
+impl Relay {
+    pub fn connect(
+        user: User,
+        set_user: Rc<dyn Fn(Option<User>)>,
+        set_address_book: Rc<dyn Fn(AddressBook)>,
+        set_chats: Rc<dyn Fn(Chats)>,
+        set_history: Rc<dyn Fn(History)>,
+        set_undelivered: Rc<dyn Fn(Undelivered)>,
+    ) -> Result<Self, wasm_bindgen::JsValue> {
+        // open connection, a linked device has an inbox of its own
+        let ws = web_sys::WebSocket::new(&format!("{}/{}", RELAY_URL, user.device_key()))?;
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        let relay = Self {
+            ws: ws.clone(),
+            outbox: Rc::new(RefCell::new(Some(Vec::new()))),
+            set_undelivered,
+        };
+
+        let cloned_relay = relay.clone();
+        let mut cloned_user = user.clone();
+
+        let mut fsm = Fsm::WaitingForChallenge;
+
+        let onmessage_callback = wasm_bindgen::prelude::Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
+            // Only care about array buffers
+            if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+                let bytes = js_sys::Uint8Array::new(&abuf).to_vec();
+
+                let next = match fsm {
+                    Fsm::WaitingForChallenge => {
+                        let sig = match Challenge::from_bytes(&bytes) {
+                            Ok(c) => c.sign(&cloned_user.secret_key()),
+                            Err(e) => {
+                                web_sys::console::error_1(&format!("invalid challenge: {}", e).into());
+                                return;
+                            }
+                        };
+
+                        if let Err(e) = cloned_relay.ws.send_with_u8_array(&sig.bytes()) {
+                            web_sys::console::error_1(&e);
+                            return;
+                        };
+
+                        // let the worker accept sealed messages made with our access key
+                        let access = Frame::Access(AccessKey::derive(&cloned_user.secret_key())).to_bytes();
+                        if let Err(e) = cloned_relay.ws.send_with_u8_array(&access) {
+                            web_sys::console::error_1(&e);
+                        }
+
+                        cloned_relay.flush();
+                        Self::refresh_devices(&cloned_user);
+                        cloned_relay.refresh_successions(&cloned_user, &set_address_book, &set_chats, &set_history);
+                        Self::refresh_revocations(&set_address_book);
+
+                        Fsm::Authed
+                    },
+                    Fsm::Authed => {
+                        match Frame::from_bytes(&bytes) {
+                            Ok(Frame::Deliver(message)) => {
+                                cloned_relay.receive(&cloned_user, message, &set_chats, &set_history)
+                            },
+                            Ok(Frame::DeliverSealed(sealed)) => match sealed.open(&cloned_user.secret_key()) {
+                                Ok(message) => cloned_relay.receive(&cloned_user, message, &set_chats, &set_history),
+                                Err(e) => web_sys::console::error_1(&e.to_string().into()),
+                            },
+                            Ok(Frame::DeliverMulti(message)) => {
+                                cloned_relay.receive_multi(&cloned_user, message, &set_chats, &set_history)
+                            },
+                            Ok(Frame::Linked(certificate)) => {
+                                if certificate.device == cloned_user.device_key() && certificate.verify() {
+                                    cloned_user.set_certificate(certificate);
+                                    cloned_user.save();
+                                    set_user(Some(cloned_user.clone()));
+                                    Self::refresh_devices(&cloned_user);
+                                }
+                            },
+                            Ok(Frame::Succeeded(succession)) => {
+                                let old = succession.old.clone();
+                                cloned_relay.migrate(&cloned_user, &old, &[succession], &set_address_book, &set_chats, &set_history)
+                            },
+                            _ => web_sys::console::error_1(&"ignoring invalid frame".into()),
+                        }
+
+                        Fsm::Authed
+                    },
+                };
+
+                fsm = next;
+            }
+        }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+
+        // set message event handler on WebSocket
+        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+        // forget the callback to keep it alive
+        onmessage_callback.forget();
+
+        // on error callback just prints the error to the console
+        let onerror_callback = wasm_bindgen::prelude::Closure::wrap(Box::new(move |e: web_sys::ErrorEvent| {
+            web_sys::console::error_1(&e);
+        }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
+        ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+        onerror_callback.forget();
+
+        // on open call back to send public key to start handshake
+        let cloned_ws = ws.clone();
+        let public_key = user.device_key();
+        let onopen_callback = wasm_bindgen::prelude::Closure::wrap(Box::new(move |_| {
+            if let Err(e) = cloned_ws.send_with_u8_array(&public_key.bytes()) {
+                web_sys::console::error_1(&e);
+            };
+        }) as Box<dyn FnMut(wasm_bindgen::JsValue)>);
+        ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+        onopen_callback.forget();
+
+        Ok(relay)
+    }
+
+    pub fn close(&self) {
+        if let Err(e) = self.ws.close() {
+            web_sys::console::error_1(&e);
+        }
+    }
+
+    /// Sends a message sealed if the recipient has given us their access key,
+    /// otherwise over the websocket. `content` is the history entry the
+    /// message carries, to show the user if it can't be sent sealed.
+    fn send(&self, message: Message, content: Option<MessageId>) {
+        match AccessKeys::from_context().get(&message.to) {
+            Some(access_key) => {
+                let sealed = SealedMessage::seal(&message, access_key);
+                let set_undelivered = self.set_undelivered.clone();
+
+                wasm_bindgen_futures::spawn_local(async move {
+                    // sending it over the websocket would show the worker who
+                    // it's from, that is left for the user to decide
+                    if let Err(e) = Self::send_sealed(&sealed).await {
+                        web_sys::console::error_1(&e);
+
+                        let error = e.as_string().unwrap_or_else(|| "the relay couldn't be reached".to_string());
+                        let mut undelivered = Undelivered::from_context();
+                        undelivered.push(message, content, error);
+                        undelivered.save();
+                        set_undelivered(undelivered);
+                    }
+                });
+            }
+            None => self.send_frame(Frame::Send(message)),
+        }
+    }
+
+    /// Tries sending a message the worker wouldn't take sealed again. A
+    /// message that fails again is held again.
+    pub fn retry_sealed(&self, id: &MessageId) {
+        if let Some(failed) = self.take_undelivered(id) {
+            self.send(failed.message, failed.content);
+        }
+    }
+
+    /// Sends a message the worker wouldn't take sealed over the websocket, at
+    /// the user's request. The worker sees who it's from.
+    pub fn send_unsealed(&self, id: &MessageId) {
+        if let Some(failed) = self.take_undelivered(id) {
+            self.send_frame(Frame::Send(failed.message));
+        }
+    }
+
+    fn take_undelivered(&self, id: &MessageId) -> Option<Failed> {
+        let mut undelivered = Undelivered::from_context();
+        let failed = undelivered.take(id)?;
+        undelivered.save();
+        (self.set_undelivered)(undelivered);
+
+        Some(failed)
+    }
+
+    fn send_frame(&self, frame: Frame) {
+        let frame = frame.to_bytes();
+
+        if let Some(outbox) = self.outbox.borrow_mut().as_mut() {
+            return outbox.push(frame);
+        }
+
+        if let Err(e) = self.ws.send_with_u8_array(&frame) {
+            web_sys::console::error_1(&e);
+        }
+    }
+
+    /// Posts a sealed message to the worker without a connection, so the worker
+    /// doesn't learn who sent it.
+    async fn send_sealed(sealed: &SealedMessage) -> Result<(), wasm_bindgen::JsValue> {
+        let mut init = web_sys::RequestInit::new();
+        init.method("POST");
+        init.body(Some(&js_sys::Uint8Array::from(sealed.to_bytes().as_slice())));
+
+        let req = web_sys::Request::new_with_str_and_init(SEALED_URL, &init)?;
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_request(&req))
+            .await?
+            .dyn_into()?;
+
+        match res.ok() {
+            true => Ok(()),
+            false => Err(format!("sealed message refused with status {}", res.status()).into()),
+        }
+    }
+
+    /// Sends content to one peer, numbered in sequence with the other messages
+    /// to them. The first message to a peer is preceded by our access key, so
+    /// they can send to us sealed.
+    pub fn send_to(&self, user: &User, to: &PublicKey, content: &Content) {
+        self.send_expiring(user, to, content, None)
+    }
+
+    /// Sends content to one peer that disappears `expires_in` milliseconds after it is sent.
+    pub fn send_expiring(&self, user: &User, to: &PublicKey, content: &Content, expires_in: Option<u64>) {
+        if AddressBook::from_context().is_revoked(to) {
+            return web_sys::console::error_1(&format!("not sending to revoked key {}", to).into());
+        }
+
+        let mut sequences = Sequences::from_context();
+        self.introduce(user, to, &mut sequences);
+
+        // a peer's linked devices can only read messages encrypted to them too
+        let devices = Devices::from_context().receivers(to);
+        if !devices.is_empty() {
+            let recipients = [(to.clone(), sequences.next(to), sequences.last(to))];
+            let devices = HashMap::from([(to.clone(), devices)]);
+            let options = MessageOptions { expires_in, ..Default::default() };
+            let message = MultiMessage::with_devices(&recipients, &devices, &user.secret_key(), content, js_sys::Date::now() as u64, options);
+            sequences.set_last(to, message.hash());
+            sequences.save();
+
+            return self.send_frame(Frame::SendMulti(message));
+        }
+
+        let options = MessageOptions { expires_in, previous: sequences.last(to), ..Default::default() };
+        let message = Message::with_options(to, &user.secret_key(), content, sequences.next(to), js_sys::Date::now() as u64, options);
+        sequences.set_last(to, message.hash());
+        sequences.save();
+
+        self.send(message, content.id());
+    }
+
+    /// Sends content to several peers at once, encrypting it only once.
+    pub fn send_multi<'a>(
+        &self,
+        user: &User,
+        to: impl IntoIterator<Item = &'a PublicKey>,
+        content: &Content,
+        expires_in: Option<u64>,
+    ) {
+        let mut sequences = Sequences::from_context();
+        let address_book = AddressBook::from_context();
+
+        let recipients: Vec<_> = to
+            .into_iter()
+            .filter(|peer| !address_book.is_revoked(peer))
+            .map(|peer| {
+                self.introduce(user, peer, &mut sequences);
+                (peer.clone(), sequences.next(peer), sequences.last(peer))
+            })
+            .collect();
+
+        let known = Devices::from_context();
+        let devices = recipients.iter().map(|(peer, _, _)| (peer.clone(), known.receivers(peer))).collect();
+
+        let options = MessageOptions { expires_in, ..Default::default() };
+        let message = MultiMessage::with_devices(&recipients, &devices, &user.secret_key(), content, js_sys::Date::now() as u64, options);
+        for (peer, _, _) in &recipients {
+            sequences.set_last(peer, message.hash());
+        }
+        sequences.save();
+
+        self.send_frame(Frame::SendMulti(message));
+    }
+
+    // the first message to a peer is our access key, so they can send to us
+    // sealed. A linked device can't be sent to sealed, it sends its
+    // certificate instead so the peer knows who it is.
+    fn introduce(&self, user: &User, to: &PublicKey, sequences: &mut Sequences) {
+        if !sequences.has_sent(to) {
+            let introduction = match user.certificate() {
+                Some(certificate) => Content::Device { certificate: certificate.clone() },
+                None => Content::Access { key: AccessKey::derive(&user.secret_key()) },
+            };
+            let options = MessageOptions { previous: sequences.last(to), ..Default::default() };
+            let message = Message::with_options(to, &user.secret_key(), &introduction, sequences.next(to), js_sys::Date::now() as u64, options);
+            sequences.set_last(to, message.hash());
+
+            self.send(message, None);
+        }
+    }
+
+    /// Sends content to everyone in a chat, through the group sender key for
+    /// group chats, expiring with the chat's timer. The updated chat is
+    /// returned and must be saved.
+    pub fn send_content(&self, user: &User, mut chat: Chat, content: &Content) -> Chat {
+        let expires_in = chat.expires_in();
+
+        match chat.group().cloned() {
+            Some(mut group) => {
+                let content = Content::Group(group.encrypt(&content.to_bytes()));
+                self.send_multi(user, group.members(), &content, expires_in);
+                chat.set_group(group);
+            }
+            None => {
+                for peer in chat.iter() {
+                    self.send_expiring(user, peer, content, expires_in);
+                }
+            }
+        }
+
+        chat
+    }
+
+    /// Sends content from the user to a chat and records it in the history.
+    pub fn publish(
+        &self,
+        user: &User,
+        chat_id: &str,
+        content: Content,
+        set_chats: &Rc<dyn Fn(Chats)>,
+        set_history: &Rc<dyn Fn(History)>,
+    ) {
+        let mut chats = Chats::from_context();
+        let mut history = History::from_context();
+
+        let chat = match chats.get(chat_id) {
+            Some(c) => self.send_content(user, c, &content),
+            None => return web_sys::console::error_1(&"message for unknown chat".into()),
+        };
+        let expires_at = chat.expires_in().map(|expires_in| js_sys::Date::now() as u64 + expires_in);
+        chats.add_chat(chat.id(), chat).unwrap();
+        history.apply(chat_id, &user.public_key(), content, expires_at);
+
+        chats.save();
+        set_chats(chats);
+        history.save();
+        set_history(history);
+    }
+
+    /// Marks what peers sent to a chat as read and tells them. Group chats
+    /// don't get receipts, like they don't get delivery receipts.
+    pub fn mark_read(&self, user: &User, chat_id: &str, set_history: &Rc<dyn Fn(History)>) {
+        match Chats::from_context().get(chat_id) {
+            Some(chat) if chat.group().is_none() => {}
+            _ => return,
+        }
+
+        let mut history = History::from_context();
+        let unread = history.mark_read(chat_id, &user.public_key());
+        if unread.is_empty() {
+            return;
+        }
+
+        for (from, messages) in unread {
+            self.send_to(user, &from, &Content::Receipt { receipt: Receipt::Read, messages });
+        }
+
+        history.save();
+        set_history(history);
+    }
+
+    /// Sends our sender key for a group to some of its members.
+    pub fn distribute<'a>(&self, user: &User, group: &Group, to: impl IntoIterator<Item = &'a PublicKey>) {
+        let content = Content::SenderKey(group.distribution());
+        for member in to {
+            self.send_to(user, member, &content);
+        }
+    }
+
+    /// Links a device to the user's identity through the worker.
+    pub fn certify(&self, certificate: DeviceCertificate) {
+        self.send_frame(Frame::Certify(certificate));
+    }
+
+    /// Replaces the user's key, passing the statement on to their contacts.
+    pub fn succeed(&self, succession: KeySuccession) {
+        let contacts = AddressBook::from_context().iter().map(|(k, _)| k.clone()).collect();
+
+        self.send_frame(Frame::Succeed { succession, contacts });
+    }
+
+    /// Looks up whether any contact's key was replaced while we weren't
+    /// connected, or before they knew about us.
+    fn refresh_successions(
+        &self,
+        user: &User,
+        set_address_book: &Rc<dyn Fn(AddressBook)>,
+        set_chats: &Rc<dyn Fn(Chats)>,
+        set_history: &Rc<dyn Fn(History)>,
+    ) {
+        let contacts: Vec<PublicKey> = AddressBook::from_context().iter().map(|(k, _)| k.clone()).collect();
+
+        let relay = self.clone();
+        let user = user.clone();
+        let (set_address_book, set_chats, set_history) = (set_address_book.clone(), set_chats.clone(), set_history.clone());
+
+        wasm_bindgen_futures::spawn_local(async move {
+            for contact in contacts {
+                // follow the statements as far as the worker has them
+                let mut statements = Vec::new();
+                let mut key = contact.clone();
+                while statements.len() < MAX_CHAIN_LENGTH {
+                    match Self::fetch_succession(&key).await {
+                        Ok(Some(succession)) => {
+                            key = succession.new.clone();
+                            statements.push(succession);
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            web_sys::console::error_1(&e);
+                            break;
+                        }
+                    }
+                }
+
+                if !statements.is_empty() {
+                    relay.migrate(&user, &contact, &statements, &set_address_book, &set_chats, &set_history);
+                }
+            }
+        });
+    }
+
+    async fn fetch_succession(public_key: &PublicKey) -> Result<Option<KeySuccession>, wasm_bindgen::JsValue> {
+        let url = format!("{}/{}", SUCCESSION_URL, public_key);
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_str(&url))
+            .await?
+            .dyn_into()?;
+        match res.status() {
+            404 => return Ok(None),
+            _ if !res.ok() => return Err(format!("succession lookup failed with status {}", res.status()).into()),
+            _ => {}
+        }
+
+        let text = JsFuture::from(res.text()?).await?.as_string().unwrap_or_default();
+        serde_json::from_str(&text).map(Some).map_err(|e| e.to_string().into())
+    }
+
+    /// Moves a contact, their chats and history over to the key that replaced
+    /// theirs, once every statement leading to it verifies. Group chats with
+    /// them get our new sender key.
+    fn migrate(
+        &self,
+        user: &User,
+        old: &PublicKey,
+        statements: &[KeySuccession],
+        set_address_book: &Rc<dyn Fn(AddressBook)>,
+        set_chats: &Rc<dyn Fn(Chats)>,
+        set_history: &Rc<dyn Fn(History)>,
+    ) {
+        // a revoked key may be in someone else's hands, who could name a key of their own
+        let mut address_book = AddressBook::from_context();
+        if address_book.who_is(old).is_none() || address_book.is_revoked(old) {
+            return;
+        }
+
+        let new = match succession::follow(old, statements) {
+            Ok(new) if new != *old => new,
+            Ok(_) => return,
+            Err(e) => return web_sys::console::error_1(&format!("ignoring key succession for {}: {}", old, e).into()),
+        };
+
+        address_book.migrate(old, new.clone());
+        address_book.save();
+        set_address_book(address_book);
+
+        let mut chats = Chats::from_context();
+        let mut history = History::from_context();
+        for (old_id, new_id) in chats.migrate(old, &new) {
+            history.migrate(&old_id, &new_id, old, &new);
+
+            if let Some(group) = chats.get(&new_id).and_then(|c| c.group().cloned()) {
+                self.distribute(user, &group, group.members());
+            }
+        }
+
+        chats.save();
+        set_chats(chats);
+        history.save();
+        set_history(history);
+    }
+
+    /// Looks up whether any contact has revoked their key.
+    fn refresh_revocations(set_address_book: &Rc<dyn Fn(AddressBook)>) {
+        let address_book = AddressBook::from_context();
+        let contacts: Vec<PublicKey> = address_book
+            .iter()
+            .map(|(k, _)| k.clone())
+            .filter(|k| !address_book.is_revoked(k))
+            .collect();
+
+        let set_address_book = set_address_book.clone();
+
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut revoked = Vec::new();
+            for contact in contacts {
+                match Self::fetch_revocation(&contact).await {
+                    Ok(Some(revocation)) if revocation.key == contact && revocation.verify() => revoked.push(contact),
+                    Ok(_) => {}
+                    Err(e) => web_sys::console::error_1(&e),
+                }
+            }
+
+            if !revoked.is_empty() {
+                let mut address_book = AddressBook::from_context();
+                for contact in &revoked {
+                    address_book.set_revoked(contact);
+                }
+                address_book.save();
+                set_address_book(address_book);
+            }
+        });
+    }
+
+    async fn fetch_revocation(public_key: &PublicKey) -> Result<Option<Revocation>, wasm_bindgen::JsValue> {
+        let url = format!("{}/{}", REVOCATIONS_URL, public_key);
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_str(&url))
+            .await?
+            .dyn_into()?;
+        match res.status() {
+            404 => return Ok(None),
+            _ if !res.ok() => return Err(format!("revocation lookup failed with status {}", res.status()).into()),
+            _ => {}
+        }
+
+        let text = JsFuture::from(res.text()?).await?.as_string().unwrap_or_default();
+        serde_json::from_str(&text).map(Some).map_err(|e| e.to_string().into())
+    }
+
+    /// Publishes a revocation, which needs no connection as the key it
+    /// revokes may be long gone.
+    pub async fn revoke(revocation: &Revocation) -> Result<(), wasm_bindgen::JsValue> {
+        let mut init = web_sys::RequestInit::new();
+        init.method("POST");
+        init.body(Some(&js_sys::Uint8Array::from(revocation.to_bytes().as_slice())));
+
+        let req = web_sys::Request::new_with_str_and_init(REVOCATIONS_URL, &init)?;
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_request(&req))
+            .await?
+            .dyn_into()?;
+
+        match res.ok() {
+            true => Ok(()),
+            false => Err(format!("revocation refused with status {}", res.status()).into()),
+        }
+    }
+
+    /// Publishes an attestation for a contact the user has verified.
+    pub async fn attest(attestation: &Attestation) -> Result<(), wasm_bindgen::JsValue> {
+        let mut init = web_sys::RequestInit::new();
+        init.method("POST");
+        init.body(Some(&js_sys::Uint8Array::from(attestation.to_bytes().as_slice())));
+
+        let req = web_sys::Request::new_with_str_and_init(ATTESTATIONS_URL, &init)?;
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_request(&req))
+            .await?
+            .dyn_into()?;
+
+        match res.ok() {
+            true => Ok(()),
+            false => Err(format!("attestation refused with status {}", res.status()).into()),
+        }
+    }
+
+    /// Everything published vouching for a key, valid or not.
+    pub async fn fetch_attestations(public_key: &PublicKey) -> Result<Vec<Attestation>, wasm_bindgen::JsValue> {
+        let url = format!("{}/{}", ATTESTATIONS_URL, public_key);
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_str(&url))
+            .await?
+            .dyn_into()?;
+        if !res.ok() {
+            return Err(format!("attestation lookup failed with status {}", res.status()).into());
+        }
+
+        let text = JsFuture::from(res.text()?).await?.as_string().unwrap_or_default();
+        serde_json::from_str(&text).map_err(|e| e.to_string().into())
+    }
+
+    /// Fetches the certified devices of the user and their contacts, so
+    /// messages to them can be read on every device.
+    fn refresh_devices(user: &User) {
+        let mut identities: Vec<PublicKey> = AddressBook::from_context().iter().map(|(k, _)| k.clone()).collect();
+        identities.push(user.public_key());
+
+        wasm_bindgen_futures::spawn_local(async move {
+            for identity in identities {
+                match Self::fetch_devices(&identity).await {
+                    Ok(certificates) => {
+                        let mut devices = Devices::from_context();
+                        devices.set(&identity, certificates);
+                        devices.save();
+                    }
+                    Err(e) => web_sys::console::error_1(&e),
+                }
+            }
+        });
+    }
+
+    async fn fetch_devices(identity: &PublicKey) -> Result<Vec<DeviceCertificate>, wasm_bindgen::JsValue> {
+        let url = format!("{}/{}", DEVICES_URL, identity);
+        let res: web_sys::Response = JsFuture::from(web_sys::window().unwrap().fetch_with_str(&url))
+            .await?
+            .dyn_into()?;
+        if !res.ok() {
+            return Err(format!("device lookup failed with status {}", res.status()).into());
+        }
+
+        let text = JsFuture::from(res.text()?).await?.as_string().unwrap_or_default();
+        serde_json::from_str(&text).map_err(|e| e.to_string().into())
+    }
+
+    fn flush(&self) {
+        let outbox = self.outbox.borrow_mut().take().unwrap_or_default();
+        for frame in outbox {
+            if let Err(e) = self.ws.send_with_u8_array(&frame) {
+                web_sys::console::error_1(&e);
+            }
+        }
+    }
+
+    fn receive(
+        &self,
+        user: &User,
+        message: Message,
+        set_chats: &Rc<dyn Fn(Chats)>,
+        set_history: &Rc<dyn Fn(History)>,
+    ) {
+        if !message.verify() {
+            return web_sys::console::error_1(&"dropping message with invalid signature".into());
+        }
+        if !self.check_sequence(&message.from, message.seq) {
+            return;
+        }
+        Self::record(&message.from, message.hash(), message.previous);
+
+        match message.decrypt(&user.secret_key()) {
+            Ok(content) => {
+                let received = Received::new(message.from.clone(), message.expires_in, message.expires_at());
+                self.handle(user, received, content, set_chats, set_history)
+            }
+            Err(e) => web_sys::console::error_1(&e.to_string().into()),
+        }
+    }
+
+    fn receive_multi(
+        &self,
+        user: &User,
+        message: MultiMessage,
+        set_chats: &Rc<dyn Fn(Chats)>,
+        set_history: &Rc<dyn Fn(History)>,
+    ) {
+        if !message.verify() {
+            return web_sys::console::error_1(&"dropping message with invalid signature".into());
+        }
+        let (seq, previous) = match message.recipient(&user.public_key()) {
+            Some(recipient) => (recipient.seq, recipient.previous),
+            None => return web_sys::console::error_1(&"dropping message for someone else".into()),
+        };
+        if !self.check_sequence(&message.from, seq) {
+            return;
+        }
+        Self::record(&message.from, message.hash(), previous);
+
+        match message.decrypt(&user.secret_key()) {
+            Ok(content) => {
+                let received = Received::new(message.from.clone(), message.expires_in, message.expires_at());
+                self.handle(user, received, content, set_chats, set_history)
+            }
+            Err(e) => web_sys::console::error_1(&e.to_string().into()),
+        }
+    }
+
+    /// Checks a verified sequence number for replays, returning whether the
+    /// message should be handled.
+    fn check_sequence(&self, from: &PublicKey, seq: u64) -> bool {
+        let mut sequences = Sequences::from_context();
+        match sequences.check_sequence(from, seq) {
+            Ok(Sequence::Gap(n)) => web_sys::console::warn_1(&format!("{} messages from {} are missing", n, from).into()),
+            Ok(_) => {}
+            Err(e) => {
+                web_sys::console::error_1(&format!("dropping message: {}", e).into());
+                return false;
+            }
+        }
+        sequences.save();
+
+        true
+    }
+
+    /// Adds a verified message to the sender's hash chain.
+    fn record(from: &PublicKey, hash: MessageHash, previous: Option<MessageHash>) {
+        let mut integrity = Integrity::from_context();
+        integrity.record(from, hash, previous);
+        integrity.save();
+    }
+
+    fn handle(
+        &self,
+        user: &User,
+        message: Received,
+        content: Content,
+        set_chats: &Rc<dyn Fn(Chats)>,
+        set_history: &Rc<dyn Fn(History)>,
+    ) {
+        if AddressBook::from_context().is_revoked(&message.from) {
+            return web_sys::console::error_1(&format!("dropping message from revoked key {}", message.from).into());
+        }
+
+        let mut chats = Chats::from_context();
+        let mut history = History::from_context();
+
+        match content {
+            Content::SenderKey(distribution) => {
+                let group_id = distribution.group_id.clone();
+
+                let result = match chats.get(&group_id).and_then(|c| c.group().cloned()) {
+                    Some(mut group) => group
+                        .process_distribution(&message.from, distribution)
+                        .map(|needs| (group, needs)),
+                    None => Group::join(&user.public_key(), &message.from, distribution).map(|group| {
+                        let needs = group.members().cloned().collect();
+                        (group, needs)
+                    }),
+                };
+
+                match result {
+                    Ok((group, needs)) => {
+                        self.distribute(user, &group, needs.iter());
+                        chats.add_chat(group_id, Chat::from_group(group)).unwrap();
+                    }
+                    Err(e) => web_sys::console::error_1(&e.to_string().into()),
+                }
+            }
+            Content::Group(group_message) => {
+                if group_message.sender != message.from {
+                    return web_sys::console::error_1(&"group message sender mismatch".into());
+                }
+
+                let mut chat = match chats.get(&group_message.group_id) {
+                    Some(c) => c,
+                    None => return web_sys::console::error_1(&"message for unknown group".into()),
+                };
+                let mut group = match chat.group().cloned() {
+                    Some(g) => g,
+                    None => return,
+                };
+
+                match group.decrypt(&group_message).map(|plaintext| Content::from_bytes(&plaintext)) {
+                    // sender keys and access keys only ever travel pairwise
+                    Ok(Ok(Content::SenderKey(_) | Content::Group(_) | Content::Access { .. })) => {
+                        web_sys::console::error_1(&"ignoring nested group content".into())
+                    }
+                    Ok(Ok(content)) => {
+                        if content.id().is_some() {
+                            chat.set_expires_in(message.expires_in);
+                        }
+                        history.apply(&chat.id(), &message.from, content, message.expires_at);
+                    }
+                    Ok(Err(e)) => web_sys::console::error_1(&e.to_string().into()),
+                    Err(e) => web_sys::console::error_1(&e.to_string().into()),
+                }
+
+                chat.set_group(group);
+                chats.add_chat(chat.id(), chat).unwrap();
+            }
+            Content::Access { key } => {
+                if message.sender != message.from {
+                    return web_sys::console::error_1(&"ignoring access key from a linked device".into());
+                }
+
+                let mut access_keys = AccessKeys::from_context();
+                access_keys.insert(message.from.clone(), key);
+                access_keys.save();
+            }
+            Content::Device { certificate } => {
+                if certificate.device != message.sender {
+                    return web_sys::console::error_1(&"device certificate for another device".into());
+                }
+
+                let mut devices = Devices::from_context();
+                devices.insert(certificate);
+                devices.save();
+            }
+            content => {
+                let chat_id = Chat::from_public_key(message.from.clone()).id();
+                let mut chat = chats.get(&chat_id).unwrap_or_else(|| Chat::from_public_key(message.from.clone()));
+
+                if let Some(id) = content.id() {
+                    // the timer follows whoever last sent something to the chat
+                    chat.set_expires_in(message.expires_in);
+
+                    let receipt = Content::Receipt { receipt: Receipt::Delivered, messages: vec![id] };
+                    self.send_to(user, &message.from, &receipt);
+                }
+                chats.add_chat(chat_id.clone(), chat).unwrap();
+
+                history.apply(&chat_id, &message.from, content, message.expires_at);
+            }
+        }
+
+        chats.save();
+        set_chats(chats);
+        history.save();
+        set_history(history);
+    }
+}
+
+/// Who sent content and when it expires, whichever envelope it came in.
+struct Received {
+    /// The identity of the sender.
+    from: PublicKey,
+    /// The key that signed the message, one of the identity's linked devices
+    /// or the identity itself.
+    sender: PublicKey,
+    expires_in: Option<u64>,
+    expires_at: Option<u64>,
+}
+
+impl Received {
+    fn new(sender: PublicKey, expires_in: Option<u64>, expires_at: Option<u64>) -> Self {
+        let from = Devices::from_context().identity_of(&sender).unwrap_or_else(|| sender.clone());
+
+        Self { from, sender, expires_in, expires_at }
+    }
+}
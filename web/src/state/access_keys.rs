@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use muruchat::{pki::PublicKey, sealed::AccessKey};
+
+/// The access keys peers have sent us, messages to a peer with an access key
+/// are sent sealed.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct AccessKeys {
+    keys: HashMap<PublicKey, AccessKey>,
+}
+
+impl AccessKeys {
+    pub fn from_context() -> Self {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        match storage.get_item("access_keys").unwrap() {
+            Some(s) => serde_json::from_str(&s).unwrap(),
+            None => Self::default(),
+        }
+    }
+
+    pub fn get(&self, public_key: &PublicKey) -> Option<&AccessKey> {
+        self.keys.get(public_key)
+    }
+
+    pub fn insert(&mut self, public_key: PublicKey, access_key: AccessKey) {
+        self.keys.insert(public_key, access_key);
+    }
+
+    pub fn save(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage
+            .set("access_keys", &serde_json::to_string(&self).unwrap())
+            .unwrap();
+    }
+
+    pub fn delete(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage.delete("access_keys").unwrap();
+    }
+}
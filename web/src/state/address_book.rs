@@ -2,7 +2,7 @@ use dioxus::prelude::*;
 // use serde::de::{Deserializer, MapAccess, Visitor};
 // use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
-use std::collections::{hash_map::Iter, HashMap};
+use std::collections::{hash_map::Iter, HashMap, HashSet};
 
 use muruchat::pki::PublicKey;
 
@@ -11,6 +11,12 @@ pub static ADDRESS_BOOK: Atom<AddressBook> = |_| AddressBook::from_context();
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct AddressBook {
     contacts: HashMap<PublicKey, String>,
+    // contacts whose safety number was compared out of band
+    #[serde(default)]
+    verified: HashSet<PublicKey>,
+    // contacts who have published a revocation of their key
+    #[serde(default)]
+    revoked: HashSet<PublicKey>,
 }
 
 impl AddressBook {
@@ -49,6 +55,51 @@ impl AddressBook {
         self.contacts.get(public_key).cloned()
     }
 
+    pub fn is_verified(&self, public_key: &PublicKey) -> bool {
+        self.verified.contains(public_key)
+    }
+
+    pub fn set_verified(&mut self, public_key: &PublicKey, verified: bool) {
+        if verified && self.contacts.contains_key(public_key) {
+            self.verified.insert(public_key.clone());
+        } else {
+            self.verified.remove(public_key);
+        }
+    }
+
+    pub fn is_revoked(&self, public_key: &PublicKey) -> bool {
+        self.revoked.contains(public_key)
+    }
+
+    /// The verified contacts whose keys haven't been revoked, whose
+    /// attestations count.
+    pub fn trusted(&self) -> HashSet<PublicKey> {
+        self.verified.difference(&self.revoked).cloned().collect()
+    }
+
+    /// Marks a contact's key as revoked, for good.
+    pub fn set_revoked(&mut self, public_key: &PublicKey) {
+        if self.contacts.contains_key(public_key) {
+            self.verified.remove(public_key);
+            self.revoked.insert(public_key.clone());
+        }
+    }
+
+    /// Moves a contact over to the key that replaced theirs, returning whether
+    /// they were in the address book. The new key has a new safety number,
+    /// so it has to be verified again.
+    pub fn migrate(&mut self, old: &PublicKey, new: PublicKey) -> bool {
+        self.verified.remove(old);
+
+        match self.contacts.remove(old) {
+            Some(nickname) => {
+                self.contacts.entry(new).or_insert(nickname);
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.contacts.len()
     }
@@ -2,7 +2,7 @@ use dioxus::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::{hash_map, hash_set, HashMap, HashSet};
 
-use muruchat::pki::PublicKey;
+use muruchat::{group::Group, pki::PublicKey};
 
 pub static CHATS: Atom<Chats> = |_| Chats::from_context();
 
@@ -34,6 +34,29 @@ impl Chats {
         self.chats.iter()
     }
 
+    /// Replaces a peer whose key was replaced in every chat with them,
+    /// returning the old and new id of each of those chats. Group chats keep
+    /// their id, other chats are keyed by their peers so theirs changes.
+    pub fn migrate(&mut self, old: &PublicKey, new: &PublicKey) -> Vec<(String, String)> {
+        let ids: Vec<String> = self
+            .chats
+            .iter()
+            .filter(|(_, chat)| chat.peers.contains(old))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        ids.into_iter()
+            .filter_map(|id| {
+                let chat = self.chats.remove(&id)?;
+                let chat = chat.replace_peer(old, new.clone());
+                let new_id = chat.id();
+                self.chats.insert(new_id.clone(), chat);
+
+                Some((id, new_id))
+            })
+            .collect()
+    }
+
     pub fn any(&self) -> bool {
         self.len() > 0
     }
@@ -63,6 +86,11 @@ impl Chats {
 pub struct Chat {
     peers: HashSet<PublicKey>,
     id: String,
+    #[serde(default)]
+    group: Option<Group>,
+    /// Milliseconds after sending that messages in this chat disappear.
+    #[serde(default)]
+    expires_in: Option<u64>,
 }
 
 impl Chat {
@@ -78,6 +106,18 @@ impl Chat {
         Self {
             peers,
             id: hex::encode(id),
+            group: None,
+            expires_in: None,
+        }
+    }
+
+    /// Group chats are keyed by the group id, so every member agrees on it.
+    pub fn from_group(group: Group) -> Self {
+        Self {
+            peers: group.members().cloned().collect(),
+            id: group.id().to_string(),
+            group: Some(group),
+            expires_in: None,
         }
     }
 
@@ -87,14 +127,6 @@ impl Chat {
         Self::new(peers)
     }
 
-    // pub fn from_public_keys(public_keys: Vec<PublicKey>) -> Self {
-    //     let mut peers = HashSet::new();
-    //     for public_key in public_keys {
-    //         peers.insert(public_key);
-    //     }
-    //     Self::new(peers)
-    // }
-
     pub fn id(&self) -> String {
         self.id.clone()
     }
@@ -103,9 +135,53 @@ impl Chat {
         self.peers.iter()
     }
 
-    // pub fn group_chat_with(&self, public_key: PublicKey) -> Self {
-    //     let mut peers = self.peers.clone();
-    //     peers.insert(public_key);
-    //     Self::new(peers)
-    // }
+    pub fn group(&self) -> Option<&Group> {
+        self.group.as_ref()
+    }
+
+    /// Replaces the group state, e.g. after a sender key has advanced.
+    pub fn set_group(&mut self, group: Group) {
+        self.peers = group.members().cloned().collect();
+        self.group = Some(group);
+    }
+
+    pub fn expires_in(&self) -> Option<u64> {
+        self.expires_in
+    }
+
+    pub fn set_expires_in(&mut self, expires_in: Option<u64>) {
+        self.expires_in = expires_in;
+    }
+
+    fn replace_peer(mut self, old: &PublicKey, new: PublicKey) -> Self {
+        match self.group.clone() {
+            // our sender key is replaced too, and has to be distributed again
+            Some(mut group) => {
+                group.remove_member(old);
+                group.add_member(new);
+                self.set_group(group);
+
+                self
+            }
+            None => {
+                let mut peers = self.peers.clone();
+                peers.remove(old);
+                peers.insert(new);
+
+                let mut chat = Self::new(peers);
+                chat.expires_in = self.expires_in;
+                chat
+            }
+        }
+    }
+
+    /// Starts a new group chat with everyone in this chat plus `public_key`.
+    pub fn group_chat_with(&self, identity: &PublicKey, public_key: PublicKey) -> Self {
+        let mut peers = self.peers.clone();
+        peers.insert(public_key);
+
+        let mut chat = Self::from_group(Group::create(identity, peers));
+        chat.expires_in = self.expires_in;
+        chat
+    }
 }
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use muruchat::{
+    device::{Capability, DeviceCertificate},
+    pki::PublicKey,
+};
+
+/// The certified devices of our contacts and of our own identity, from the
+/// worker and from devices introducing themselves.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Devices {
+    devices: HashMap<PublicKey, Vec<DeviceCertificate>>,
+}
+
+impl Devices {
+    pub fn from_context() -> Self {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        match storage.get_item("devices").unwrap() {
+            Some(s) => serde_json::from_str(&s).unwrap(),
+            None => Self::default(),
+        }
+    }
+
+    /// Adds a certificate if it is valid, returning whether it was new.
+    pub fn insert(&mut self, certificate: DeviceCertificate) -> bool {
+        if !certificate.verify() {
+            return false;
+        }
+
+        let devices = self.devices.entry(certificate.identity.clone()).or_default();
+        if devices.iter().any(|d| d.device == certificate.device) {
+            return false;
+        }
+        devices.push(certificate);
+
+        true
+    }
+
+    /// Replaces the devices of an identity with the valid certificates in `certificates`.
+    pub fn set(&mut self, identity: &PublicKey, certificates: Vec<DeviceCertificate>) {
+        let certificates = certificates
+            .into_iter()
+            .filter(|c| c.identity == *identity && c.verify())
+            .collect();
+
+        self.devices.insert(identity.clone(), certificates);
+    }
+
+    pub fn get(&self, identity: &PublicKey) -> &[DeviceCertificate] {
+        self.devices.get(identity).map_or(&[], Vec::as_slice)
+    }
+
+    /// The devices messages to an identity should also be encrypted to.
+    pub fn receivers(&self, identity: &PublicKey) -> Vec<PublicKey> {
+        self.get(identity)
+            .iter()
+            .filter(|c| c.can(Capability::Receive))
+            .map(|c| c.device.clone())
+            .collect()
+    }
+
+    /// The identity a device may send for.
+    pub fn identity_of(&self, device: &PublicKey) -> Option<PublicKey> {
+        self.devices
+            .values()
+            .flatten()
+            .find(|c| c.device == *device && c.can(Capability::Send))
+            .map(|c| c.identity.clone())
+    }
+
+    pub fn save(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage
+            .set("devices", &serde_json::to_string(&self).unwrap())
+            .unwrap();
+    }
+
+    pub fn delete(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage.delete("devices").unwrap();
+    }
+}
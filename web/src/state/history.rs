@@ -0,0 +1,245 @@
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use muruchat::{
+    attachment::Attachment,
+    content::{Content, MessageId, Receipt},
+    pki::PublicKey,
+};
+
+pub static HISTORY: Atom<History> = |_| History::from_context();
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct History {
+    chats: HashMap<String, Vec<Entry>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub id: MessageId,
+    pub from: PublicKey,
+    pub text: String,
+    pub parent: Option<MessageId>,
+    pub reactions: Vec<(PublicKey, String)>,
+    pub edited: bool,
+    pub deleted: bool,
+    pub receipt: Option<Receipt>,
+    /// Whether a read receipt went out for an entry someone else sent.
+    #[serde(default)]
+    pub read: bool,
+    pub attachment: Option<Attachment>,
+    /// Content from a newer client that can't be shown.
+    pub unsupported: bool,
+    /// Milliseconds since the unix epoch when the entry disappears.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl Entry {
+    fn new(id: MessageId, from: PublicKey, text: String, parent: Option<MessageId>) -> Self {
+        Self {
+            id,
+            from,
+            text,
+            parent,
+            reactions: Vec::new(),
+            edited: false,
+            deleted: false,
+            receipt: None,
+            read: false,
+            attachment: None,
+            unsupported: false,
+            expires_at: None,
+        }
+    }
+}
+
+impl History {
+    pub fn from_context() -> Self {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        match storage.get_item("history").unwrap() {
+            Some(h) => serde_json::from_str(&h).unwrap(),
+            None => Self::default(),
+        }
+    }
+
+    /// Applies content sent to a chat, by us or a peer. Edits and deletes are
+    /// only honoured from the original sender. New entries disappear at
+    /// `expires_at`, if set.
+    pub fn apply(&mut self, chat_id: &str, from: &PublicKey, content: Content, expires_at: Option<u64>) {
+        let entries = self.chats.entry(chat_id.to_string()).or_default();
+        let entry = |id, text, parent| Entry { expires_at, ..Entry::new(id, from.clone(), text, parent) };
+
+        match content {
+            Content::Text { id, text } => entries.push(entry(id, text, None)),
+            Content::Reply { id, parent, text } => entries.push(entry(id, text, Some(parent))),
+            Content::Attachment(attachment) => {
+                let mut entry = entry(attachment.id, attachment.name.clone(), None);
+                entry.attachment = Some(attachment);
+                entries.push(entry);
+            }
+            Content::Reaction { parent, emoji } => {
+                if let Some(entry) = entries.iter_mut().find(|e| e.id == parent) {
+                    entry.reactions.retain(|(public_key, _)| public_key != from);
+                    entry.reactions.push((from.clone(), emoji));
+                }
+            }
+            Content::Edit { parent, text } => {
+                if let Some(entry) = entries.iter_mut().find(|e| e.id == parent && e.from == *from && !e.deleted) {
+                    entry.text = text;
+                    entry.edited = true;
+                }
+            }
+            Content::Delete { parent } => {
+                if let Some(entry) = entries.iter_mut().find(|e| e.id == parent && e.from == *from) {
+                    entry.text.clear();
+                    entry.reactions.clear();
+                    entry.attachment = None;
+                    entry.deleted = true;
+                }
+            }
+            Content::Receipt { receipt, messages } => {
+                for entry in entries.iter_mut().filter(|e| messages.contains(&e.id) && e.from != *from) {
+                    entry.receipt = entry.receipt.max(Some(receipt));
+                }
+            }
+            Content::Unknown => {
+                let mut entry = Entry::new(MessageId::generate(), from.clone(), String::new(), None);
+                entry.unsupported = true;
+                entries.push(entry);
+            }
+            // plumbing is handled by the relay, it never reaches the history
+            Content::SenderKey(_) | Content::Group(_) | Content::Access { .. } | Content::Device { .. } => {}
+        }
+    }
+
+    /// Removes entries that have expired, returning whether any were removed.
+    pub fn expire(&mut self, now: u64) -> bool {
+        let mut expired = false;
+        for entries in self.chats.values_mut() {
+            let before = entries.len();
+            entries.retain(|e| e.expires_at.map_or(true, |expires_at| now < expires_at));
+            expired |= entries.len() != before;
+        }
+
+        expired
+    }
+
+    /// Moves a chat's entries to its new id after a peer's key was replaced,
+    /// showing what they sent with the old key as from the new one.
+    pub fn migrate(&mut self, old_id: &str, new_id: &str, old: &PublicKey, new: &PublicKey) {
+        let mut entries = self.chats.remove(old_id).unwrap_or_default();
+        for entry in entries.iter_mut() {
+            if entry.from == *old {
+                entry.from = new.clone();
+            }
+            for (from, _) in entry.reactions.iter_mut().filter(|(from, _)| from == old) {
+                *from = new.clone();
+            }
+        }
+
+        self.chats.entry(new_id.to_string()).or_default().extend(entries);
+    }
+
+    /// Whether others sent entries to a chat that haven't been marked read.
+    pub fn has_unread(&self, chat_id: &str, me: &PublicKey) -> bool {
+        self.get(chat_id).iter().any(|e| is_unread(e, me))
+    }
+
+    /// Marks what others sent to a chat as read, returning the ids by sender
+    /// so receipts can be sent to them.
+    pub fn mark_read(&mut self, chat_id: &str, me: &PublicKey) -> Vec<(PublicKey, Vec<MessageId>)> {
+        let mut unread: Vec<(PublicKey, Vec<MessageId>)> = Vec::new();
+
+        for entry in self.chats.get_mut(chat_id).into_iter().flatten().filter(|e| is_unread(e, me)) {
+            entry.read = true;
+            match unread.iter_mut().find(|(from, _)| *from == entry.from) {
+                Some((_, ids)) => ids.push(entry.id),
+                None => unread.push((entry.from.clone(), vec![entry.id])),
+            }
+        }
+
+        unread
+    }
+
+    pub fn get(&self, chat_id: &str) -> &[Entry] {
+        self.chats.get(chat_id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn find(&self, chat_id: &str, id: &MessageId) -> Option<&Entry> {
+        self.get(chat_id).iter().find(|e| e.id == *id)
+    }
+
+    pub fn save(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage
+            .set("history", &serde_json::to_string(&self).unwrap())
+            .unwrap();
+    }
+
+    pub fn delete(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage.delete("history").unwrap();
+    }
+}
+
+// unsupported entries get an id of our own, the sender wouldn't know it
+fn is_unread(entry: &Entry, me: &PublicKey) -> bool {
+    entry.from != *me && !entry.read && !entry.unsupported
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use wasm_bindgen_test::*;
+
+    use muruchat::pki::SecretKey;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn test_read_supersedes_delivered() {
+        let me = SecretKey::generate().public_key();
+        let peer = SecretKey::generate().public_key();
+        let id = MessageId::generate();
+
+        let mut history = History::default();
+        history.apply("chat", &me, Content::Text { id, text: "hi".to_string() }, None);
+
+        let receipt = |receipt| Content::Receipt { receipt, messages: vec![id] };
+        history.apply("chat", &peer, receipt(Receipt::Delivered), None);
+        assert_eq!(history.find("chat", &id).unwrap().receipt, Some(Receipt::Delivered));
+
+        history.apply("chat", &peer, receipt(Receipt::Read), None);
+        assert_eq!(history.find("chat", &id).unwrap().receipt, Some(Receipt::Read));
+
+        // a delivery receipt arriving late doesn't undo it
+        history.apply("chat", &peer, receipt(Receipt::Delivered), None);
+        assert_eq!(history.find("chat", &id).unwrap().receipt, Some(Receipt::Read));
+
+        // nor can the sender mark their own message read
+        let other = MessageId::generate();
+        history.apply("chat", &me, Content::Text { id: other, text: "hello".to_string() }, None);
+        history.apply("chat", &me, Content::Receipt { receipt: Receipt::Read, messages: vec![other] }, None);
+        assert_eq!(history.find("chat", &other).unwrap().receipt, None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_mark_read() {
+        let me = SecretKey::generate().public_key();
+        let peer = SecretKey::generate().public_key();
+        let ids: Vec<MessageId> = (0..3).map(|_| MessageId::generate()).collect();
+
+        let mut history = History::default();
+        history.apply("chat", &peer, Content::Text { id: ids[0], text: "hi".to_string() }, None);
+        history.apply("chat", &me, Content::Text { id: ids[1], text: "hi".to_string() }, None);
+        history.apply("chat", &peer, Content::Text { id: ids[2], text: "how are you?".to_string() }, None);
+        assert!(history.has_unread("chat", &me));
+
+        assert_eq!(history.mark_read("chat", &me), vec![(peer.clone(), vec![ids[0], ids[2]])]);
+        assert!(!history.has_unread("chat", &me));
+        assert!(history.mark_read("chat", &me).is_empty());
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use muruchat::{
+    conversation::{ChainIssue, ConversationLog, MessageHash},
+    pki::PublicKey,
+};
+
+/// The hash chains of messages received from each peer, to spot messages
+/// the relay dropped or swapped.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Integrity {
+    log: ConversationLog,
+}
+
+impl Integrity {
+    pub fn from_context() -> Self {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        match storage.get_item("integrity").unwrap() {
+            Some(s) => serde_json::from_str(&s).unwrap(),
+            None => Self::default(),
+        }
+    }
+
+    pub fn record(&mut self, from: &PublicKey, hash: MessageHash, previous: Option<MessageHash>) {
+        self.log.record(from, hash, previous);
+    }
+
+    pub fn issues(&self, from: &PublicKey) -> Vec<ChainIssue> {
+        self.log.issues(from)
+    }
+
+    pub fn save(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage
+            .set("integrity", &serde_json::to_string(&self).unwrap())
+            .unwrap();
+    }
+
+    pub fn delete(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage.delete("integrity").unwrap();
+    }
+}
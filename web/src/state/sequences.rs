@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use muruchat::{
+    conversation::MessageHash,
+    pki::PublicKey,
+    replay::{ReplayError, ReplayGuard, Sequence},
+};
+
+/// Sequence numbers for messages sent to each peer and the last of them to
+/// chain the next one to, and the replay guard for messages received from them.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Sequences {
+    sent: HashMap<PublicKey, u64>,
+    #[serde(default)]
+    last: HashMap<PublicKey, MessageHash>,
+    received: ReplayGuard,
+}
+
+impl Sequences {
+    pub fn from_context() -> Self {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        match storage.get_item("sequences").unwrap() {
+            Some(s) => serde_json::from_str(&s).unwrap(),
+            None => Self::default(),
+        }
+    }
+
+    pub fn next(&mut self, to: &PublicKey) -> u64 {
+        let seq = self.sent.entry(to.clone()).or_default();
+        *seq += 1;
+        *seq
+    }
+
+    /// The last message sent to a peer.
+    pub fn last(&self, to: &PublicKey) -> Option<MessageHash> {
+        self.last.get(to).copied()
+    }
+
+    pub fn set_last(&mut self, to: &PublicKey, hash: MessageHash) {
+        self.last.insert(to.clone(), hash);
+    }
+
+    /// Whether anything has been sent to a peer yet.
+    pub fn has_sent(&self, to: &PublicKey) -> bool {
+        self.sent.contains_key(to)
+    }
+
+    pub fn check_sequence(&mut self, from: &PublicKey, seq: u64) -> Result<Sequence, ReplayError> {
+        self.received.check_sequence(from, seq)
+    }
+
+    pub fn save(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage
+            .set("sequences", &serde_json::to_string(&self).unwrap())
+            .unwrap();
+    }
+
+    pub fn delete(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage.delete("sequences").unwrap();
+    }
+}
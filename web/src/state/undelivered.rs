@@ -0,0 +1,60 @@
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use muruchat::{content::MessageId, message::Message, pki::PublicKey};
+
+pub static UNDELIVERED: Atom<Undelivered> = |_| Undelivered::from_context();
+
+/// Messages the worker wouldn't take sealed. Sending them over the websocket
+/// instead would show the worker who they are from, so they are held until
+/// the user picks what to do with each.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Undelivered {
+    messages: Vec<Failed>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Failed {
+    pub message: Message,
+    /// The history entry the message carries, if it carries one.
+    pub content: Option<MessageId>,
+    pub error: String,
+}
+
+impl Undelivered {
+    pub fn from_context() -> Self {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        match storage.get_item("undelivered").unwrap() {
+            Some(s) => serde_json::from_str(&s).unwrap(),
+            None => Self::default(),
+        }
+    }
+
+    pub fn push(&mut self, message: Message, content: Option<MessageId>, error: String) {
+        self.messages.push(Failed { message, content, error });
+    }
+
+    /// Removes a message to handle it, by its id.
+    pub fn take(&mut self, id: &MessageId) -> Option<Failed> {
+        let i = self.messages.iter().position(|f| f.message.id == *id)?;
+
+        Some(self.messages.remove(i))
+    }
+
+    /// The messages held for a peer.
+    pub fn to<'a>(&'a self, public_key: &'a PublicKey) -> impl Iterator<Item = &'a Failed> {
+        self.messages.iter().filter(move |f| f.message.to == *public_key)
+    }
+
+    pub fn save(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage
+            .set("undelivered", &serde_json::to_string(&self).unwrap())
+            .unwrap();
+    }
+
+    pub fn delete(&self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+        storage.delete("undelivered").unwrap();
+    }
+}
@@ -1,14 +1,22 @@
 use dioxus::prelude::*;
 use std::str::FromStr;
 
-use muruchat::pki::{PublicKey, SecretKey};
+use muruchat::{
+    device::DeviceCertificate,
+    pki::{PublicKey, SecretKey},
+};
 
 pub static USER: Atom<Option<User>> = |_| User::from_context();
 
+/// The user of this browser. On a device linked to an identity the secret key
+/// is the device's own, and the certificate names the identity.
 #[derive(Clone)]
 pub struct User {
     secret_key: SecretKey,
     public_key: PublicKey,
+    certificate: Option<DeviceCertificate>,
+    // shown until the identity device certifies this one
+    link_code: Option<String>,
 }
 
 impl User {
@@ -18,11 +26,18 @@ impl User {
 
     pub fn from_context() -> Option<Self> {
         let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
-        storage.get_item("secret_key").unwrap().map(|secret_key|
-            Self::from_secret_key(
+        storage.get_item("secret_key").unwrap().map(|secret_key| {
+            let mut user = Self::from_secret_key(
                 SecretKey::from_str(&secret_key).unwrap(),
-            )
-        )
+            );
+            user.certificate = storage
+                .get_item("device_certificate")
+                .unwrap()
+                .map(|c| serde_json::from_str(&c).unwrap());
+            user.link_code = storage.get_item("link_code").unwrap();
+
+            user
+        })
     }
 
     pub fn from_secret_key(secret_key: SecretKey) -> Self {
@@ -31,10 +46,21 @@ impl User {
         Self {
             secret_key,
             public_key,
+            certificate: None,
+            link_code: None,
         }
     }
 
+    /// The identity others know the user by.
     pub fn public_key(&self) -> PublicKey {
+        match &self.certificate {
+            Some(certificate) => certificate.identity.clone(),
+            None => self.public_key.clone(),
+        }
+    }
+
+    /// The key this browser holds, which it connects to the worker with.
+    pub fn device_key(&self) -> PublicKey {
         self.public_key.clone()
     }
 
@@ -42,14 +68,43 @@ impl User {
         self.secret_key.clone()
     }
 
+    pub fn certificate(&self) -> Option<&DeviceCertificate> {
+        self.certificate.as_ref()
+    }
+
+    pub fn set_certificate(&mut self, certificate: DeviceCertificate) {
+        self.certificate = Some(certificate);
+        self.link_code = None;
+    }
+
+    /// The link code to show while waiting to be linked to an identity.
+    pub fn link_code(&self) -> Option<&str> {
+        self.link_code.as_deref()
+    }
+
+    pub fn set_link_code(&mut self, link_code: String) {
+        self.link_code = Some(link_code);
+    }
+
     pub fn delete(&self) {
         let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
-        storage.remove_item("private_key").unwrap()
+        storage.remove_item("secret_key").unwrap();
+        storage.remove_item("device_certificate").unwrap();
+        storage.remove_item("link_code").unwrap();
     }
 
     pub fn save(&self) {
         let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
         let doc = self.secret_key.to_string();
         storage.set("secret_key", &doc).unwrap();
+
+        match &self.certificate {
+            Some(certificate) => storage.set("device_certificate", &serde_json::to_string(certificate).unwrap()).unwrap(),
+            None => storage.remove_item("device_certificate").unwrap(),
+        }
+        match &self.link_code {
+            Some(link_code) => storage.set("link_code", link_code).unwrap(),
+            None => storage.remove_item("link_code").unwrap(),
+        }
     }
 }
@@ -0,0 +1,82 @@
+use worker::{async_trait::async_trait, *};
+
+use muruchat::{
+    attachment::{CHUNK_SIZE, MAX_CHUNKS},
+    blob::{BlobError, BlobStore},
+    content::MessageId,
+};
+
+/// AES-GCM tag on the end of every sealed chunk.
+const TAG_SIZE: usize = 16;
+
+/// Per attachment storage, addressed by the attachment id. Holds the sealed
+/// chunks, which it can't read.
+#[durable_object]
+pub struct Blobs {
+    state: State,
+}
+
+#[durable_object]
+impl DurableObject for Blobs {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let path = req.path();
+        let mut segments = path.trim_start_matches('/').split('/');
+
+        let key = match (segments.next(), segments.next(), segments.next()) {
+            (Some("blobs"), Some(id), Some(index)) => match (id.parse::<MessageId>(), index.parse::<u32>()) {
+                (Ok(id), Ok(index)) if index < MAX_CHUNKS => format!("{}/{}", id, index),
+                _ => return Response::error("Invalid chunk", 400),
+            },
+            _ => return Response::error("Not Found", 404),
+        };
+
+        let mut store = DurableBlobStore(self.state.storage());
+
+        match req.method() {
+            Method::Put => {
+                let chunk = req.bytes().await?;
+                if chunk.len() > CHUNK_SIZE + TAG_SIZE {
+                    return Response::error("Chunk too large", 413);
+                }
+
+                // chunks are written once, nobody gets to swap them out later
+                if store.get(&key).await.map_err(|e| Error::RustError(e.to_string()))?.is_some() {
+                    return Response::error("Chunk already uploaded", 409);
+                }
+
+                store.put(&key, chunk).await.map_err(|e| Error::RustError(e.to_string()))?;
+
+                Response::empty()
+            }
+            Method::Get => match store.get(&key).await.map_err(|e| Error::RustError(e.to_string()))? {
+                Some(chunk) => Response::from_bytes(chunk),
+                None => Response::error("Not Found", 404),
+            },
+            _ => Response::error("Method Not Allowed", 405),
+        }
+    }
+}
+
+/// Keeps blobs in durable object storage, hex encoded as values are stored as JSON.
+pub struct DurableBlobStore(Storage);
+
+#[async_trait(?Send)]
+impl BlobStore for DurableBlobStore {
+    async fn put(&mut self, key: &str, blob: Vec<u8>) -> std::result::Result<(), BlobError> {
+        self.0.put(key, hex::encode(blob)).await.map_err(BlobError::new)
+    }
+
+    async fn get(&self, key: &str) -> std::result::Result<Option<Vec<u8>>, BlobError> {
+        // storage reports a missing key as an error
+        let hex: String = match self.0.get(key).await {
+            Ok(hex) => hex,
+            Err(_) => return Ok(None),
+        };
+
+        hex::decode(hex).map(Some).map_err(BlobError::new)
+    }
+}
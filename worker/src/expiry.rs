@@ -0,0 +1,152 @@
+//! Dropping undelivered messages once they expire.
+//!
+//! Every stored message that expires gets an index entry named by its expiry
+//! time, pointing at the message. Storage lists keys in order, so the inbox's
+//! alarm only lists the entries that have expired, and the first entry left is
+//! when the alarm next has to run. Storing a message never looks at the others.
+//!
+//! Everything here takes the time as a parameter, the inbox passes the clock.
+
+/// The prefix of index entries.
+pub const EXPIRING: &str = "expiring/";
+
+/// The most index entries an alarm handles, each with its message. The alarm
+/// runs again straight away for any more.
+pub const BATCH: usize = 64;
+
+/// The index entry for the message `id` expiring at `expires_at`.
+pub fn index_key(expires_at: u64, id: &str) -> String {
+    format!("{}{:016}-{}", EXPIRING, expires_at, id)
+}
+
+/// The end of the index entries that have expired by `now`, exclusive.
+pub fn expired_end(now: u64) -> String {
+    format!("{}{:016}", EXPIRING, now.saturating_add(1))
+}
+
+/// When the message an index entry is for expires.
+pub fn expires_at(index_key: &str) -> Option<u64> {
+    index_key.strip_prefix(EXPIRING)?.split('-').next()?.parse().ok()
+}
+
+/// When the alarm has to run once a message expiring at `expires_at` is
+/// stored, `None` if the alarm already set for `current` runs soon enough.
+pub fn reschedule(current: Option<u64>, expires_at: u64) -> Option<u64> {
+    match current {
+        Some(current) if current <= expires_at => None,
+        _ => Some(expires_at),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate wasm_bindgen_test;
+
+    use std::collections::BTreeMap;
+
+    use wasm_bindgen_test::*;
+
+    use super::*;
+
+    /// Ordered keys like durable object storage, with an alarm.
+    #[derive(Default)]
+    struct Storage {
+        keys: BTreeMap<String, String>,
+        alarm: Option<u64>,
+    }
+
+    impl Storage {
+        fn store(&mut self, id: &str, received: u64, expires_at: Option<u64>) {
+            let message = format!("messages/{:016}-{}", received, id);
+            self.keys.insert(message.clone(), String::new());
+
+            if let Some(expires_at) = expires_at {
+                self.keys.insert(index_key(expires_at, id), message);
+                if let Some(at) = reschedule(self.alarm, expires_at) {
+                    self.alarm = Some(at);
+                }
+            }
+        }
+
+        // what the inbox's alarm does
+        fn run_alarm(&mut self, now: u64) {
+            let end = expired_end(now);
+            let expired: Vec<(String, String)> = self
+                .keys
+                .range(EXPIRING.to_string()..end)
+                .take(BATCH)
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+
+            for (index, message) in expired {
+                self.keys.remove(&index);
+                self.keys.remove(&message);
+            }
+
+            self.alarm = self.keys.keys().find(|k| k.starts_with(EXPIRING)).and_then(|k| expires_at(k));
+        }
+
+        fn messages(&self) -> usize {
+            self.keys.keys().filter(|k| k.starts_with("messages/")).count()
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_index_key() {
+        let key = index_key(1234, "abcd");
+        assert_eq!(expires_at(&key), Some(1234));
+        assert_eq!(expires_at("messages/0000000000001234-abcd"), None);
+
+        // keys sort by expiry, and the range ends after everything expired by now
+        assert!(index_key(999, "ffff") < index_key(1000, "0000"));
+        assert!(index_key(1000, "ffff") < expired_end(1000));
+        assert!(expired_end(1000) < index_key(1001, "0000"));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reschedule() {
+        assert_eq!(reschedule(None, 100), Some(100));
+        assert_eq!(reschedule(Some(200), 100), Some(100));
+        assert_eq!(reschedule(Some(100), 100), None);
+        assert_eq!(reschedule(Some(50), 100), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_alarm_drops_expired_messages() {
+        let mut storage = Storage::default();
+        storage.store("a", 0, Some(1000));
+        storage.store("b", 10, None);
+        storage.store("c", 20, Some(500));
+        storage.store("d", 30, Some(2000));
+        assert_eq!(storage.alarm, Some(500));
+
+        // too early, nothing has expired
+        storage.run_alarm(499);
+        assert_eq!((storage.messages(), storage.alarm), (4, Some(500)));
+
+        storage.run_alarm(500);
+        assert_eq!((storage.messages(), storage.alarm), (3, Some(1000)));
+
+        // a late alarm catches up on everything expired since
+        storage.run_alarm(5000);
+        assert_eq!((storage.messages(), storage.alarm), (1, None));
+        assert!(storage.keys.contains_key("messages/0000000000000010-b"));
+
+        storage.store("e", 6000, Some(7000));
+        assert_eq!(storage.alarm, Some(7000));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_alarm_runs_again_for_more_than_a_batch() {
+        let mut storage = Storage::default();
+        for i in 0..BATCH + 1 {
+            storage.store(&format!("{:04}", i), 0, Some(100));
+        }
+
+        storage.run_alarm(100);
+        assert_eq!((storage.messages(), storage.alarm), (1, Some(100)));
+
+        storage.run_alarm(100);
+        assert_eq!((storage.messages(), storage.alarm), (0, None));
+    }
+}
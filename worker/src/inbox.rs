@@ -0,0 +1,663 @@
+use std::{cell::RefCell, rc::Rc};
+
+use futures_util::stream::StreamExt;
+use serde::de::DeserializeOwned;
+use worker::*;
+
+use muruchat::{
+    attestation::Attestation,
+    device::{Capability, DeviceCertificate},
+    frame::Frame,
+    handshake::Challenge,
+    message::Message,
+    multi::MultiMessage,
+    pki::{PublicKey, Signature},
+    prekey::PrekeyUpload,
+    replay::ReplayGuard,
+    revocation::Revocation,
+    sealed::{AccessKey, SealedMessage},
+    succession::KeySuccession,
+};
+
+use crate::expiry::{self, EXPIRING};
+
+const PREKEYS: &str = "prekeys";
+const MESSAGES: &str = "messages/";
+const REPLAY: &str = "replay";
+const ACCESS: &str = "access";
+const TOKENS: &str = "tokens";
+const DEVICES: &str = "devices";
+const SUCCESSION: &str = "succession";
+const REVOCATION: &str = "revocation";
+const ATTESTATIONS: &str = "attestations";
+
+/// How far a delivery token's timestamp can be from now, in milliseconds.
+const TOKEN_LIFETIME: u64 = 5 * 60 * 1000;
+
+/// The most sealed messages an inbox accepts in a minute.
+const SEALED_PER_MINUTE: usize = 120;
+
+/// The most attestations kept for a key, the oldest are dropped first.
+const MAX_ATTESTATIONS: usize = 256;
+
+/// Per public key storage, addressed by the hex encoded public key. Holds the
+/// prekeys, linked devices, successor, revocation, attestations and
+/// undelivered messages for the key, and the websockets its owner is
+/// connected with.
+#[durable_object]
+pub struct Inbox {
+    state: State,
+    env: Env,
+    sockets: Rc<RefCell<Vec<WebSocket>>>,
+}
+
+#[durable_object]
+impl DurableObject for Inbox {
+    fn new(state: State, env: Env) -> Self {
+        Self {
+            state,
+            env,
+            sockets: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    async fn fetch(&mut self, req: Request) -> Result<Response> {
+        let path = req.path();
+        let mut segments = path.trim_start_matches('/').split('/');
+
+        // a revoked key can't connect, and nothing more is delivered to it
+        let revoked = self.revocation().await.is_some();
+
+        match (req.method(), segments.next(), segments.next()) {
+            (Method::Get, Some("chat"), _) | (Method::Put, Some("prekeys"), _) if revoked => {
+                Response::error("Key has been revoked", 403)
+            }
+            (Method::Post, Some("messages" | "sealed" | "multi" | "forward" | "attestations"), _) if revoked => {
+                Response::error("Key has been revoked", 410)
+            }
+            (Method::Get, Some("chat"), Some(public_key)) => match public_key.parse() {
+                Ok(public_key) => self.connect(public_key),
+                Err(e) => Response::error(format!("Invalid public key: {}", e), 400),
+            },
+            (Method::Post, Some("messages"), _) => self.deliver(req).await,
+            (Method::Post, Some("sealed"), _) => self.deliver_sealed(req).await,
+            (Method::Post, Some("multi"), Some(public_key)) => match public_key.parse() {
+                Ok(public_key) => self.deliver_multi(public_key, req).await,
+                Err(e) => Response::error(format!("Invalid public key: {}", e), 400),
+            },
+            (Method::Post, Some("forward"), _) => self.forward(req).await,
+            (Method::Get, Some("devices"), _) => self.list_devices().await,
+            (Method::Get, Some("succession"), _) => self.get_succession().await,
+            (Method::Post, Some("revocations"), _) => self.revoke(req).await,
+            (Method::Get, Some("revocations"), _) => match self.revocation().await {
+                Some(revocation) => Response::from_json(&revocation),
+                None => Response::error("Key has not been revoked", 404),
+            },
+            (Method::Post, Some("attestations"), _) => self.attest(req).await,
+            (Method::Get, Some("attestations"), _) => self.list_attestations().await,
+            (Method::Put, Some("prekeys"), _) => self.put_prekeys(req).await,
+            (Method::Get, Some("prekeys"), _) => self.take_prekey_bundle().await,
+            _ => Response::error("Not Found", 404),
+        }
+    }
+
+    async fn alarm(&mut self) -> Result<Response> {
+        self.expire(Date::now().as_millis()).await?;
+
+        Response::empty()
+    }
+}
+
+impl Inbox {
+    /// Accepts a websocket from the owner of the inbox. The owner has to pass
+    /// the handshake before anything is delivered to or sent from it.
+    fn connect(&mut self, public_key: PublicKey) -> Result<Response> {
+        let pair = WebSocketPair::new()?;
+        pair.server.accept()?;
+
+        let connection = Connection {
+            public_key,
+            socket: pair.server,
+            sockets: self.sockets.clone(),
+            storage: self.state.storage(),
+            inboxes: self.env.durable_object("INBOX")?,
+        };
+
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = connection.run().await {
+                console_log!("connection error: {}", e);
+            }
+        });
+
+        Response::from_websocket(pair.client)
+    }
+
+    /// Delivers a message, unless it has been delivered before or has expired.
+    async fn deliver(&mut self, mut req: Request) -> Result<Response> {
+        let message = match Message::from_bytes(&req.bytes().await?) {
+            Ok(message) => message,
+            Err(e) => return Response::error(e.to_string(), 400),
+        };
+        if message.is_expired(Date::now().as_millis()) {
+            return Response::error("Message has expired", 410);
+        }
+
+        let mut storage = self.state.storage();
+        let mut guard: ReplayGuard = storage.get(REPLAY).await.unwrap_or_default();
+        match guard.check(&message) {
+            Ok(_) => storage.put(REPLAY, guard).await?,
+            Err(e) => return Response::error(e.to_string(), 409),
+        }
+
+        self.hand_over(Frame::Deliver(message), Date::now().as_millis()).await
+    }
+
+    /// Delivers a multi recipient message to `public_key`, the owner of this
+    /// inbox, checking the sequence number in their entry for replays.
+    async fn deliver_multi(&mut self, public_key: PublicKey, mut req: Request) -> Result<Response> {
+        let message = match MultiMessage::from_bytes(&req.bytes().await?) {
+            Ok(message) => message,
+            Err(e) => return Response::error(e.to_string(), 400),
+        };
+        let seq = match message.recipient(&public_key) {
+            Some(recipient) => recipient.seq,
+            None => return Response::error("Not a recipient of the message", 400),
+        };
+        if message.is_expired(Date::now().as_millis()) {
+            return Response::error("Message has expired", 410);
+        }
+
+        let mut storage = self.state.storage();
+        let mut guard: ReplayGuard = storage.get(REPLAY).await.unwrap_or_default();
+        match guard.check_sequence(&message.from, seq) {
+            Ok(_) => storage.put(REPLAY, guard).await?,
+            Err(e) => return Response::error(e.to_string(), 409),
+        }
+
+        self.fan_out(&message).await?;
+        self.hand_over(Frame::DeliverMulti(message), Date::now().as_millis()).await
+    }
+
+    /// Forwards a multi recipient message for the owner to each of their
+    /// linked devices it was encrypted to.
+    async fn fan_out(&self, message: &MultiMessage) -> Result<()> {
+        let devices: Vec<DeviceCertificate> = self.state.storage().get(DEVICES).await.unwrap_or_default();
+        let inboxes = self.env.durable_object("INBOX")?;
+        let bytes = Frame::DeliverMulti(message.clone()).to_bytes();
+
+        for certificate in devices {
+            if certificate.can(Capability::Receive) && message.is_for_device(&certificate.device) {
+                post(&inboxes, &certificate.device, "https://inbox/forward", &bytes).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Takes a frame fanned out by the identity this inbox's owner is linked
+    /// to, the certificate linking them, or a contact's key succession. Only other inboxes can reach this,
+    /// the router never forwards it.
+    async fn forward(&mut self, mut req: Request) -> Result<Response> {
+        let now = Date::now().as_millis();
+
+        match Frame::from_bytes(&req.bytes().await?) {
+            Ok(Frame::DeliverMulti(message)) if !message.is_expired(now) => {
+                self.hand_over(Frame::DeliverMulti(message), now).await
+            }
+            Ok(Frame::Linked(certificate)) if certificate.verify() => self.hand_over(Frame::Linked(certificate), now).await,
+            Ok(Frame::Succeeded(succession)) if succession.verify().is_ok() => {
+                self.hand_over(Frame::Succeeded(succession), now).await
+            }
+            _ => Response::error("Invalid forwarded frame", 400),
+        }
+    }
+
+    /// The certificates of the devices linked to the owner.
+    async fn list_devices(&self) -> Result<Response> {
+        let devices: Vec<DeviceCertificate> = self.state.storage().get(DEVICES).await.unwrap_or_default();
+
+        Response::from_json(&devices)
+    }
+
+    /// The statement naming the key that replaced the owner's, if any.
+    async fn get_succession(&self) -> Result<Response> {
+        match self.state.storage().get::<KeySuccession>(SUCCESSION).await {
+            Ok(succession) => Response::from_json(&succession),
+            Err(_) => Response::error("Key has not been replaced", 404),
+        }
+    }
+
+    async fn revocation(&self) -> Option<Revocation> {
+        self.state.storage().get(REVOCATION).await.ok()
+    }
+
+    /// Publishes the owner's revocation, checked by the caller, and drops
+    /// everything the key could still be reached or used through.
+    async fn revoke(&mut self, mut req: Request) -> Result<Response> {
+        let revocation = match Revocation::from_bytes(&req.bytes().await?) {
+            Ok(revocation) => revocation,
+            Err(e) => return Response::error(e.to_string(), 400),
+        };
+
+        let mut storage = self.state.storage();
+        storage.put(REVOCATION, revocation).await?;
+        storage.delete_multiple(vec![PREKEYS, DEVICES, ACCESS]).await?;
+
+        for socket in self.sockets.borrow_mut().drain(..) {
+            if let Err(e) = socket.close(Some(1008), Some("Key has been revoked")) {
+                console_log!("failed to close socket: {}", e);
+            }
+        }
+
+        Response::empty()
+    }
+
+    /// Keeps an attestation for the owner, checked by the caller. A newer one
+    /// from the same attester replaces theirs.
+    async fn attest(&mut self, mut req: Request) -> Result<Response> {
+        let attestation = match Attestation::from_bytes(&req.bytes().await?) {
+            Ok(attestation) => attestation,
+            Err(e) => return Response::error(e.to_string(), 400),
+        };
+
+        let mut storage = self.state.storage();
+        let mut attestations: Vec<Attestation> = storage.get(ATTESTATIONS).await.unwrap_or_default();
+        attestations.retain(|a| a.attester != attestation.attester);
+        attestations.push(attestation);
+        if attestations.len() > MAX_ATTESTATIONS {
+            attestations.sort_by_key(|a| std::cmp::Reverse(a.created_at));
+            attestations.truncate(MAX_ATTESTATIONS);
+        }
+        storage.put(ATTESTATIONS, attestations).await?;
+
+        Response::empty()
+    }
+
+    /// Everyone who has vouched for the owner. Whose attestations count is up
+    /// to whoever asks.
+    async fn list_attestations(&self) -> Result<Response> {
+        let attestations: Vec<Attestation> = self.state.storage().get(ATTESTATIONS).await.unwrap_or_default();
+
+        Response::from_json(&attestations)
+    }
+
+    /// Delivers a sealed message if its token was made with the owner's access
+    /// key. The sender is hidden so the replay guard can't be used, instead
+    /// every token is only accepted once and sealed messages are rate limited.
+    async fn deliver_sealed(&mut self, mut req: Request) -> Result<Response> {
+        let sealed = match SealedMessage::from_bytes(&req.bytes().await?) {
+            Ok(sealed) => sealed,
+            Err(e) => return Response::error(e.to_string(), 400),
+        };
+
+        let mut storage = self.state.storage();
+        let access_key: AccessKey = match storage.get(ACCESS).await {
+            Ok(access_key) => access_key,
+            Err(_) => return Response::error("Sealed messages are not accepted", 403),
+        };
+
+        let now = Date::now().as_millis();
+        if !access_key.verify(&sealed.to, &sealed.token) || now.abs_diff(sealed.token.timestamp) > TOKEN_LIFETIME {
+            return Response::error("Invalid delivery token", 403);
+        }
+
+        // nonces are kept for twice the lifetime, past that their token has expired
+        let mut tokens: Vec<(u64, [u8; 16])> = storage.get(TOKENS).await.unwrap_or_default();
+        tokens.retain(|(received, _)| now.saturating_sub(*received) <= 2 * TOKEN_LIFETIME);
+
+        if tokens.iter().any(|(_, nonce)| *nonce == sealed.token.nonce) {
+            return Response::error("Delivery token already used", 409);
+        }
+        if tokens.iter().filter(|(received, _)| now.saturating_sub(*received) < 60 * 1000).count() >= SEALED_PER_MINUTE {
+            return Response::error("Too many sealed messages", 429);
+        }
+
+        tokens.push((now, sealed.token.nonce));
+        storage.put(TOKENS, tokens).await?;
+
+        self.hand_over(Frame::DeliverSealed(sealed), now).await
+    }
+
+    /// Hands a frame to every connected socket, or stores it until the owner
+    /// connects. A frame that expires is indexed by when, and the alarm is
+    /// brought forward if it has to run sooner to drop it.
+    async fn hand_over(&mut self, frame: Frame, now: u64) -> Result<Response> {
+        let bytes = frame.to_bytes();
+        let delivered = self
+            .sockets
+            .borrow()
+            .iter()
+            .filter(|socket| socket.send_with_bytes(&bytes).is_ok())
+            .count();
+
+        if delivered == 0 {
+            let mut id = [0; 4];
+            getrandom::getrandom(&mut id).map_err(|e| Error::RustError(e.to_string()))?;
+            let id = hex::encode(id);
+            let key = format!("{}{:016}-{}", MESSAGES, now, id);
+
+            let mut storage = self.state.storage();
+            storage.put(&key, &frame).await?;
+
+            if let Some(expires_at) = expires_at(&frame) {
+                storage.put(&expiry::index_key(expires_at, &id), &key).await?;
+
+                let current = storage.get_alarm().await?.map(|at| at as u64);
+                if let Some(at) = expiry::reschedule(current, expires_at) {
+                    storage.set_alarm(scheduled_at(at)).await?;
+                }
+            }
+        }
+
+        Response::empty()
+    }
+
+    /// Deletes the stored messages that expired by `now` before they were
+    /// delivered, and sets the alarm for the next to expire.
+    async fn expire(&mut self, now: u64) -> Result<()> {
+        let mut storage = self.state.storage();
+
+        let end = expiry::expired_end(now);
+        let expired = storage
+            .list_with_options(ListOptions::new().prefix(EXPIRING).end(&end).limit(expiry::BATCH))
+            .await?;
+
+        let mut keys = Vec::new();
+        for entry in expired.entries() {
+            let entry: js_sys::Array = entry?.into();
+            keys.extend(entry.get(0).as_string());
+            keys.extend(entry.get(1).as_string());
+        }
+        if !keys.is_empty() {
+            storage.delete_multiple(keys).await?;
+        }
+
+        // anything left past a full batch is due now, the alarm runs again
+        let next = storage.list_with_options(ListOptions::new().prefix(EXPIRING).limit(1)).await?;
+        let next = next.keys().into_iter().next().transpose()?.and_then(|key| key.as_string());
+        if let Some(at) = next.as_deref().and_then(expiry::expires_at) {
+            storage.set_alarm(scheduled_at(at)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the stored prekeys, the upload has already been verified by the
+    /// caller. An upload that isn't newer than the stored one is refused, so an
+    /// old one can't be replayed to bring back used one-time prekeys.
+    async fn put_prekeys(&mut self, mut req: Request) -> Result<Response> {
+        let upload: PrekeyUpload = req.json().await?;
+
+        let mut storage = self.state.storage();
+        if let Ok(stored) = storage.get::<PrekeyUpload>(PREKEYS).await {
+            if !upload.is_newer_than(&stored) {
+                return Response::error("Prekey upload is older than the stored one", 409);
+            }
+        }
+        storage.put(PREKEYS, upload).await?;
+
+        Response::empty()
+    }
+
+    /// Returns a bundle with at most one one-time prekey, which is removed from storage.
+    async fn take_prekey_bundle(&mut self) -> Result<Response> {
+        let mut storage = self.state.storage();
+
+        let mut upload: PrekeyUpload = match storage.get(PREKEYS).await {
+            Ok(upload) => upload,
+            Err(_) => return Response::error("No prekeys uploaded", 404),
+        };
+
+        let bundle = upload.take_bundle();
+        if bundle.one_time_prekey.is_some() {
+            storage.put(PREKEYS, upload).await?;
+        }
+
+        Response::from_json(&bundle)
+    }
+}
+
+// sealed messages hide their expiry with the rest of the metadata, the
+// recipient drops them instead
+fn expires_at(frame: &Frame) -> Option<u64> {
+    match frame {
+        Frame::Deliver(message) => message.expires_at(),
+        Frame::DeliverMulti(message) => message.expires_at(),
+        _ => None,
+    }
+}
+
+fn scheduled_at(millis: u64) -> ScheduledTime {
+    ScheduledTime::new(js_sys::Date::new(&(millis as f64).into()))
+}
+
+/// Reads back a value listed from storage, which `Storage::put` stores as JSON.
+fn from_stored<T: DeserializeOwned>(value: wasm_bindgen::JsValue) -> Result<T> {
+    let json: String = js_sys::JSON::stringify(&value)?.into();
+
+    Ok(serde_json::from_str(&json)?)
+}
+
+#[derive(Debug)]
+enum Fsm {
+    WaitingForPK,
+    WaitingForSignature(Challenge),
+    Authed,
+}
+
+struct Connection {
+    public_key: PublicKey,
+    socket: WebSocket,
+    sockets: Rc<RefCell<Vec<WebSocket>>>,
+    storage: Storage,
+    inboxes: ObjectNamespace,
+}
+
+impl Connection {
+    async fn run(mut self) -> Result<()> {
+        let result = self.serve().await;
+
+        // however it ended, frames must be stored rather than sent to a socket
+        // nobody reads any more
+        self.sockets.borrow_mut().retain(|socket| *socket != self.socket);
+
+        result
+    }
+
+    async fn serve(&mut self) -> Result<()> {
+        // start finite state machine to track handshake
+        let mut fsm = Fsm::WaitingForPK;
+
+        let socket = self.socket.clone();
+        let mut event_stream = socket.events()?;
+
+        while let Some(event) = event_stream.next().await {
+            let bytes = match event? {
+                WebsocketEvent::Message(msg) => match msg.bytes() {
+                    Some(bytes) => bytes,
+                    None => continue,
+                },
+                WebsocketEvent::Close(_event) => break,
+            };
+
+            fsm = match fsm {
+                Fsm::WaitingForPK => {
+                    // the public key must be the owner of this inbox, respond with a challenge.
+                    match PublicKey::from_bytes(&bytes) {
+                        Ok(pk) if pk == self.public_key => {
+                            let challenge = Challenge::new();
+                            self.socket.send_with_bytes(challenge.bytes())?;
+
+                            Fsm::WaitingForSignature(challenge)
+                        },
+                        _ => break,
+                    }
+                },
+                Fsm::WaitingForSignature(challenge) => {
+                    // verify the signature against challenge
+                    match Signature::from_bytes(&bytes) {
+                        Ok(sig) if challenge.verify(&self.public_key, &sig) => {
+                            self.flush(Date::now().as_millis()).await?;
+                            self.sockets.borrow_mut().push(self.socket.clone());
+
+                            Fsm::Authed
+                        },
+                        _ => break,
+                    }
+                },
+                Fsm::Authed => {
+                    // one frame failing, say a post to another inbox, doesn't
+                    // end the connection
+                    let handled = match Frame::from_bytes(&bytes) {
+                        Ok(Frame::Send(message)) => self.send(message).await,
+                        Ok(Frame::SendMulti(message)) => self.send_multi(message).await,
+                        Ok(Frame::Access(access_key)) => self.storage.put(ACCESS, access_key).await,
+                        Ok(Frame::Certify(certificate)) => self.certify(certificate).await,
+                        Ok(Frame::Succeed { succession, contacts }) => self.succeed(succession, contacts).await,
+                        _ => {
+                            console_log!("ignoring invalid frame");
+                            Ok(())
+                        }
+                    };
+                    if let Err(e) = handled {
+                        console_log!("failed to handle frame: {}", e);
+                    }
+
+                    Fsm::Authed
+                },
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Sends the frames stored while the owner was offline, dropping any that
+    /// have expired since, along with the index of when they expire.
+    async fn flush(&mut self, now: u64) -> Result<()> {
+        let stored = self
+            .storage
+            .list_with_options(ListOptions::new().prefix(MESSAGES))
+            .await?;
+
+        let mut keys = Vec::new();
+        for entry in stored.entries() {
+            let entry: js_sys::Array = entry?.into();
+            let frame: Frame = from_stored(entry.get(1))?;
+
+            if expires_at(&frame).map_or(true, |expires_at| now < expires_at) {
+                self.socket.send_with_bytes(frame.to_bytes())?;
+            }
+            keys.push(entry.get(0).as_string().unwrap_or_default());
+        }
+
+        let index = self
+            .storage
+            .list_with_options(ListOptions::new().prefix(EXPIRING))
+            .await?;
+        for key in index.keys() {
+            keys.extend(key?.as_string());
+        }
+
+        if !keys.is_empty() {
+            self.storage.delete_multiple(keys).await?;
+            self.storage.delete_alarm().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Relays a message from the owner to the inbox of its recipient.
+    async fn send(&self, message: Message) -> Result<()> {
+        if message.from != self.public_key || !message.verify() {
+            console_log!("dropping message with invalid sender or signature");
+            return Ok(());
+        }
+
+        self.post(&message.to, "https://inbox/messages", &message.to_bytes()).await
+    }
+
+    /// Relays a multi recipient message from the owner to the inbox of every
+    /// recipient. The body is shared, every inbox gets the same bytes.
+    async fn send_multi(&self, message: MultiMessage) -> Result<()> {
+        if message.from != self.public_key || !message.verify() {
+            console_log!("dropping multi recipient message with invalid sender or signature");
+            return Ok(());
+        }
+
+        let bytes = message.to_bytes();
+        for recipient in &message.recipients {
+            let url = format!("https://inbox/multi/{}", recipient.to);
+            self.post(&recipient.to, &url, &bytes).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Links a device to the owner and hands it the certificate. A device
+    /// is only ever certified once, so a link code can't be used again.
+    async fn certify(&mut self, certificate: DeviceCertificate) -> Result<()> {
+        if certificate.identity != self.public_key || !certificate.verify() {
+            console_log!("dropping certificate with invalid identity or signature");
+            return Ok(());
+        }
+
+        let mut devices: Vec<DeviceCertificate> = self.storage.get(DEVICES).await.unwrap_or_default();
+        if devices.iter().any(|d| d.device == certificate.device) {
+            console_log!("dropping certificate for a device that is already linked");
+            return Ok(());
+        }
+        devices.push(certificate.clone());
+        self.storage.put(DEVICES, devices).await?;
+
+        let device = certificate.device.clone();
+        post(&self.inboxes, &device, "https://inbox/forward", &Frame::Linked(certificate).to_bytes()).await
+    }
+
+    /// Publishes a statement replacing the owner's key and passes it on to
+    /// their contacts. A key only ever has one successor, later statements
+    /// naming another are dropped.
+    async fn succeed(&mut self, succession: KeySuccession, contacts: Vec<PublicKey>) -> Result<()> {
+        if succession.old != self.public_key || succession.verify().is_err() {
+            console_log!("dropping key succession with invalid key or signature");
+            return Ok(());
+        }
+
+        match self.storage.get::<KeySuccession>(SUCCESSION).await {
+            Ok(existing) if existing.new != succession.new => {
+                console_log!("dropping key succession naming another successor");
+                return Ok(());
+            }
+            _ => self.storage.put(SUCCESSION, &succession).await?,
+        }
+
+        let bytes = Frame::Succeeded(succession).to_bytes();
+        for contact in &contacts {
+            self.post(contact, "https://inbox/forward", &bytes).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn post(&self, to: &PublicKey, url: &str, bytes: &[u8]) -> Result<()> {
+        post(&self.inboxes, to, url, bytes).await
+    }
+}
+
+/// Posts to the inbox of another key.
+async fn post(inboxes: &ObjectNamespace, to: &PublicKey, url: &str, bytes: &[u8]) -> Result<()> {
+    let body = js_sys::Uint8Array::from(bytes);
+    let req = Request::new_with_init(
+        url,
+        RequestInit::new()
+            .with_method(Method::Post)
+            .with_body(Some(body.into())),
+    )?;
+
+    inboxes
+        .id_from_name(&hex::encode(to.bytes()))?
+        .get_stub()?
+        .fetch_with_request(req)
+        .await?;
+
+    Ok(())
+}
@@ -1,11 +1,17 @@
-use worker::*;
+use std::str::FromStr;
 
-use muruchat::{pki::{Signature, PublicKey}, handshake::Challenge};
+use worker::*;
 
-use futures_util::stream::StreamExt;
+use muruchat::{attestation::Attestation, pki::PublicKey, prekey::PrekeyUpload, revocation::Revocation, sealed::SealedMessage};
 
+mod blobs;
+mod expiry;
+mod inbox;
 mod utils;
 
+pub use blobs::Blobs;
+pub use inbox::Inbox;
+
 fn log_request(req: &Request) {
     console_log!(
         "{} - [{}], located at: {:?}, within: {}",
@@ -16,39 +22,52 @@ fn log_request(req: &Request) {
     );
 }
 
-#[durable_object]
-pub struct Inbox {
-    counter: usize,
-
-    // used for durable object
-    #[allow(dead_code)]
-    state: State,
-    #[allow(dead_code)]
-    env: Env,
+// inboxes are named by the hex key, however keys are displayed
+fn inbox<D>(ctx: &RouteContext<D>, public_key: &PublicKey) -> Result<Stub> {
+    ctx.durable_object("INBOX")?
+        .id_from_name(&hex::encode(public_key.bytes()))?
+        .get_stub()
 }
 
-#[durable_object]
-impl DurableObject for Inbox {
-    fn new(state: State, env: Env) -> Self {
-        Self {
-            counter: 0,
-            state,
-            env,
-        }
-    }
+/// Forwards a request to the blob storage for an attachment.
+async fn blobs<D>(ctx: &RouteContext<D>, req: Request) -> Result<Response> {
+    let id = ctx.param("id").cloned().unwrap_or_default();
+
+    let res = ctx
+        .durable_object("BLOBS")?
+        .id_from_name(&id)?
+        .get_stub()?
+        .fetch_with_request(req)
+        .await?;
 
-    async fn fetch(&mut self, _req: Request) -> Result<Response> {
-        self.counter += 1;
+    copy_with_cors(res).await
+}
 
-        Response::ok(&format!("counting: {}", self.counter))
+/// The public key a route is for, or the response refusing the request.
+fn param_key<D>(ctx: &RouteContext<D>) -> std::result::Result<PublicKey, Result<Response>> {
+    match ctx.param("public_key").map(|pk| PublicKey::from_str(pk)) {
+        Some(Ok(pk)) => Ok(pk),
+        Some(Err(e)) => Err(Response::error(format!("Invalid public key: {}", e), 400)),
+        None => Err(Response::error("Missing public key", 400)),
     }
 }
 
-#[derive(Debug)]
-enum FSM {
-    WaitingForPK,
-    WaitingForSignature(PublicKey, Challenge),
-    Authed(PublicKey),
+/// Copies a response from another object to add the CORS headers, its own
+/// headers are immutable.
+async fn copy_with_cors(mut res: Response) -> Result<Response> {
+    Response::from_bytes(res.bytes().await?)?
+        .with_status(res.status_code())
+        .with_cors(&cors())
+}
+
+/// The web client uploads and downloads attachments and prekeys, sends sealed
+/// messages, looks up devices, key successions, revocations and attestations, and
+/// publishes revocations and attestations from another origin.
+fn cors() -> Cors {
+    Cors::new()
+        .with_origins(["*"])
+        .with_methods([Method::Get, Method::Put, Method::Post, Method::Options])
+        .with_allowed_headers(["content-type"])
 }
 
 #[event(fetch)]
@@ -59,86 +78,120 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> Result<Respo
     let router = Router::new();
 
     router
-        .get_async("/chat", |req, _ctx| async move {
+        .get_async("/chat/:public_key", |req, ctx| async move {
             // ensure websocket
-            if req.headers().get("Upgrade")? != Some("websocket".to_string()) {    
+            if req.headers().get("Upgrade")? != Some("websocket".to_string()) {
                 return Response::error("Expected Upgrade: websocket", 426);
             }
 
-            // accept connection
-            let web_socker_pair = WebSocketPair::new()?;
-            web_socker_pair.server.accept()?;
-
-            // process messages async
-            wasm_bindgen_futures::spawn_local(async move {
-                // start finite state machine to track handshake
-                let mut fsm = FSM::WaitingForPK;
-
-                // open stream
-                let mut event_stream = web_socker_pair.server.events().expect("could not open stream");
-
-                while let Some(event) = event_stream.next().await {
-                    match event.expect("received error in websocket") {
-                        WebsocketEvent::Message(msg) => {
-                            if let Some(bytes) = msg.bytes() {
-                                let next = match fsm {
-                                    FSM::WaitingForPK => {
-                                        // read public key from client and respond with a challenge.
-                                        match PublicKey::from_bytes(&bytes) {
-                                            Ok(pk) => {
-                                                let challenge = Challenge::new();
-
-                                                if web_socker_pair.server.send_with_bytes(&challenge.bytes()).is_err() {
-                                                    break;
-                                                }
-
-                                                FSM::WaitingForSignature(pk, challenge)
-                                            },
-                                            _ => break,
-                                        }
-                                    }
-                                    FSM::WaitingForSignature(pk, challenge) => {
-                                        // verify the signature against challenge
-                                        match Signature::from_bytes(&bytes) {
-                                            Ok(sig) => {
-                                                if !challenge.verify(&pk, &sig) {
-                                                    break;
-                                                }
-
-                                                FSM::Authed(pk)
-                                            },
-                                            _ => break,
-                                        }
-                                    },
-                                    FSM::Authed(pk) => {
-                                        // run echo server in a loop
-                                        if let Ok(s) = String::from_utf8(bytes.to_vec()) {
-                                            let res = format!("Hello, {}!", s);
-
-                                            if web_socker_pair.server.send_with_bytes(&res.as_bytes()).is_err() {
-                                                break;
-                                            }
-                                        }
-
-                                        FSM::Authed(pk)
-                                    }
-                                };
-
-                                fsm = next;
-                            }
-                        },
-                        WebsocketEvent::Close(_event) => break,
-                    }
-                }
-            });
-
-            Response::from_websocket(web_socker_pair.client)
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res,
+            };
+
+            // the inbox runs the handshake and holds on to the connection
+            inbox(&ctx, &public_key)?.fetch_with_request(req).await
         })
-        .on_async("/counter", |_req, ctx| async move {
-            let namespace = ctx.durable_object("INBOX")?;
-            let stub = namespace.id_from_name("A")?.get_stub()?;
-            stub.fetch_with_str("/messages").await
+        .options("/prekeys/:public_key", |_, _| Response::empty()?.with_cors(&cors()))
+        .put_async("/prekeys/:public_key", |req, ctx| async move {
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res?.with_cors(&cors()),
+            };
+
+            // only accept prekeys signed by the identity they are filed under
+            let upload: PrekeyUpload = match req.clone()?.json().await {
+                Ok(upload) => upload,
+                Err(_) => return Response::error("Invalid prekey upload", 400)?.with_cors(&cors()),
+            };
+            if upload.identity_key != public_key || !upload.verify() {
+                return Response::error("Invalid prekey signature", 400)?.with_cors(&cors());
+            }
+
+            copy_with_cors(inbox(&ctx, &public_key)?.fetch_with_request(req).await?).await
+        })
+        .get_async("/prekeys/:public_key", |req, ctx| async move {
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res?.with_cors(&cors()),
+            };
+
+            copy_with_cors(inbox(&ctx, &public_key)?.fetch_with_request(req).await?).await
+        })
+        .options("/sealed", |_, _| Response::empty()?.with_cors(&cors()))
+        .post_async("/sealed", |req, ctx| async move {
+            // sealed messages are sent without a connection, so the sender stays
+            // hidden. The recipient's inbox checks the delivery token.
+            let sealed = match SealedMessage::from_bytes(&req.clone()?.bytes().await?) {
+                Ok(sealed) => sealed,
+                Err(e) => return Response::error(format!("Invalid sealed message: {}", e), 400)?.with_cors(&cors()),
+            };
+
+            copy_with_cors(inbox(&ctx, &sealed.to)?.fetch_with_request(req).await?).await
+        })
+        .get_async("/devices/:public_key", |req, ctx| async move {
+            // anyone can look up an identity's devices to encrypt to them
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res?.with_cors(&cors()),
+            };
+
+            copy_with_cors(inbox(&ctx, &public_key)?.fetch_with_request(req).await?).await
+        })
+        .get_async("/succession/:public_key", |req, ctx| async move {
+            // contacts look up whether a key has been replaced and by which
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res?.with_cors(&cors()),
+            };
+
+            copy_with_cors(inbox(&ctx, &public_key)?.fetch_with_request(req).await?).await
+        })
+        .options("/revocations", |_, _| Response::empty()?.with_cors(&cors()))
+        .post_async("/revocations", |req, ctx| async move {
+            // anyone holding a revocation can publish it, it can only have been
+            // made with the key it revokes
+            let revocation = match Revocation::from_bytes(&req.clone()?.bytes().await?) {
+                Ok(revocation) => revocation,
+                Err(e) => return Response::error(format!("Invalid revocation: {}", e), 400)?.with_cors(&cors()),
+            };
+            if !revocation.verify() {
+                return Response::error("Invalid revocation signature", 400)?.with_cors(&cors());
+            }
+
+            copy_with_cors(inbox(&ctx, &revocation.key)?.fetch_with_request(req).await?).await
+        })
+        .get_async("/revocations/:public_key", |req, ctx| async move {
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res?.with_cors(&cors()),
+            };
+
+            copy_with_cors(inbox(&ctx, &public_key)?.fetch_with_request(req).await?).await
+        })
+        .options("/attestations", |_, _| Response::empty()?.with_cors(&cors()))
+        .post_async("/attestations", |req, ctx| async move {
+            let attestation = match Attestation::from_bytes(&req.clone()?.bytes().await?) {
+                Ok(attestation) => attestation,
+                Err(e) => return Response::error(format!("Invalid attestation: {}", e), 400)?.with_cors(&cors()),
+            };
+            if !attestation.verify() {
+                return Response::error("Invalid attestation signature", 400)?.with_cors(&cors());
+            }
+
+            copy_with_cors(inbox(&ctx, &attestation.subject)?.fetch_with_request(req).await?).await
+        })
+        .get_async("/attestations/:public_key", |req, ctx| async move {
+            let public_key = match param_key(&ctx) {
+                Ok(pk) => pk,
+                Err(res) => return res?.with_cors(&cors()),
+            };
+
+            copy_with_cors(inbox(&ctx, &public_key)?.fetch_with_request(req).await?).await
         })
+        .options("/blobs/:id/:index", |_, _| Response::empty()?.with_cors(&cors()))
+        .put_async("/blobs/:id/:index", |req, ctx| async move { blobs(&ctx, req).await })
+        .get_async("/blobs/:id/:index", |req, ctx| async move { blobs(&ctx, req).await })
         .run(req, env)
         .await
 }